@@ -0,0 +1,98 @@
+use std::collections::BTreeMap;
+
+use std::time::Duration;
+
+use regex::bytes::Regex;
+use serde_json::json;
+use simple_eyre::eyre::Report;
+
+use bsc::{Beanstalk, BuryResponse, Id, StatsTubeResponse};
+
+use crate::consume::watch_only;
+use crate::output::Printer;
+use crate::purge;
+
+/// The bound of each `kick` sent by kick-all, so that no single command keeps the
+/// server busy for long.
+const KICK_BOUND: u32 = 100_000;
+
+/// Runs `bsc kick-all`: kicks every buried job, then every delayed job, of `tubes`,
+/// and prints how many were kicked per tube.
+///
+/// `kick` is sent until it kicks nothing, or until as many jobs as the tube held when
+/// starting are kicked, so that jobs buried again meanwhile by a failing worker do not
+/// keep it going forever.
+pub fn kick_all(bsc: &mut Beanstalk, tubes: &[String], out: &Printer) -> Result<(), Report> {
+    let mut kicked = BTreeMap::new();
+    for tube in tubes {
+        let target = match bsc.stats_tube(tube)? {
+            StatsTubeResponse::Ok(stats) => stats.current_jobs_buried + stats.current_jobs_delayed,
+            // dropped since listed
+            StatsTubeResponse::NotFound => continue,
+        };
+        bsc.use_(tube)?;
+        let mut n = 0;
+        while n < target as usize {
+            match bsc.kick(KICK_BOUND)? {
+                0 => break,
+                k => n += k,
+            }
+        }
+        kicked.insert(tube.clone(), n);
+    }
+
+    let total: usize = kicked.values().sum();
+    if !out.quiet && out.raw() {
+        for (tube, n) in &kicked {
+            println!("{tube}: {n}");
+        }
+    }
+    out.emit(
+        format_args!("KICKED {total}"),
+        json!({ "reply": "KICKED", "count": total, "tubes": kicked }),
+    )
+}
+
+/// Runs `bsc bury-all`: reserves every ready job of `tube` and buries it with its
+/// priority, e.g. to freeze a tube while a bad deploy is rolled back. With `pattern`,
+/// only the jobs whose body matches it are buried, the others being held until the
+/// end and then released.
+pub fn bury_all(
+    bsc: &mut Beanstalk,
+    tube: &str,
+    pattern: Option<&Regex>,
+    out: &Printer,
+) -> Result<(), Report> {
+    watch_only(bsc, tube)?;
+    let mut held: Vec<(Id, u32)> = Vec::new();
+    let mut buried = 0;
+    while let Some(job) = purge::reserve_next(bsc, &held)? {
+        // a held job comes back once its TTR elapses, every job has been seen
+        if held.iter().any(|(id, _)| *id == job.id) {
+            break;
+        }
+        let Some(stats) = purge::stats(bsc, job.id)? else {
+            continue;
+        };
+        let matches = match pattern {
+            Some(pattern) => pattern.is_match(&job.data.into_bytes()?),
+            None => true,
+        };
+        if !matches {
+            held.push((job.id, stats.pri));
+            continue;
+        }
+        if let BuryResponse::Buried = bsc.bury(job.id, stats.pri)? {
+            buried += 1;
+        }
+    }
+    let skipped = held.len();
+    for (id, pri) in held {
+        bsc.release(id, pri, Duration::ZERO)?;
+    }
+
+    out.emit(
+        format_args!("BURIED {buried} ({skipped} not matching)"),
+        json!({ "reply": "BURIED", "tube": tube, "count": buried, "skipped": skipped }),
+    )
+}
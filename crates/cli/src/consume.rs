@@ -0,0 +1,762 @@
+use std::io;
+use std::iter;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::Args;
+use serde_json::json;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use simple_eyre::eyre::{Report, WrapErr};
+
+use bsc::{Beanstalk, Body, Id, ReserveResponse, ShutdownSignal, StatsJob, StatsJobResponse};
+
+use crate::output::Printer;
+
+/// What to do with the jobs a handler fails.
+#[derive(Args)]
+pub struct Retries {
+    #[arg(
+        long,
+        default_value_t = 3,
+        help = "How many times a failed job is released to be tried again, before it is buried."
+    )]
+    retries: u32,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = crate::parse_timeout,
+        default_value = "1s",
+        help = "The delay of the first release of a failed job, doubled on each of the next ones, e.g. 10s."
+    )]
+    backoff: Duration,
+
+    #[arg(
+        long,
+        value_name = "DURATION",
+        value_parser = crate::parse_timeout,
+        default_value = "1h",
+        help = "The longest delay of a release."
+    )]
+    max_backoff: Duration,
+}
+
+impl Retries {
+    /// The delay of the release of a job that already has been released `releases`
+    /// times.
+    fn delay(&self, releases: u32) -> Duration {
+        let factor = 2u32.saturating_pow(releases);
+        self.backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+/// How many jobs are handled, how many at once, and how fast.
+#[derive(Args)]
+pub struct Limits {
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Exit once that many jobs are handled, e.g. to drain a tube in batches from cron."
+    )]
+    max_jobs: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Exit once no job is ready for a second, e.g. with --max-jobs when the tube may hold fewer jobs."
+    )]
+    exit_when_empty: bool,
+
+    #[arg(
+        long,
+        value_name = "RATE",
+        value_parser = parse_rate,
+        help = "Reserve at most that many jobs per second, or per the given duration, e.g. 10/s, 100/m or 5/10s."
+    )]
+    rate: Option<Duration>,
+
+    #[arg(
+        long,
+        short = 'j',
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "How many jobs are handled in parallel, each on its own connection."
+    )]
+    concurrency: u16,
+
+    #[arg(
+        long,
+        value_name = "HH:MM-HH:MM=COUNT",
+        value_parser = parse_window,
+        help = "How many jobs are handled in parallel between two times of day (UTC) instead of --concurrency,\ne.g. 09:00-18:00=20, or 22:00-06:00=0 to pause overnight. Can be repeated, the first matching window applies.\nA connection is opened per worker the schedule ever allows and stays open, idle when its window is closed."
+    )]
+    schedule: Vec<Window>,
+}
+
+impl Limits {
+    /// The most workers that may run at once, whatever the time of day.
+    fn max_workers(&self) -> u16 {
+        let scheduled = self.schedule.iter().map(|window| window.workers);
+        scheduled.fold(self.concurrency, u16::max)
+    }
+
+    /// How many workers may run at `time`, in seconds since midnight.
+    fn workers_at(&self, time: u32) -> u16 {
+        (self.schedule.iter())
+            .find(|window| window.contains(time))
+            .map_or(self.concurrency, |window| window.workers)
+    }
+}
+
+/// A number of workers for a time of day window of `--schedule`, the times being in
+/// seconds since midnight.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Window {
+    start: u32,
+    end: u32,
+    workers: u16,
+}
+
+impl Window {
+    /// Whether `time` is in the window, which may wrap around midnight.
+    fn contains(&self, time: u32) -> bool {
+        match self.start < self.end {
+            true => (self.start..self.end).contains(&time),
+            false => time >= self.start || time < self.end,
+        }
+    }
+}
+
+/// Parses a window of `--schedule`, as "09:00-18:00=20".
+fn parse_window(arg: &str) -> Result<Window, String> {
+    let expected = || format!("expected HH:MM-HH:MM=COUNT, got {arg:?}");
+    let (times, workers) = arg.split_once('=').ok_or_else(expected)?;
+    let (start, end) = times.split_once('-').ok_or_else(expected)?;
+    let workers = workers.parse().map_err(|_| expected())?;
+    let (start, end) = (parse_time_of_day(start)?, parse_time_of_day(end)?);
+    if start == end {
+        return Err(format!("the window {times:?} is empty"));
+    }
+    Ok(Window {
+        start,
+        end,
+        workers,
+    })
+}
+
+/// Parses "HH:MM" into seconds since midnight.
+fn parse_time_of_day(arg: &str) -> Result<u32, String> {
+    let time = arg.split_once(':').and_then(|(hours, minutes)| {
+        if minutes.len() != 2 {
+            return None;
+        }
+        let hours = hours.parse::<u32>().ok().filter(|hours| *hours < 24)?;
+        let minutes = minutes
+            .parse::<u32>()
+            .ok()
+            .filter(|minutes| *minutes < 60)?;
+        Some(hours * 3600 + minutes * 60)
+    });
+    time.ok_or_else(|| format!("expected a time of day as HH:MM, got {arg:?}"))
+}
+
+/// Parses a number of jobs per duration, as "10/s", "100/m", "5/10s" or just "10",
+/// into the interval between two jobs.
+fn parse_rate(arg: &str) -> Result<Duration, String> {
+    let (count, per) = arg.split_once('/').unwrap_or((arg, "s"));
+    let count = match count.parse::<u32>() {
+        Ok(count) if count > 0 => count,
+        _ => return Err(format!("expected a positive number of jobs, got {count:?}")),
+    };
+    let per = match per.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        true => crate::parse_timeout(&format!("1{per}"))?,
+        false => crate::parse_timeout(per)?,
+    };
+    Ok(per / count)
+}
+
+/// How long a worker waits for a job before checking whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long before the TTR of a job its handler is killed, so that the job is released
+/// by this client rather than by the server. beanstalkd warns a second before the TTR.
+/// Jobs with a TTR of up to the margin get their whole TTR, see [`time_limit`].
+const TTR_MARGIN: Duration = Duration::from_secs(1);
+
+/// How often a running handler is checked for having exited.
+const WAIT_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The jobs the workers may still take under `--max-jobs` or `--exit-when-empty`, when
+/// the next one may be reserved under `--rate`, and which workers may run under
+/// `--schedule`.
+struct Pace<'a> {
+    limits: &'a Limits,
+    taken: AtomicUsize,
+    /// Raised once a worker finds no job ready under `--exit-when-empty`.
+    emptied: AtomicBool,
+    next: Mutex<Instant>,
+    /// The slots handed out to the workers, those over the scheduled number of
+    /// workers staying idle.
+    slots: AtomicUsize,
+}
+
+impl<'a> Pace<'a> {
+    fn new(limits: &'a Limits) -> Self {
+        Self {
+            limits,
+            taken: AtomicUsize::new(0),
+            emptied: AtomicBool::new(false),
+            next: Mutex::new(Instant::now()),
+            slots: AtomicUsize::new(0),
+        }
+    }
+
+    /// The slot of a worker, from 0 on.
+    fn slot(&self) -> usize {
+        self.slots.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Waits until the worker of `slot` may run under `--schedule`, false if `shutdown`
+    /// is triggered or the jobs run out meanwhile.
+    fn wait_scheduled(&self, slot: usize, shutdown: &ShutdownSignal) -> bool {
+        loop {
+            if shutdown.is_triggered() || self.emptied.load(Ordering::SeqCst) {
+                return false;
+            }
+            let since_epoch = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            let time = (since_epoch.as_secs() % 86400) as u32;
+            if slot < self.limits.workers_at(time).into() {
+                return true;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Stops the workers under `--exit-when-empty`, a reserve having timed out.
+    /// Returns whether they are stopped.
+    fn timed_out(&self) -> bool {
+        if self.limits.exit_when_empty {
+            self.emptied.store(true, Ordering::SeqCst);
+        }
+        self.limits.exit_when_empty
+    }
+
+    /// Takes one of the jobs left, false once they are all taken.
+    fn take(&self) -> bool {
+        let Some(max) = self.limits.max_jobs else {
+            return true;
+        };
+        self.taken
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |taken| {
+                (taken < max).then_some(taken + 1)
+            })
+            .is_ok()
+    }
+
+    /// Waits for the turn of the next job, false if `shutdown` is triggered meanwhile.
+    fn wait_turn(&self, shutdown: &ShutdownSignal) -> bool {
+        let Some(interval) = self.limits.rate else {
+            return true;
+        };
+        let turn = {
+            let mut next = self.next.lock().expect("no worker panics holding the lock");
+            let turn = (*next).max(Instant::now());
+            *next = turn + interval;
+            turn
+        };
+        loop {
+            if shutdown.is_triggered() {
+                return false;
+            }
+            let left = turn.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return true;
+            }
+            thread::sleep(left.min(POLL_INTERVAL));
+        }
+    }
+}
+
+/// What became of a job, sent by the workers to the thread printing the outcomes.
+struct Handled {
+    job: StatsJob,
+    status: ExitStatus,
+    timed_out: bool,
+    elapsed: Duration,
+    outcome: &'static str,
+    delay: Option<Duration>,
+}
+
+/// Runs `bsc consume`: reserves the jobs of `tube`, or of the watched tubes, and runs
+/// `command` for each of them with the body on its stdin. A job is deleted when the
+/// command succeeds, released with a growing delay when it fails or outlives the TTR of
+/// the job, and buried once it has been retried enough.
+///
+/// Up to `--concurrency` commands run in parallel, each worker having its own connection
+/// from `connect`, as a job can only be deleted by the connection that reserved it.
+/// With a `--schedule`, there are as many workers as it ever allows, the ones over the
+/// number of the current window idling. Their connections are all opened up front and
+/// stay open for the whole run, so a window of 0 workers still holds them, idle.
+/// SIGTERM and SIGINT stop the workers from reserving jobs, and the command returns
+/// once the running ones are handled. A second signal exits right away. The command
+/// also returns once the `--max-jobs` of `limits` are handled, or once no job is
+/// ready with `--exit-when-empty`.
+pub fn consume(
+    bsc: &mut Beanstalk,
+    connect: &dyn Fn() -> Result<Beanstalk, Report>,
+    tube: Option<&str>,
+    command: &[String],
+    retries: &Retries,
+    limits: &Limits,
+    out: &Printer,
+) -> Result<(), Report> {
+    let mut conns = Vec::with_capacity(limits.max_workers().into());
+    for _ in 1..limits.max_workers() {
+        conns.push(connect()?);
+    }
+    let shutdown = ShutdownSignal::new();
+    on_termination(shutdown.clone())?;
+
+    let pace = Pace::new(limits);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (iter::once(bsc).chain(conns.iter_mut()))
+            .map(|bsc| {
+                let (shutdown, sender, pace) = (shutdown.clone(), sender.clone(), &pace);
+                scope.spawn(move || {
+                    let res = work(bsc, tube, command, retries, pace, &shutdown, &sender);
+                    if res.is_err() {
+                        // the others stop too, rather than consuming with fewer workers
+                        shutdown.trigger();
+                    }
+                    res
+                })
+            })
+            .collect();
+        drop(sender);
+
+        // until every worker is done
+        let mut printed = Ok(());
+        for handled in receiver {
+            if printed.is_ok() {
+                printed = report(out, &handled);
+            }
+        }
+        for worker in workers {
+            worker.join().expect("a worker panicked")?;
+        }
+        printed
+    })
+}
+
+/// Triggers `shutdown` on the first SIGTERM or SIGINT, and exits on the second one.
+fn on_termination(shutdown: ShutdownSignal) -> Result<(), Report> {
+    let mut signals =
+        Signals::new([SIGTERM, SIGINT]).wrap_err("unable to handle the termination signals")?;
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            if shutdown.is_triggered() {
+                std::process::exit(128 + signal);
+            }
+            eprintln!("waiting for the running jobs to be handled, signal again to exit now");
+            shutdown.trigger();
+        }
+    });
+    Ok(())
+}
+
+/// Handles jobs with `bsc`, one at a time and at the `pace`, until `shutdown` is
+/// triggered or no jobs are left to take.
+fn work(
+    bsc: &mut Beanstalk,
+    tube: Option<&str>,
+    command: &[String],
+    retries: &Retries,
+    pace: &Pace,
+    shutdown: &ShutdownSignal,
+    handled: &Sender<Handled>,
+) -> Result<(), Report> {
+    if let Some(tube) = tube {
+        watch_only(bsc, tube)?;
+    }
+    let slot = pace.slot();
+    while pace.wait_scheduled(slot, shutdown) && pace.take() && pace.wait_turn(shutdown) {
+        let Some((id, data)) = reserve(bsc, pace, shutdown)? else {
+            break;
+        };
+        let job = match bsc.stats_job(id)? {
+            StatsJobResponse::Ok(job) => job,
+            // deleted by an admin in the meantime
+            StatsJobResponse::NotFound => continue,
+        };
+        let start = Instant::now();
+        let (status, timed_out) = handle(command, &job, data)?;
+        let elapsed = start.elapsed();
+        let (outcome, delay) = if status.success() {
+            bsc.delete(id)?;
+            ("deleted", None)
+        } else if job.releases < retries.retries {
+            let delay = retries.delay(job.releases);
+            bsc.release(id, job.pri, delay)?;
+            ("released", Some(delay))
+        } else {
+            bsc.bury(id, job.pri)?;
+            ("buried", None)
+        };
+        let _ = handled.send(Handled {
+            job,
+            status,
+            timed_out,
+            elapsed,
+            outcome,
+            delay,
+        });
+    }
+    Ok(())
+}
+
+/// Reserves a job, or returns `None` once `shutdown` is triggered, or once no job is
+/// ready under `--exit-when-empty`.
+fn reserve(
+    bsc: &mut Beanstalk,
+    pace: &Pace,
+    shutdown: &ShutdownSignal,
+) -> Result<Option<(Id, Body)>, Report> {
+    while !shutdown.is_triggered() {
+        match bsc.reserve(Some(POLL_INTERVAL))? {
+            ReserveResponse::Reserved { id, data } => return Ok(Some((id, data))),
+            ReserveResponse::TimedOut if pace.timed_out() => break,
+            _ => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Watches `tube`, and only it.
+pub fn watch_only(bsc: &mut Beanstalk, tube: &str) -> Result<(), Report> {
+    bsc.watch(tube)?;
+    let others: Vec<String> = (bsc.list_tube_watched()?.into_iter())
+        .filter(|watched| *watched != tube)
+        .map(String::from)
+        .collect();
+    for other in others {
+        bsc.ignore(&other)?;
+    }
+    Ok(())
+}
+
+/// Runs `command` for `job`, the body on its stdin and the job described by
+/// environment variables. Its stdout goes to stderr, so that stdout only has the
+/// outcome of the jobs. The command is killed when it runs up to the TTR of the job,
+/// which is then told by the returned flag.
+fn handle(command: &[String], job: &StatsJob, data: Body) -> Result<(ExitStatus, bool), Report> {
+    let (program, args) = command.split_first().expect("the command is required");
+    let mut child = Command::new(program)
+        .args(args)
+        .env("BSC_JOB_ID", job.id.to_string())
+        .env("BSC_TUBE", &job.tube)
+        .env("BSC_JOB_PRI", job.pri.to_string())
+        .env("BSC_JOB_TTR", job.ttr.to_string())
+        .env("BSC_JOB_RELEASES", job.releases.to_string())
+        .env("BSC_JOB_RESERVES", job.reserves.to_string())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::from(io::stderr()))
+        .spawn()
+        .wrap_err_with(|| format!("unable to run {program:?}"))?;
+
+    // written from another thread, as a handler may not read the whole body before
+    // exiting, or before writing more than a pipe buffer
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = thread::spawn(move || {
+        // a handler is free to ignore its stdin
+        let _ = data.write_to(&mut stdin);
+    });
+    let deadline = Instant::now() + time_limit(job.ttr);
+    let mut timed_out = false;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            // it may have exited in the meantime
+            let _ = child.kill();
+            timed_out = true;
+            break child.wait()?;
+        }
+        thread::sleep(WAIT_INTERVAL);
+    };
+    let _ = writer.join();
+    Ok((status, timed_out))
+}
+
+/// How long the handler of a job with a TTR of `ttr` seconds may run: the TTR less
+/// the [`TTR_MARGIN`], or the whole TTR when it is not longer than the margin, rather
+/// than killing the handler right away.
+fn time_limit(ttr: u32) -> Duration {
+    let ttr = Duration::from_secs(ttr.into());
+    if ttr > TTR_MARGIN {
+        ttr - TTR_MARGIN
+    } else {
+        ttr
+    }
+}
+
+/// Prints what became of a job.
+fn report(out: &Printer, handled: &Handled) -> Result<(), Report> {
+    let Handled {
+        job,
+        status,
+        timed_out,
+        elapsed,
+        outcome,
+        delay,
+    } = handled;
+    let mut text = format!("{} {} {outcome}", job.id, job.tube);
+    if let Some(delay) = delay {
+        text.push_str(&format!(" with a delay of {delay:?}"));
+    }
+    text.push_str(&format!(" after {elapsed:?}"));
+    if *timed_out {
+        text.push_str(&format!(", killed at its TTR of {}s", job.ttr));
+    } else if !status.success() {
+        text.push_str(&format!(", {status}"));
+    }
+    let doc = json!({
+        "id": job.id,
+        "tube": job.tube,
+        "outcome": outcome,
+        "delay": delay.map(|delay| delay.as_secs()),
+        "exit-code": status.code(),
+        "timed-out": timed_out,
+        "elapsed": elapsed.as_secs_f64(),
+    });
+    out.emit(text, doc)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bsc::State;
+
+    const HOUR: u32 = 3600;
+
+    fn limits(concurrency: u16, schedule: &[&str]) -> Limits {
+        Limits {
+            max_jobs: None,
+            exit_when_empty: false,
+            rate: None,
+            concurrency,
+            schedule: schedule
+                .iter()
+                .map(|arg| parse_window(arg).unwrap())
+                .collect(),
+        }
+    }
+
+    fn retries(retries: u32) -> Retries {
+        Retries {
+            retries,
+            backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(60),
+        }
+    }
+
+    fn job(ttr: u32) -> StatsJob {
+        StatsJob {
+            id: 3,
+            tube: "emails".to_string(),
+            state: State::Reserved,
+            pri: 10,
+            age: Duration::ZERO,
+            delay: Duration::ZERO,
+            ttr,
+            time_left: Duration::from_secs(ttr.into()),
+            file: 0,
+            reserves: 1,
+            timeouts: 0,
+            releases: 0,
+            buries: 0,
+            kicks: 0,
+        }
+    }
+
+    fn sh(script: &str) -> Vec<String> {
+        ["sh", "-c", script].map(String::from).to_vec()
+    }
+
+    #[test]
+    fn time_limits() {
+        assert_eq!(time_limit(1), Duration::from_secs(1));
+        assert_eq!(time_limit(2), Duration::from_secs(1));
+        assert_eq!(time_limit(60), Duration::from_secs(59));
+    }
+
+    #[test]
+    fn handles_jobs() {
+        let script = r#"test "$(cat)" = hello && test "$BSC_JOB_ID" = 3 && exit 4"#;
+        let (status, timed_out) =
+            handle(&sh(script), &job(60), Body::from(b"hello".to_vec())).unwrap();
+        assert_eq!((status.code(), timed_out), (Some(4), false));
+    }
+
+    #[test]
+    fn kills_handlers_at_the_ttr() {
+        let start = Instant::now();
+        let (status, timed_out) = handle(&sh("sleep 10"), &job(1), Body::from(Vec::new())).unwrap();
+        assert!(!status.success() && timed_out);
+        // a TTR of 1s is not shortened by the margin
+        assert!(
+            start.elapsed() >= Duration::from_secs(1),
+            "{:?}",
+            start.elapsed()
+        );
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "{:?}",
+            start.elapsed()
+        );
+    }
+
+    /// Runs a worker handling one job with `command`, the server sending `replies`.
+    fn work_once(command: &[String], replies: &str) -> Handled {
+        let stats = "---\nid: 3\ntube: emails\nstate: reserved\npri: 10\nage: 0\ndelay: 0\n\
+            ttr: 60\ntime-left: 60\nfile: 0\nreserves: 1\ntimeouts: 0\nreleases: 0\n\
+            buries: 0\nkicks: 0\n";
+        let script = format!(
+            "RESERVED 3 5\r\nhello\r\nOK {}\r\n{stats}\r\n{replies}",
+            stats.len()
+        );
+        let mut bsc = Beanstalk::from_parts(io::Cursor::new(script.into_bytes()), io::sink());
+        let mut limits = limits(1, &[]);
+        limits.max_jobs = Some(1);
+        let pace = Pace::new(&limits);
+        let (sender, receiver) = mpsc::channel();
+        let shutdown = ShutdownSignal::new();
+        work(
+            &mut bsc,
+            None,
+            command,
+            &retries(3),
+            &pace,
+            &shutdown,
+            &sender,
+        )
+        .unwrap();
+        drop(sender);
+        let handled: Vec<_> = receiver.into_iter().collect();
+        assert_eq!(handled.len(), 1);
+        handled.into_iter().next().unwrap()
+    }
+
+    #[test]
+    fn works_through_jobs() {
+        let handled = work_once(&sh("cat >/dev/null"), "DELETED\r\n");
+        assert_eq!((handled.job.id, handled.outcome), (3, "deleted"));
+        assert_eq!(handled.delay, None);
+
+        let handled = work_once(&sh("exit 1"), "RELEASED\r\n");
+        assert_eq!(handled.outcome, "released");
+        assert_eq!(handled.delay, Some(Duration::from_secs(1)));
+        assert!(!handled.timed_out);
+    }
+
+    #[test]
+    fn exits_when_empty() {
+        let mut bsc = Beanstalk::from_parts(io::Cursor::new(&b"TIMED_OUT\r\n"[..]), io::sink());
+        let mut limits = limits(2, &[]);
+        limits.exit_when_empty = true;
+        let pace = Pace::new(&limits);
+        let (sender, receiver) = mpsc::channel();
+        let shutdown = ShutdownSignal::new();
+        work(
+            &mut bsc,
+            None,
+            &sh("true"),
+            &retries(3),
+            &pace,
+            &shutdown,
+            &sender,
+        )
+        .unwrap();
+        drop(sender);
+        assert_eq!(receiver.into_iter().count(), 0);
+        // the other workers stop too, even those idle under --schedule
+        assert!(!pace.wait_scheduled(pace.slot(), &shutdown));
+    }
+
+    #[test]
+    fn parses_rates() {
+        let ms = Duration::from_millis;
+        assert_eq!(parse_rate("10/s"), Ok(ms(100)));
+        assert_eq!(parse_rate("10"), Ok(ms(100)));
+        assert_eq!(parse_rate("120/m"), Ok(ms(500)));
+        assert_eq!(parse_rate("1/h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_rate("5/10s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_rate("4/500ms"), Ok(ms(125)));
+
+        for arg in ["0/s", "-1/s", "many/s", "", "10/", "10/d", "10/0s"] {
+            assert!(parse_rate(arg).is_err(), "{arg}");
+        }
+    }
+
+    #[test]
+    fn parses_windows() {
+        let window = parse_window("09:00-18:30=20").unwrap();
+        assert_eq!(
+            window,
+            Window {
+                start: 9 * HOUR,
+                end: 18 * HOUR + 30 * 60,
+                workers: 20
+            }
+        );
+        assert_eq!(parse_window("22:00-06:00=0").unwrap().workers, 0);
+
+        for arg in [
+            "09:00-18:00",
+            "09:00=20",
+            "09:00-18:00=many",
+            "09:00-18:00=-1",
+            "9:0-18:00=1",
+            "24:00-06:00=1",
+            "09:60-18:00=1",
+            "0900-1800=1",
+            "09:00-09:00=1",
+        ] {
+            assert!(parse_window(arg).is_err(), "{arg}");
+        }
+    }
+
+    #[test]
+    fn windows_may_wrap_around_midnight() {
+        let day = parse_window("09:00-18:00=1").unwrap();
+        assert!(day.contains(9 * HOUR));
+        assert!(day.contains(18 * HOUR - 1));
+        assert!(!day.contains(18 * HOUR));
+        assert!(!day.contains(8 * HOUR));
+
+        let night = parse_window("22:00-06:00=1").unwrap();
+        assert!(night.contains(23 * HOUR));
+        assert!(night.contains(0));
+        assert!(night.contains(6 * HOUR - 1));
+        assert!(!night.contains(6 * HOUR));
+        assert!(!night.contains(12 * HOUR));
+    }
+
+    #[test]
+    fn first_matching_window_applies() {
+        let limits = limits(5, &["09:00-18:00=20", "12:00-14:00=2", "22:00-06:00=0"]);
+        assert_eq!(limits.workers_at(13 * HOUR), 20);
+        assert_eq!(limits.workers_at(23 * HOUR), 0);
+        assert_eq!(limits.workers_at(20 * HOUR), 5);
+        assert_eq!(limits.max_workers(), 20);
+        assert_eq!(self::limits(3, &[]).max_workers(), 3);
+    }
+}
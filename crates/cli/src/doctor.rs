@@ -0,0 +1,230 @@
+use std::net::ToSocketAddrs;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use simple_eyre::eyre::Report;
+
+use bsc::{Beanstalk, BeanstalkUrl, ServerInfo, TubeConfig};
+
+use crate::output::{with_causes, Printer, GREEN, RED, RESET, YELLOW};
+
+/// How long connecting may take when `--connect-timeout` is not given.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The number of round trips measured.
+const PINGS: u32 = 5;
+
+/// A round trip slower than this is worth a warning.
+const SLOW_RTT: Duration = Duration::from_millis(50);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "fail",
+        }
+    }
+}
+
+/// The outcome of the checks of one endpoint, in the order they ran.
+struct Diagnosis {
+    endpoint: String,
+    checks: Vec<(&'static str, Status, String)>,
+}
+
+impl Diagnosis {
+    fn check(&mut self, name: &'static str, status: Status, detail: impl Into<String>) {
+        self.checks.push((name, status, detail.into()));
+    }
+
+    fn failed(&self) -> bool {
+        self.checks
+            .iter()
+            .any(|(_, status, _)| *status == Status::Fail)
+    }
+}
+
+/// Runs `bsc doctor`: checks that every endpoint is reachable and healthy, printing
+/// what was found and what is worth a look. Fails when an endpoint is unreachable.
+pub fn doctor(
+    endpoints: &[&str],
+    connect_timeout: Option<Duration>,
+    out: &Printer,
+) -> Result<(), Report> {
+    for endpoint in endpoints {
+        let diagnosis = diagnose(endpoint, connect_timeout.unwrap_or(CONNECT_TIMEOUT));
+        if diagnosis.failed() {
+            out.fail();
+        }
+        if out.raw() {
+            print_diagnosis(&diagnosis, out);
+        } else {
+            let checks: Vec<Value> = diagnosis
+                .checks
+                .iter()
+                .map(|(name, status, detail)| {
+                    json!({ "check": name, "status": status.as_str(), "detail": detail })
+                })
+                .collect();
+            out.emit_doc(json!({ "endpoint": diagnosis.endpoint, "checks": checks }))?;
+        }
+    }
+    Ok(())
+}
+
+fn print_diagnosis(diagnosis: &Diagnosis, out: &Printer) {
+    if out.quiet {
+        return;
+    }
+    println!("{}", diagnosis.endpoint);
+    for (name, status, detail) in &diagnosis.checks {
+        let style = match status {
+            Status::Ok => GREEN,
+            Status::Warn => YELLOW,
+            Status::Fail => RED,
+        };
+        let status = format!("{:<4}", status.as_str());
+        match out.color {
+            true => println!("  {style}{status}{RESET}  {name:<12} {detail}"),
+            false => println!("  {status}  {name:<12} {detail}"),
+        }
+    }
+}
+
+/// Checks `endpoint`, stopping at the first check that fails.
+fn diagnose(endpoint: &str, connect_timeout: Duration) -> Diagnosis {
+    let mut diagnosis = Diagnosis {
+        endpoint: endpoint.to_string(),
+        checks: Vec::new(),
+    };
+
+    // name resolution, then connection
+    let url = match endpoint.contains("://") {
+        true => match endpoint.parse::<BeanstalkUrl>() {
+            Ok(url) => Some(url),
+            Err(err) => {
+                diagnosis.check("address", Status::Fail, err.to_string());
+                return diagnosis;
+            }
+        },
+        false => None,
+    };
+    let tcp_address = match &url {
+        Some(url) => url.tcp_address(),
+        None => Some(endpoint),
+    };
+    if let Some(addr) = tcp_address {
+        match addr.to_socket_addrs() {
+            Ok(addrs) => {
+                let addrs: Vec<String> = addrs.map(|addr| addr.to_string()).collect();
+                diagnosis.check("resolve", Status::Ok, addrs.join(", "));
+            }
+            Err(err) => {
+                diagnosis.check("resolve", Status::Fail, err.to_string());
+                return diagnosis;
+            }
+        }
+    }
+    let start = Instant::now();
+    let res = match &url {
+        Some(url) => url.connect_with_timeout(&TubeConfig::default(), connect_timeout),
+        None => Beanstalk::connect_timeout(endpoint, connect_timeout),
+    };
+    let mut bsc = match res {
+        Ok(bsc) => {
+            diagnosis.check("connect", Status::Ok, format!("in {:?}", start.elapsed()));
+            bsc
+        }
+        Err(err) => {
+            diagnosis.check("connect", Status::Fail, with_causes(&err));
+            return diagnosis;
+        }
+    };
+
+    // round trips with a command that costs the server nothing
+    let mut rtts = Vec::new();
+    for _ in 0..PINGS {
+        let start = Instant::now();
+        if let Err(err) = bsc.list_tube_used() {
+            diagnosis.check("rtt", Status::Fail, with_causes(&err));
+            return diagnosis;
+        }
+        rtts.push(start.elapsed());
+    }
+    let min = rtts.iter().min().copied().unwrap_or_default();
+    let max = rtts.iter().max().copied().unwrap_or_default();
+    let avg = rtts.iter().sum::<Duration>() / PINGS;
+    let status = if avg > SLOW_RTT {
+        Status::Warn
+    } else {
+        Status::Ok
+    };
+    let detail = format!("min {min:?}, avg {avg:?}, max {max:?} over {PINGS} round trips");
+    diagnosis.check("rtt", status, detail);
+
+    let stats = match bsc.stats() {
+        Ok(stats) => stats,
+        Err(err) => {
+            diagnosis.check("stats", Status::Fail, with_causes(&err));
+            return diagnosis;
+        }
+    };
+    let info = ServerInfo::from(&stats);
+    let version = format!(
+        "beanstalkd {}, up {:?}, pid {}",
+        info.version, stats.uptime, stats.pid
+    );
+    match info.supports_reserve_job() {
+        true => diagnosis.check("version", Status::Ok, version),
+        false => diagnosis.check(
+            "version",
+            Status::Warn,
+            format!("{version}, older than 1.12: reserve-job is not supported"),
+        ),
+    }
+    match info.draining {
+        false => diagnosis.check("drain", Status::Ok, "accepting new jobs"),
+        true => diagnosis.check(
+            "drain",
+            Status::Warn,
+            "server draining, puts will fail with DRAINING",
+        ),
+    }
+    match stats.binlog_current_index {
+        0 => diagnosis.check(
+            "binlog",
+            Status::Warn,
+            "disabled, jobs are lost when the server restarts (start it with -b)",
+        ),
+        index => diagnosis.check(
+            "binlog",
+            Status::Ok,
+            format!(
+                "file {index}, oldest {}, {} records written",
+                stats.binlog_oldest_index, stats.binlog_records_written
+            ),
+        ),
+    }
+    diagnosis.check(
+        "max-job-size",
+        Status::Ok,
+        format!("{} bytes", info.max_job_size),
+    );
+    match stats.current_jobs_buried {
+        0 => diagnosis.check("buried", Status::Ok, "no buried jobs"),
+        n => diagnosis.check(
+            "buried",
+            Status::Warn,
+            format!("{n} buried jobs, see `bsc stats-tubes` for their tubes"),
+        ),
+    }
+    diagnosis
+}
@@ -0,0 +1,160 @@
+use clap::Args;
+use serde_json::json;
+use simple_eyre::eyre::Report;
+
+use bsc::{Beanstalk, StatsTubeResponse};
+
+use crate::output::Printer;
+
+/// The exit status when a threshold is exceeded.
+pub const UNHEALTHY: i32 = 1;
+
+/// The exit status when the server cannot be reached, or fails to answer.
+pub const UNREACHABLE: i32 = 2;
+
+/// The exit status when the checked tube does not exist.
+pub const NO_TUBE: i32 = 3;
+
+/// The most jobs of each state the server, or the tube, may hold to be healthy.
+#[derive(Args)]
+pub struct Thresholds {
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Unhealthy above that many ready jobs."
+    )]
+    max_ready: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Unhealthy above that many urgent jobs."
+    )]
+    max_urgent: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Unhealthy above that many reserved jobs."
+    )]
+    max_reserved: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Unhealthy above that many delayed jobs."
+    )]
+    max_delayed: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Unhealthy above that many buried jobs."
+    )]
+    max_buried: Option<u32>,
+
+    #[arg(
+        long,
+        help = "Unhealthy when the server is draining, i.e. refuses new jobs."
+    )]
+    fail_if_draining: bool,
+}
+
+/// Checks the job counts of `tube`, or of the whole server, against `thresholds`,
+/// prints a one line verdict, and exits with a status telling what is wrong.
+pub fn healthcheck(
+    bsc: &mut Beanstalk,
+    tube: Option<&str>,
+    thresholds: &Thresholds,
+    out: &Printer,
+) -> Result<(), Report> {
+    let stats = match bsc.stats() {
+        Ok(stats) => stats,
+        Err(err) => unreachable_server(err.into(), out),
+    };
+    // the counts are those of the tube when one is given
+    let counts = match tube {
+        Some(tube) => match bsc.stats_tube(tube) {
+            Ok(StatsTubeResponse::Ok(stats)) => [
+                stats.current_jobs_ready,
+                stats.current_jobs_urgent,
+                stats.current_jobs_reserved,
+                stats.current_jobs_delayed,
+                stats.current_jobs_buried,
+            ],
+            Ok(_) => {
+                verdict(out, "UNKNOWN", &format!("no tube named {tube:?}"), &[]);
+                std::process::exit(NO_TUBE);
+            }
+            Err(err) => unreachable_server(err.into(), out),
+        },
+        None => [
+            stats.current_jobs_ready,
+            stats.current_jobs_urgent,
+            stats.current_jobs_reserved,
+            stats.current_jobs_delayed,
+            stats.current_jobs_buried,
+        ],
+    };
+    let limits = [
+        ("ready", thresholds.max_ready),
+        ("urgent", thresholds.max_urgent),
+        ("reserved", thresholds.max_reserved),
+        ("delayed", thresholds.max_delayed),
+        ("buried", thresholds.max_buried),
+    ];
+
+    let mut violations = Vec::new();
+    let mut summary = Vec::new();
+    for ((state, max), count) in limits.into_iter().zip(counts) {
+        match max {
+            Some(max) if count > max => {
+                violations.push(format!("{state} {count} > {max}"));
+            }
+            Some(max) => summary.push(format!("{state} {count} <= {max}")),
+            None => summary.push(format!("{state} {count}")),
+        }
+    }
+    if thresholds.fail_if_draining && stats.draining {
+        violations.push("server draining".to_string());
+    }
+
+    let scope = match tube {
+        Some(tube) => format!("tube {tube}: "),
+        None => String::new(),
+    };
+    let fields: Vec<(&str, u32)> = limits.iter().map(|(state, _)| *state).zip(counts).collect();
+    if violations.is_empty() {
+        let message = format!("{scope}{}", summary.join(", "));
+        verdict(out, "OK", &message, &fields);
+        return Ok(());
+    }
+    let message = format!("{scope}{}", violations.join(", "));
+    verdict(out, "CRITICAL", &message, &fields);
+    std::process::exit(UNHEALTHY);
+}
+
+/// Reports that the server cannot be reached, and exits.
+pub fn unreachable_server(report: Report, out: &Printer) -> ! {
+    verdict(out, "UNREACHABLE", &format!("{report:#}"), &[]);
+    std::process::exit(UNREACHABLE);
+}
+
+/// Prints `status` and `message` in the single line monitoring systems show, or as a
+/// document with the job `counts`.
+fn verdict(out: &Printer, status: &str, message: &str, counts: &[(&str, u32)]) {
+    if out.quiet {
+        return;
+    }
+    if out.raw() {
+        println!("{status}: {message}");
+        return;
+    }
+    let counts: serde_json::Map<_, _> = counts
+        .iter()
+        .map(|(state, count)| (state.to_string(), json!(count)))
+        .collect();
+    let doc = json!({ "status": status, "message": message, "counts": counts });
+    // the status is what matters, a failure to print it must not change it
+    let _ = out.emit_doc(doc);
+}
@@ -1,30 +1,289 @@
 use serde_json::json;
-use simple_eyre::eyre::{Report, WrapErr};
+use simple_eyre::eyre::{bail, eyre, Report, WrapErr};
+use std::cell::Cell;
 use std::io::{self, Read, Write};
 use std::path::PathBuf;
+use std::thread;
 use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 
 use bsc::*;
 
+mod bulk;
+mod consume;
+mod doctor;
+mod health;
+mod output;
+mod purge;
+mod push;
+mod tail;
+mod top;
+mod trace;
+mod transfer;
+
+use consume::{Limits, Retries};
+use health::Thresholds;
+use output::{about_job, error_doc, reply, use_color, with_causes, Output, Printer};
+use purge::JobState;
+use push::Sinks;
+use trace::{LogFile, LogFormat, Trace};
+use transfer::Duplicates;
+
 fn main() -> Result<(), Report> {
     simple_eyre::install()?;
 
     let cli = Cli::parse();
+    let (output, addr) = (cli.output, cli.addr.join(","));
+    let log = match &cli.log_file {
+        Some(path) => Some(LogFile::open(path, cli.log_format)?),
+        None => None,
+    };
+    if cli.deadline.is_some() && cli.cmd.runs_until_interrupted() {
+        // the watchdog would exit in the middle of a job, or with the terminal still raw
+        let report = eyre!("--timeout does not apply to commands running until interrupted (consume, top, tail, push-stats, --watch)");
+        fail(report, output, &addr, log);
+    }
+    if let Some(timeout) = cli.deadline {
+        // a watchdog, as the command may wait in many places: connecting, resolving the
+        // address, or reserving in a loop
+        let log = log.as_ref().map(LogFile::try_clone).transpose()?;
+        let addr = addr.clone();
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            let report = Report::new(Error::ResponseTimeout)
+                .wrap_err(format!("the command did not complete within {timeout:?}"));
+            fail(report, output, &addr, log)
+        });
+    }
+    if let Err(report) = execute(cli, log.as_ref()) {
+        fail(report, output, &addr, log);
+    }
+    Ok(())
+}
+
+/// Reports the error ending the run, to the log file too, and exits.
+fn fail(report: Report, output: Output, addr: &str, log: Option<LogFile>) -> ! {
+    if let Some(mut log) = log {
+        log.error(&report, addr);
+    }
+    if output == Output::Json {
+        // tooling gets a document it can branch on, rather than a report to parse
+        eprintln!("{}", error_doc(&report, addr));
+    } else {
+        eprintln!("Error: {}", with_causes(report.as_ref()));
+    }
+    std::process::exit(1);
+}
+
+/// Connects as asked by the global flags of `cli`, and runs its command.
+fn execute(cli: Cli, log: Option<&LogFile>) -> Result<(), Report> {
+    if let Cmd::Completions { shell } = cli.cmd {
+        // generated in memory, as writing to a closed pipe would panic
+        let mut script = Vec::new();
+        clap_complete::generate(shell, &mut Cli::command(), "bsc", &mut script);
+        io::stdout().write_all(&script)?;
+        return Ok(());
+    }
 
-    let mut bsc = Beanstalk::connect(cli.addr)?;
+    let out = Printer {
+        output: cli.output,
+        format: cli.format,
+        color: use_color(cli.no_color),
+        quiet: cli.quiet,
+        failed: Cell::new(false),
+    };
+    let endpoints = endpoints(&cli.addr);
+    if let Cmd::Doctor = cli.cmd {
+        doctor::doctor(&endpoints, cli.connect_timeout, &out)?;
+        if out.failed() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Cmd::Migrate {
+        from,
+        to,
+        tubes,
+        limit,
+    } = &cli.cmd
+    {
+        // the servers are given by --from and --to rather than --addr
+        let connect = |addr: &String| -> Result<Beanstalk, Report> {
+            let endpoints = self::endpoints(std::slice::from_ref(addr));
+            let (_, _, mut bsc) = connect_any(&endpoints, cli.connect_timeout)
+                .wrap_err_with(|| format!("unable to connect to {addr}"))?;
+            intercept(&mut bsc, cli.verbose, cli.trace_bodies, log)?;
+            Ok(bsc)
+        };
+        let (mut src, mut dst) = (connect(from)?, connect(to)?);
+        let mut tubes = tubes.clone();
+        tubes.extend(cli.tube.clone());
+        transfer::migrate(&mut src, &mut dst, &tubes, *limit, &out)?;
+        if out.failed() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let (endpoint, url, mut bsc) = match connect_any(&endpoints, cli.connect_timeout) {
+        Err(report) if matches!(cli.cmd, Cmd::Healthcheck { .. }) => {
+            health::unreachable_server(report, &out)
+        }
+        res => res?,
+    };
+    if endpoints.len() > 1 && !cli.quiet {
+        eprintln!("connected to {endpoint}");
+    }
 
+    // a URL brings its own tubes and put defaults, the flags take precedence
+    let mut config = url
+        .as_ref()
+        .map(|url| url.tube_config().clone())
+        .unwrap_or_default();
+    if let Some(namespace) = cli.namespace {
+        config = config.namespace(namespace);
+    }
     if let Some(used) = cli.tube {
-        bsc.use_(&used)?;
+        config = config.use_(used);
+    }
+    // given by --tube or by the URL
+    let tube = config.used_tube().map(String::from);
+    if cli.require_explicit_tube && tube.is_none() && cli.cmd.is_destructive() {
+        bail!("refusing to run a destructive command against the implicit \"default\" tube, use `--tube default` to target it explicitly");
     }
 
-    match cli.cmd {
+    let mut tcp = TcpOptions::default();
+    if cli.tcp_nodelay {
+        tcp = tcp.nodelay(true);
+    }
+    if let Some(idle) = cli.tcp_keepalive {
+        tcp = tcp.keepalive(idle);
+    }
+    if let Some(size) = cli.tcp_send_buffer {
+        tcp = tcp.send_buffer_size(size);
+    }
+    if let Some(size) = cli.tcp_recv_buffer {
+        tcp = tcp.recv_buffer_size(size);
+    }
+    let setup = |bsc: &mut Beanstalk| -> Result<(), Report> {
+        // the tubes are set up once tracing is in place, so that it shows their commands
+        intercept(bsc, cli.verbose, cli.trace_bodies, log)?;
+        bsc.configure(&config)?;
+        if tcp != TcpOptions::default() {
+            bsc.tune_tcp(&tcp)?;
+        }
+        Ok(())
+    };
+    setup(&mut bsc)?;
+    let put_defaults = url
+        .as_ref()
+        .map(BeanstalkUrl::put_options)
+        .unwrap_or_default();
+    // more connections to the same endpoint, for the commands running in parallel
+    let connect = || -> Result<Beanstalk, Report> {
+        let (_, _, mut bsc) = connect_any(&[endpoint], cli.connect_timeout)?;
+        setup(&mut bsc)?;
+        Ok(bsc)
+    };
+
+    run(
+        cli.cmd,
+        &mut bsc,
+        &connect,
+        &out,
+        put_defaults,
+        tube.as_deref(),
+    )?;
+    if out.failed() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Adds the interceptors tracing the commands of `bsc` with `-v`, and logging them
+/// with `--log-file`.
+fn intercept(
+    bsc: &mut Beanstalk,
+    verbose: u8,
+    trace_bodies: bool,
+    log: Option<&LogFile>,
+) -> Result<(), Report> {
+    if verbose > 0 {
+        bsc.add_interceptor(Box::new(Trace::new(verbose, trace_bodies)));
+    }
+    if let Some(log) = log {
+        bsc.add_interceptor(Box::new(log.try_clone()?));
+    }
+    Ok(())
+}
+
+/// The endpoints given by the `--addr` flags, each of them being a comma separated
+/// list unless it is a URL, whose query may contain commas.
+fn endpoints(addrs: &[String]) -> Vec<&str> {
+    addrs
+        .iter()
+        .flat_map(|addr| match addr.contains("://") {
+            true => vec![addr.as_str()],
+            false => addr.split(',').map(str::trim).collect(),
+        })
+        .filter(|addr| !addr.is_empty())
+        .collect()
+}
+
+/// Connects to the first reachable endpoint, without setting up its tubes, giving up
+/// on each endpoint after `timeout` if any. Only connection errors move on to the next
+/// endpoint.
+fn connect_any<'a>(
+    endpoints: &[&'a str],
+    timeout: Option<Duration>,
+) -> Result<(&'a str, Option<BeanstalkUrl>, Beanstalk), Report> {
+    let mut last_err = None;
+    for &endpoint in endpoints {
+        let url = if endpoint.contains("://") {
+            Some(endpoint.parse::<BeanstalkUrl>()?)
+        } else {
+            None
+        };
+        let config = TubeConfig::default();
+        let res = match (&url, timeout) {
+            (Some(url), Some(timeout)) => url.connect_with_timeout(&config, timeout),
+            (Some(url), None) => url.connect_with(&config),
+            (None, Some(timeout)) => Beanstalk::connect_timeout(endpoint, timeout),
+            (None, None) => Beanstalk::connect(endpoint),
+        };
+        match res {
+            Ok(bsc) => return Ok((endpoint, url, bsc)),
+            Err(err @ Error::Io(_)) => last_err = Some(err),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    match last_err {
+        Some(err) if endpoints.len() > 1 => Err(err)
+            .wrap_err_with(|| format!("unable to connect to any of {}", endpoints.join(", "))),
+        Some(err) => Err(err.into()),
+        None => bail!("no server address given"),
+    }
+}
+
+/// Runs `cmd`, printing its result with `out`. `tube` is the one given by `--tube` or
+/// by the `--addr` URL, that commands such as healthcheck act on rather than just use. `connect` opens
+/// another connection set up like `bsc`.
+fn run(
+    cmd: Cmd,
+    bsc: &mut Beanstalk,
+    connect: &dyn Fn() -> Result<Beanstalk, Report>,
+    out: &Printer,
+    put_defaults: PutOptions,
+    tube: Option<&str>,
+) -> Result<(), Report> {
+    match cmd {
         Cmd::Put {
             pri,
             delay,
             ttr,
             filepath,
+            id_only,
         } => {
             let data = match filepath {
                 Some(fp) => std::fs::read(fp).wrap_err("unable to read <filepath>")?,
@@ -36,137 +295,223 @@ fn main() -> Result<(), Report> {
                     buf
                 }
             };
-            let res = bsc.put(pri, delay, ttr, &data[..])?;
-            println!("{res:?}");
-            Ok(())
-        }
-        Cmd::Peek { id } => {
-            match bsc.peek(id)? {
-                PeekResponse::Found { data, .. } => {
-                    io::stdout().write_all(&data)?;
-                }
-                res => println!("{res:?}"),
+            let mut opts = put_defaults;
+            if let Some(pri) = pri {
+                opts = opts.pri(pri);
             }
-            Ok(())
+            if let Some(delay) = delay {
+                opts = opts.delay(delay);
+            }
+            if let Some(ttr) = ttr {
+                opts = opts.ttr(ttr);
+            }
+            let res = bsc.put_with(&data[..], opts)?;
+            if id_only {
+                return match res {
+                    PutResponse::Inserted(id) | PutResponse::Buried(id) => {
+                        println!("{id}");
+                        Ok(())
+                    }
+                    res => {
+                        eprintln!("{res}");
+                        out.fail();
+                        Ok(())
+                    }
+                };
+            }
+            let doc = match res {
+                PutResponse::Inserted(id) => json!({ "reply": "INSERTED", "id": id }),
+                PutResponse::Buried(id) => json!({ "reply": "BURIED", "id": id }),
+                _ => reply(&res),
+            };
+            out.emit(&res, doc)
         }
+        Cmd::Peek { id } => match bsc.peek(id)? {
+            PeekResponse::Found { data, .. } if out.raw() => out.emit_data(&data),
+            res => out.emit_job(res),
+        },
         Cmd::Reserve {
             timeout,
             data: only_data,
-        } => {
-            match bsc.reserve(timeout)? {
-                ReserveResponse::Reserved { id, data } => {
-                    if only_data {
-                        io::stdout().write_all(&data)?;
-                    } else {
-                        match std::str::from_utf8(&data) {
-                            Ok(data) => serde_json::to_writer(
-                                io::stdout(),
-                                &json!({ "id": id, "data": data }),
-                            )?,
-                            Err(_) => serde_json::to_writer(
-                                io::stdout(),
-                                &json!({ "id": id, "data": data }),
-                            )?,
-                        };
-                    }
-                }
-                res => println!("{res:?}"),
+            id_only,
+        } => match bsc.reserve(timeout)? {
+            ReserveResponse::Reserved { id, .. } if id_only => {
+                println!("{id}");
+                Ok(())
             }
-            Ok(())
-        }
+            ReserveResponse::Reserved { data, .. } if only_data => out.emit_data(&data),
+            ReserveResponse::Reserved { id, data } if out.raw() => {
+                out.emit_doc(serde_json::to_value(Job { id, data })?)
+            }
+            res if id_only => {
+                eprintln!("{res}");
+                out.fail();
+                Ok(())
+            }
+            ReserveResponse::Reserved { id, data } => {
+                out.emit_doc(json!({ "reply": "RESERVED", "id": id, "data": data }))
+            }
+            res => out.emit(&res, reply(&res)),
+        },
         Cmd::Delete { id } => {
             let res = bsc.delete(id)?;
-            println!("{res:?}");
-            Ok(())
+            out.emit(&res, about_job(&res, id))
         }
         Cmd::Release { id, pri, delay } => {
             let res = bsc.release(id, pri, delay)?;
-            println!("{res:?}");
-            Ok(())
+            out.emit(&res, about_job(&res, id))
         }
         Cmd::Bury { id, pri } => {
             let res = bsc.bury(id, pri)?;
-            println!("{res:?}");
-            Ok(())
+            out.emit(&res, about_job(&res, id))
         }
         Cmd::Touch { id } => {
             let res = bsc.touch(id)?;
-            println!("{res:?}");
-            Ok(())
+            out.emit(&res, about_job(&res, id))
         }
         Cmd::Watch { tube } => {
             let n = bsc.watch(&tube)?;
-            println!("Watching({n})");
-            Ok(())
+            out.emit(
+                format_args!("WATCHING {n}"),
+                json!({ "reply": "WATCHING", "count": n }),
+            )
         }
         Cmd::Ignore { tube } => {
             let res = bsc.ignore(&tube)?;
-            println!("{res:?}");
-            Ok(())
+            let doc = match res {
+                IgnoreResponse::Count(n) => json!({ "reply": "WATCHING", "count": n }),
+                IgnoreResponse::NotIgnored => reply(&res),
+            };
+            out.emit(&res, doc)
         }
-        Cmd::PeekReady => {
-            let res = bsc.peek_ready()?;
-            println!("{res:?}");
-            Ok(())
+        Cmd::PeekReady => out.emit_job(bsc.peek_ready()?),
+        Cmd::PeekDelayed => out.emit_job(bsc.peek_delayed()?),
+        Cmd::PeekBuried => out.emit_job(bsc.peek_buried()?),
+        Cmd::Kick { bound } => {
+            let n = bsc.kick(bound)?;
+            out.emit(
+                format_args!("KICKED {n}"),
+                json!({ "reply": "KICKED", "count": n }),
+            )
         }
-        Cmd::PeekDelayed => {
-            let res = bsc.peek_delayed()?;
-            println!("{res:?}");
-            Ok(())
+        Cmd::Purge {
+            states,
+            older_than,
+            limit,
+        } => purge::purge(bsc, &states, older_than, limit, out),
+        Cmd::Move {
+            from,
+            to,
+            limit,
+            dry_run,
+        } => transfer::move_jobs(bsc, &from, &to, limit, dry_run, out),
+        Cmd::Export {
+            states,
+            filepath,
+            drain,
+        } => {
+            let tube = bsc.used_tube().to_string();
+            transfer::export(bsc, &tube, &states, filepath.as_deref(), drain, out)
         }
-        Cmd::PeekBuried => {
-            let res = bsc.peek_buried()?;
-            println!("{res:?}");
-            Ok(())
+        Cmd::Import {
+            filepath,
+            skip_duplicates,
+            journal,
+            dry_run,
+        } => {
+            let duplicates = Duplicates {
+                skip_same_body: skip_duplicates,
+                journal: journal.as_deref(),
+            };
+            transfer::import(bsc, filepath.as_deref(), tube, &duplicates, dry_run, out)
         }
-        Cmd::Kick { bound } => {
-            let n = bsc.kick(bound)?;
-            println!("Kicked({n})");
-            Ok(())
+        Cmd::KickAll { all_tubes } => {
+            let tubes = match all_tubes {
+                true => bsc.list_tubes()?.into_iter().map(String::from).collect(),
+                false => vec![bsc.used_tube().to_string()],
+            };
+            bulk::kick_all(bsc, &tubes, out)
+        }
+        Cmd::BuryAll { pattern } => {
+            let tube = bsc.used_tube().to_string();
+            bulk::bury_all(bsc, &tube, pattern.as_ref(), out)
         }
         Cmd::KickJob { id } => {
             let res = bsc.kick_job(id)?;
-            println!("{res:?}");
-            Ok(())
+            out.emit(&res, about_job(&res, id))
         }
-        Cmd::StatsJob { id } => {
-            match bsc.stats_job(id)? {
-                StatsJobResponse::Ok(res) => serde_json::to_writer(io::stdout(), &res)?,
-                StatsJobResponse::NotFound => println!("NotFound"),
+        Cmd::StatsJob { id } => match bsc.stats_job(id)? {
+            StatsJobResponse::Ok(res) => out.emit_stats(serde_yaml::to_value(res)?),
+            res => out.emit(&res, about_job(&res, id)),
+        },
+        Cmd::StatsTube {
+            tube,
+            watch: Some(interval),
+        } => out.watch_stats(interval, || match bsc.stats_tube(&tube)? {
+            StatsTubeResponse::Ok(res) => Ok(Some(serde_yaml::to_value(res)?)),
+            res => {
+                out.emit(&res, json!({ "reply": res.to_string(), "tube": tube }))?;
+                Ok(None)
             }
-            Ok(())
-        }
-        Cmd::StatsTube { tube } => {
-            match bsc.stats_tube(&tube)? {
-                StatsTubeResponse::Ok(res) => serde_json::to_writer(io::stdout(), &res)?,
-                StatsTubeResponse::NotFound => println!("NotFound"),
-            }
-            Ok(())
+        }),
+        Cmd::StatsTube { tube, watch: None } => match bsc.stats_tube(&tube)? {
+            StatsTubeResponse::Ok(res) => out.emit_stats(serde_yaml::to_value(res)?),
+            res => out.emit(&res, json!({ "reply": res.to_string(), "tube": tube })),
+        },
+        Cmd::Stats {
+            watch: Some(interval),
+        } => out.watch_stats(interval, || Ok(Some(serde_yaml::to_value(bsc.stats()?)?))),
+        Cmd::Stats { watch: None } => out.emit_stats(serde_yaml::to_value(bsc.stats()?)?),
+        Cmd::StatsTubes => out.emit_stats(serde_yaml::to_value(bsc.stats_all_tubes()?)?),
+        Cmd::ListTubes => out.emit_doc(json!(bsc.list_tubes()?)),
+        Cmd::ListTubesUsed => out.emit_doc(json!(bsc.list_tube_used()?)),
+        Cmd::ListTubesWatched => out.emit_doc(json!(bsc.list_tube_watched()?)),
+        Cmd::PauseTube { tube, delay } => {
+            let res = bsc.pause_tube(&tube, delay)?;
+            out.emit(&res, json!({ "reply": res.to_string(), "tube": tube }))
         }
-        Cmd::Stats => {
-            let res = bsc.stats()?;
-            serde_json::to_writer(io::stdout(), &res)?;
-            Ok(())
+        Cmd::ServerInfo => {
+            let info = bsc.server_info()?.clone();
+            let reserve_job = supported(bsc.reserve_by_id(0))?;
+            let kick_job = supported(bsc.kick_job(0))?;
+            let touch = supported(bsc.touch(0))?;
+            out.emit_doc(json!({
+                "version": info.version,
+                "max-job-size": info.max_job_size,
+                "draining": info.draining,
+                "commands": {
+                    "reserve-job": reserve_job,
+                    "kick-job": kick_job,
+                    "touch": touch,
+                },
+            }))
         }
-        Cmd::ListTubes => {
-            let res = bsc.list_tubes()?;
-            serde_json::to_writer(io::stdout(), &res)?;
-            Ok(())
+        Cmd::Top { interval, pause } => top::top(bsc, interval, pause, out.color),
+        Cmd::Tail { interval, preview } => {
+            let tube = bsc.used_tube().to_string();
+            tail::tail(bsc, &tube, interval, preview, out)
         }
-        Cmd::ListTubesUsed => {
-            let res = bsc.list_tube_used()?;
-            serde_json::to_writer(io::stdout(), &res)?;
-            Ok(())
+        Cmd::PushStats {
+            statsd,
+            graphite,
+            prefix,
+            interval,
+        } => {
+            let sinks = Sinks {
+                statsd,
+                graphite,
+                prefix,
+            };
+            push::push_stats(bsc, &sinks, interval)
         }
-        Cmd::ListTubesWatched => {
-            let res = bsc.list_tube_watched()?;
-            serde_json::to_writer(io::stdout(), &res)?;
-            Ok(())
-        }
-        Cmd::PauseTube { tube, delay } => {
-            let res = bsc.pause_tube(&tube, delay)?;
-            println!("{res:?}");
-            Ok(())
+        Cmd::Healthcheck { thresholds } => health::healthcheck(bsc, tube, &thresholds, out),
+        Cmd::Consume {
+            retries,
+            limits,
+            command,
+        } => consume::consume(bsc, connect, tube, &command, &retries, &limits, out),
+        Cmd::Completions { .. } | Cmd::Doctor | Cmd::Migrate { .. } => {
+            unreachable!("completions, doctor and migrate run without connecting to --addr")
         }
     }
 }
@@ -180,6 +525,7 @@ pub struct Cli {
     #[arg(
         long,
         short,
+        visible_alias = "use",
         help = "The <tube> name to use for the command. The default tube is \"default\".\nIf this is set, the \"use <tube>\" command will be issued prior to the actual command.",
         global = true,
         env
@@ -189,12 +535,153 @@ pub struct Cli {
     #[arg(
         long,
         short,
-        help = "The Beanstalkd endpoint to communicate with.",
+        help = "The Beanstalkd endpoint to communicate with, as \"host:port\" or as a URL:\n\"beanstalkd://host:port/tube?watch=a,b&ttr=60\" or \"beanstalkd+unix:///path/to/socket?tube=a\".\nThe tubes and put options of the URL apply unless given by flags.\nRepeat it, or give a comma separated list of \"host:port\", to fail over to the next endpoint when one is unreachable.",
         default_value = "127.0.0.1:11300",
         global = true,
         env = "BEANSTALKD"
     )]
-    addr: String,
+    addr: Vec<String>,
+
+    #[arg(
+        long,
+        help = "A prefix (e.g. \"staging.\") added to every tube name sent to the server, and stripped from the ones received.\nOnly the tubes having this prefix are listed.",
+        global = true,
+        env
+    )]
+    namespace: Option<String>,
+
+    #[arg(
+        long,
+        help = "Refuse to run destructive commands (e.g. kick) unless the tube is given explicitly with --tube\nor by the --addr URL, even if it is \"default\". Prevents acting on the \"default\" tube by accident.",
+        global = true,
+        env = "REQUIRE_EXPLICIT_TUBE"
+    )]
+    require_explicit_tube: bool,
+
+    #[arg(
+        long,
+        short,
+        value_enum,
+        default_value_t = Output::Text,
+        help = "How results are printed. \"json\" and \"yaml\" print one document per command, with the\nreply of the server in \"reply\" (e.g. {\"reply\": \"INSERTED\", \"id\": 12}), or the stats and tube lists as is.",
+        global = true,
+        env = "BSC_OUTPUT"
+    )]
+    output: Output,
+
+    #[arg(
+        long,
+        help = "Do not color the stats tables. Colors are also disabled when NO_COLOR is set, or when the output is not a terminal.",
+        global = true
+    )]
+    no_color: bool,
+
+    #[arg(
+        long,
+        short,
+        help = "Print nothing, and exit with status 1 when the server replies with e.g. NOT_FOUND or TIMED_OUT.",
+        global = true
+    )]
+    quiet: bool,
+
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "Prints the result through a template instead, e.g. \"{{id}} {{tube}} {{state}}\" for stats-job.\nThe placeholders are the fields of the --output json document, durations being in seconds.",
+        global = true
+    )]
+    format: Option<String>,
+
+    #[arg(
+        long,
+        short,
+        action = clap::ArgAction::Count,
+        help = "Log the commands sent and the replies received to stderr, with their latency.\n-vv adds timestamps, -vvv logs every raw line as it is written and read, with the size of the job bodies.",
+        global = true
+    )]
+    verbose: u8,
+
+    #[arg(
+        long,
+        help = "With -vvv, also log the job bodies, escaped.",
+        global = true
+    )]
+    trace_bodies: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Append a record per command, with its reply and latency, and the error ending the run if any, to this file.",
+        global = true,
+        env = "BSC_LOG_FILE"
+    )]
+    log_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = LogFormat::Text,
+        help = "The format of the --log-file records.",
+        global = true,
+        env = "BSC_LOG_FORMAT"
+    )]
+    log_format: LogFormat,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = parse_timeout,
+        help = "Give up connecting to an endpoint after that many seconds (e.g. 0.5), rather than when TCP does.",
+        global = true,
+        env = "BSC_CONNECT_TIMEOUT"
+    )]
+    connect_timeout: Option<Duration>,
+
+    #[arg(
+        long = "timeout",
+        value_name = "SECONDS",
+        value_parser = parse_timeout,
+        help = "Fail when the whole command, connecting included, takes more than that many seconds (e.g. 2.5).\nRefused for the commands running until interrupted.",
+        global = true,
+        env = "BSC_TIMEOUT"
+    )]
+    deadline: Option<Duration>,
+
+    #[arg(
+        long,
+        help = "Disable Nagle's algorithm (TCP_NODELAY), so that commands are sent right away.",
+        global = true,
+        env
+    )]
+    tcp_nodelay: bool,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        value_parser = parse_duration,
+        help = "Enable TCP keepalive (SO_KEEPALIVE), probing the server after that many seconds without traffic.",
+        global = true,
+        env
+    )]
+    tcp_keepalive: Option<Duration>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        help = "The size of the socket send buffer (SO_SNDBUF).",
+        global = true,
+        env
+    )]
+    tcp_send_buffer: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        help = "The size of the socket receive buffer (SO_RCVBUF).",
+        global = true,
+        env
+    )]
+    tcp_recv_buffer: Option<usize>,
 }
 
 #[derive(Subcommand)]
@@ -206,24 +693,22 @@ pub enum Cmd {
         #[arg(
             long,
             short,
-            default_value = "0",
-            help = "Jobs with smaller priority values will be scheduled before jobs with larger priorities.\nThe most urgent priority is 0; the least urgent priority is 4,294,967,295.",
+            help = "Jobs with smaller priority values will be scheduled before jobs with larger priorities.\nThe most urgent priority is 0 (the default); the least urgent priority is 4,294,967,295.",
             env
         )]
-        pri: u32,
+        pri: Option<u32>,
 
         #[arg(
             long,
             short,
-            default_value = "0",
             value_parser = parse_duration,
-            help = "An integer number of seconds to wait before putting the job in the ready queue.\nThe job will be in the \"delayed\" state during this time",
+            help = "An integer number of seconds to wait before putting the job in the ready queue (0 by default).\nThe job will be in the \"delayed\" state during this time",
             env
         )]
-        delay: Duration,
+        delay: Option<Duration>,
 
-        #[arg(long, default_value = "0", value_parser = parse_duration, help = TTR_HELP)]
-        ttr: Duration,
+        #[arg(long, value_parser = parse_duration, help = TTR_HELP)]
+        ttr: Option<Duration>,
 
         #[arg(
             index = 1,
@@ -231,6 +716,12 @@ pub enum Cmd {
             env
         )]
         filepath: Option<PathBuf>,
+
+        #[arg(
+            long,
+            help = "Only print the id of the job, and exit with status 1 if it is not inserted."
+        )]
+        id_only: bool,
     },
 
     #[command(
@@ -248,6 +739,13 @@ pub enum Cmd {
 
         #[arg(long, short, help = "Only return the data.")]
         data: bool,
+
+        #[arg(
+            long,
+            conflicts_with = "data",
+            help = "Only print the id of the job, and exit with status 1 if none is reserved."
+        )]
+        id_only: bool,
     },
 
     #[command(
@@ -349,6 +847,165 @@ pub enum Cmd {
         bound: u32,
     },
 
+    #[command(
+        about = "Kicks all the buried and delayed jobs of the currently used tube, or of every tube.",
+        long_about = "Kicks all the buried jobs, then all the delayed jobs, of the currently used tube, or of every tube\nwith --all-tubes, without having to guess a bound. Prints how many jobs were kicked per tube.\nNo more jobs than the tube held when starting are kicked, in case a worker buries them again."
+    )]
+    KickAll {
+        #[arg(long, help = "Kick the jobs of every tube.")]
+        all_tubes: bool,
+    },
+
+    #[command(
+        about = "Buries all the ready jobs of the currently used tube, e.g. to freeze it while a bad deploy is rolled back.",
+        long_about = "Reserves all the ready jobs of the currently used tube and buries them with their priority, e.g. to freeze\nthe tube while a bad deploy is rolled back. Kick them back with `bsc kick-all`.\nWith --match, the jobs whose body does not match are released once done.",
+        after_help = "Example: bsc bury-all --tube broken --match '\"version\":\\s*3'"
+    )]
+    BuryAll {
+        #[arg(
+            long = "match",
+            value_name = "REGEX",
+            value_parser = regex::bytes::Regex::new,
+            help = "Only bury the jobs whose body matches this regular expression."
+        )]
+        pattern: Option<regex::bytes::Regex>,
+    },
+
+    #[command(
+        about = "Deletes the jobs of the currently used tube in the given states, e.g. to clear a poisoned tube.",
+        long_about = "Deletes the jobs of the currently used tube in the given states, then prints how many were deleted.\nThe jobs are found with peek-<state> and deleted one at a time. With --older-than, the ready jobs\nare reserved to find the old ones, the others being released at the end, and the buried ones are\ndeleted until the first younger one. Delayed jobs cannot be filtered by age.",
+        after_help = "Example: bsc purge --tube emails --state ready,buried --older-than 1h"
+    )]
+    Purge {
+        #[arg(
+            long = "state",
+            value_enum,
+            value_delimiter = ',',
+            required = true,
+            help = "The states of the jobs to delete, as a comma separated list."
+        )]
+        states: Vec<JobState>,
+
+        #[arg(
+            long,
+            value_name = "DURATION",
+            value_parser = parse_timeout,
+            help = "Only delete the jobs older than that, e.g. 1h."
+        )]
+        older_than: Option<Duration>,
+
+        #[arg(long, value_name = "COUNT", help = "Delete at most that many jobs.")]
+        limit: Option<usize>,
+    },
+
+    #[command(
+        about = "Moves the ready jobs of a tube to another one, keeping their priority and TTR.",
+        long_about = "Moves the ready jobs of a tube to another one, e.g. to recover from producers putting jobs in the wrong tube.\nEach job is reserved, put into the destination with the priority and TTR read from stats-job, then deleted.\nDelayed and buried jobs are not moved, kick them first if needed.",
+        after_help = "Example: bsc move --from bad-tube --to good-tube --limit 1000"
+    )]
+    Move {
+        #[arg(long, value_name = "TUBE", help = "The tube to take the jobs from.")]
+        from: String,
+
+        #[arg(long, value_name = "TUBE", help = "The tube to put the jobs into.")]
+        to: String,
+
+        #[arg(long, value_name = "COUNT", help = "Move at most that many jobs.")]
+        limit: Option<usize>,
+
+        #[arg(long, help = "Only tell how many jobs would be moved.")]
+        dry_run: bool,
+    },
+
+    #[command(
+        about = "Writes the jobs of the currently used tube to a file, as one JSON object per line.",
+        long_about = "Writes the jobs of the currently used tube to <filepath>, or to stdout, as one JSON object per line with\nthe id, tube, state, pri, delay-left, ttr and the body in base64, which `bsc import` reads back.\nThe jobs are left as they are: beanstalkd cannot list jobs, so every job id is probed with peek,\nwhich takes a while on a server that has seen many jobs. With --drain, the jobs are rather found\nwith peek-<state> and deleted once written.",
+        after_help = "Example: bsc export --tube emails --state ready,delayed,buried dump.ndjson"
+    )]
+    Export {
+        #[arg(
+            long = "state",
+            value_enum,
+            value_delimiter = ',',
+            default_value = "ready,delayed,buried",
+            help = "The states of the jobs to export, as a comma separated list."
+        )]
+        states: Vec<JobState>,
+
+        #[arg(
+            index = 1,
+            help = "The file to write the jobs to.\nIf no <filepath> is given, the jobs are written to <stdout>."
+        )]
+        filepath: Option<PathBuf>,
+
+        #[arg(long, help = "Delete the jobs once written.")]
+        drain: bool,
+    },
+
+    #[command(
+        about = "Puts the jobs written by `bsc export`, into their tube or into the --tube.",
+        long_about = "Puts the jobs written by `bsc export` to <filepath>, or read from stdin, with their priority, remaining delay\nand TTR, into the tube they were exported from, or into the --tube if given. Buried jobs are put as ready ones.\nA job id found twice in the input is only imported once. With --journal, the ids of the jobs imported are recorded, so that running the import again after\nan interruption skips them.",
+        after_help = "Example: bsc import --journal dump.imported dump.ndjson"
+    )]
+    Import {
+        #[arg(
+            index = 1,
+            help = "The file to read the jobs from.\nIf no <filepath> is given, the jobs are read from <stdin>."
+        )]
+        filepath: Option<PathBuf>,
+
+        #[arg(
+            long,
+            help = "Skip the jobs with the same tube and body as an earlier job of the input."
+        )]
+        skip_duplicates: bool,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "A file recording the ids of the jobs imported, which are skipped when importing again."
+        )]
+        journal: Option<PathBuf>,
+
+        #[arg(
+            long,
+            help = "Only check the input and tell how many jobs would be imported."
+        )]
+        dry_run: bool,
+    },
+
+    #[command(
+        about = "Moves the jobs of a server to another one, e.g. to replace its host.",
+        long_about = "Moves the ready and delayed jobs of the --tubes, and of the --tube, or of every tube, from a server to another one.\nThe jobs keep their tube, priority, TTR and remaining delay. Each job is deleted from the source once put\ninto the destination, so an interrupted migration is resumed by running it again.\nBuried and reserved jobs are not migrated, and are counted as left. --addr is not used.",
+        after_help = "Example: bsc migrate --from old:11300 --to new:11300 --tubes emails,payments"
+    )]
+    Migrate {
+        #[arg(
+            long,
+            value_name = "ADDR",
+            help = "The server to take the jobs from, as \"host:port\" or as a URL."
+        )]
+        from: String,
+
+        #[arg(
+            long,
+            value_name = "ADDR",
+            help = "The server to put the jobs into, as \"host:port\" or as a URL."
+        )]
+        to: String,
+
+        #[arg(
+            long,
+            value_name = "TUBE",
+            value_delimiter = ',',
+            help = "The tubes to migrate, as a comma separated list. Every tube by default."
+        )]
+        tubes: Vec<String>,
+
+        #[arg(long, value_name = "COUNT", help = "Migrate at most that many jobs.")]
+        limit: Option<usize>,
+    },
+
     #[command(
         about = "The kick-job command is a variant of kick that operates with a single job identified by its job id.",
         long_about = "The kick-job command is a variant of kick that operates with a single job identified by its job id.\nIf the given job id exists and is in a buried or delayed state, it will be moved to the ready queue of\nthe the same tube where it currently belongs."
@@ -372,12 +1029,23 @@ pub enum Cmd {
     StatsTube {
         #[arg(index = 1, env, help = "The <tube> name.")]
         tube: String,
+
+        #[arg(long, value_name = "SECONDS", value_parser = parse_timeout, help = WATCH_HELP)]
+        watch: Option<Duration>,
     },
 
     #[command(
         about = "The stats command gives statistical information about the system as a whole."
     )]
-    Stats,
+    Stats {
+        #[arg(long, value_name = "SECONDS", value_parser = parse_timeout, help = WATCH_HELP)]
+        watch: Option<Duration>,
+    },
+
+    #[command(
+        about = "Prints the stats of every tube, as a table with a line per tube in text mode."
+    )]
+    StatsTubes,
 
     #[command(about = "The list-tubes command returns a list of all existing tubes.")]
     ListTubes,
@@ -408,15 +1076,253 @@ pub enum Cmd {
         )]
         delay: Duration,
     },
+
+    #[command(
+        about = "Prints the server version, max-job-size, drain state and the optional commands it supports as JSON.",
+        long_about = "Prints the server version, max-job-size, drain state and the optional commands it supports as JSON.\nOptional commands are probed using job id 0, which beanstalkd never assigns, so no job is affected."
+    )]
+    ServerInfo,
+
+    #[command(
+        about = "A live view of the server and of its tubes, sortable, from which tubes can be paused and kicked.",
+        long_about = "A live view of the global stats and of a table of the tubes, with their put rates, refreshed every <interval>.\nKeys: q quit, up/down select a tube, left/right sort by another column, r reverse the order,\np pause the selected tube, u unpause it, k kick its buried (or delayed) jobs."
+    )]
+    Top {
+        #[arg(
+            long,
+            short,
+            value_name = "SECONDS",
+            value_parser = parse_timeout,
+            default_value = "2",
+            help = "The time between refreshes, in seconds (e.g. 0.5)."
+        )]
+        interval: Duration,
+
+        #[arg(
+            long,
+            value_name = "SECONDS",
+            value_parser = parse_duration,
+            default_value = "60",
+            help = "How long the p key pauses the selected tube."
+        )]
+        pause: Duration,
+    },
+
+    #[command(
+        about = "Shows the jobs put into the --tube from now on, with a preview of their body, without reserving them.",
+        long_about = "Shows the jobs put into the --tube from now on, with a preview of their body, e.g. to check what a producer emits.\nNo job is reserved: the tube's total-jobs is polled every <interval>, and the new job ids are probed with peek.\nJobs already deleted by a consumer when polled cannot be shown, and are only counted on stderr."
+    )]
+    Tail {
+        #[arg(
+            long,
+            short,
+            value_name = "SECONDS",
+            value_parser = parse_timeout,
+            default_value = "1",
+            help = "The time between polls, in seconds (e.g. 0.5)."
+        )]
+        interval: Duration,
+
+        #[arg(
+            long,
+            value_name = "BYTES",
+            default_value_t = 80,
+            help = "How many bytes of each body are shown."
+        )]
+        preview: usize,
+    },
+
+    #[command(
+        about = "Checks that the server is reachable and healthy, and tells what is worth a look.",
+        long_about = "Checks that the server is reachable and healthy, and tells what is worth a look: name resolution,\nconnection, round trip time, version, drain mode, binlog, max-job-size and buried jobs.\nEvery --addr endpoint is checked, the exit status is 1 when one of them is unreachable."
+    )]
+    Doctor,
+
+    #[command(
+        about = "Pushes the stats of the server and of every tube to StatsD or Graphite, every <interval>.",
+        long_about = "Pushes the numeric stats of the server, and those of every tube as <prefix>tubes.<tube>.<field>, to StatsD\nas gauges or to Graphite with its plaintext protocol, every <interval>, until interrupted.\nDashes and dots in the names are turned into underscores, durations are in seconds.",
+        group(clap::ArgGroup::new("sink").required(true).multiple(true).args(["statsd", "graphite"]))
+    )]
+    PushStats {
+        #[arg(
+            long,
+            value_name = "HOST:PORT",
+            help = "The StatsD server, e.g. localhost:8125."
+        )]
+        statsd: Option<String>,
+
+        #[arg(
+            long,
+            value_name = "HOST:PORT",
+            help = "The Graphite server, e.g. localhost:2003."
+        )]
+        graphite: Option<String>,
+
+        #[arg(
+            long,
+            default_value = "beanstalk.",
+            help = "Prepended to every metric name."
+        )]
+        prefix: String,
+
+        #[arg(
+            long,
+            short,
+            value_name = "DURATION",
+            value_parser = parse_timeout,
+            default_value = "10s",
+            help = "The time between pushes, e.g. 10s or 1m."
+        )]
+        interval: Duration,
+    },
+
+    #[command(
+        about = "Checks the job counts of the server, or of the --tube, against thresholds, for monitoring probes.",
+        long_about = "Checks the job counts of the server, or of the --tube, against thresholds, for monitoring probes.\nPrints a one line verdict, and exits with status 0 when healthy, 1 when a threshold is exceeded,\n2 when the server is unreachable, and 3 when the tube does not exist."
+    )]
+    Healthcheck {
+        #[command(flatten)]
+        thresholds: Thresholds,
+    },
+
+    #[command(
+        about = "Runs a command for each job reserved from the --tube, or from the watched tubes, until interrupted.",
+        long_about = "Runs a command for each job reserved from the --tube, or from the watched tubes, until interrupted.\nThe body of the job is written to its stdin, and the job is described by the BSC_JOB_ID, BSC_TUBE,\nBSC_JOB_PRI, BSC_JOB_TTR, BSC_JOB_RELEASES and BSC_JOB_RESERVES environment variables.\nThe job is deleted when the command exits with status 0, released with a growing delay otherwise,\nand buried once retried --retries times. A command still running at the TTR of its job is killed.\nThe output of the command goes to stderr.\nSIGTERM or Ctrl-C stops reserving jobs and waits for the running commands, a second one exits now.",
+        after_help = "Example: bsc consume --tube emails -- ./send-email.sh --verbose"
+    )]
+    Consume {
+        #[command(flatten)]
+        retries: Retries,
+
+        #[command(flatten)]
+        limits: Limits,
+
+        #[arg(
+            last = true,
+            required = true,
+            value_name = "COMMAND",
+            help = "The command to run, and its arguments, after --."
+        )]
+        command: Vec<String>,
+    },
+
+    #[command(
+        about = "Prints the completion script of a shell, e.g. `bsc completions bash > /etc/bash_completion.d/bsc`."
+    )]
+    Completions {
+        #[arg(
+            index = 1,
+            value_enum,
+            help = "The <shell> to complete the commands and flags in."
+        )]
+        shell: Shell,
+    },
+}
+
+impl Cmd {
+    /// Commands that alter the jobs of the currently used tube, guarded by
+    /// `--require-explicit-tube`.
+    fn is_destructive(&self) -> bool {
+        matches!(
+            self,
+            Cmd::Kick { .. }
+                | Cmd::KickAll { all_tubes: false }
+                | Cmd::BuryAll { .. }
+                | Cmd::Purge { .. }
+                | Cmd::Export { drain: true, .. }
+        )
+    }
+
+    /// Commands that keep going until interrupted, for which `--timeout` is refused.
+    fn runs_until_interrupted(&self) -> bool {
+        matches!(
+            self,
+            Cmd::Consume { .. }
+                | Cmd::Top { .. }
+                | Cmd::Tail { .. }
+                | Cmd::PushStats { .. }
+                | Cmd::Stats { watch: Some(_) }
+                | Cmd::StatsTube { watch: Some(_), .. }
+        )
+    }
+}
+
+/// Probes whether the server knows about an optional command, given the result of
+/// calling it with arguments that cannot affect any job.
+fn supported<T>(res: Result<T, Error>) -> Result<bool, Report> {
+    match res {
+        Ok(_) => Ok(true),
+        Err(Error::Unsupported { .. }) => Ok(false),
+        Err(Error::Reply { reply, .. }) if reply == "UNKNOWN_COMMAND" => Ok(false),
+        Err(err) => Err(err.into()),
+    }
 }
 
 fn parse_duration(arg: &str) -> Result<Duration, std::num::ParseIntError> {
     Ok(Duration::from_secs(arg.parse()?))
 }
 
+/// Parses a positive number of seconds, possibly fractional, or a duration with a
+/// unit: "500ms", "10s", "5m" or "1h".
+fn parse_timeout(arg: &str) -> Result<Duration, String> {
+    let (number, unit) = match arg.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(at) => arg.split_at(at),
+        None => (arg, "s"),
+    };
+    let scale = match unit {
+        "ms" => 1e-3,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(format!("unknown unit {unit:?}, expected ms, s, m or h")),
+    };
+    match number
+        .parse::<f64>()
+        .map(|n| Duration::try_from_secs_f64(n * scale))
+    {
+        Ok(Ok(timeout)) if !timeout.is_zero() => Ok(timeout),
+        _ => Err(format!("expected a positive duration, got {arg:?}")),
+    }
+}
+
+const WATCH_HELP: &str = "Refresh the stats every that many seconds (e.g. 2), showing how much the counters\nsuch as cmd-put changed since the previous refresh, and their rate per second.";
+
 const TTR_HELP: &str = r#"-- time to run -- is an integer number of seconds to allow a worker to run this job.
 This time is counted from the moment a worker reserves this job.
 If the worker does not delete, release, or bury the job within `ttr` seconds,
 the job will time out and the server will release the job. The minimum ttr is 1.
 If the  client sends 0, the server will silently increase the ttr to 1.
-Maximum ttr is 2**32-1."#;
+Maximum ttr is 2**32-1. Defaults to 60."#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("0"), Ok(Duration::ZERO));
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert!(parse_duration("1.5").is_err());
+        assert!(parse_duration("-1").is_err());
+        assert!(parse_duration("10s").is_err());
+    }
+
+    #[test]
+    fn parses_timeouts() {
+        let ms = Duration::from_millis;
+        assert_eq!(parse_timeout("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_timeout("2.5"), Ok(ms(2500)));
+        assert_eq!(parse_timeout("500ms"), Ok(ms(500)));
+        assert_eq!(parse_timeout("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_timeout("1.5m"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_timeout("1h"), Ok(Duration::from_secs(3600)));
+
+        for arg in ["0", "0s", "-1", "", "s", "soon", "1e400"] {
+            assert!(parse_timeout(arg).is_err(), "{arg}");
+        }
+        assert_eq!(
+            parse_timeout("2d"),
+            Err("unknown unit \"d\", expected ms, s, m or h".to_string())
+        );
+    }
+}
@@ -0,0 +1,555 @@
+use std::cell::Cell;
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use simple_eyre::eyre::{bail, eyre, Report};
+
+use bsc::{Body, Error, Id, PeekResponse};
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// The replies of the server, tables for stats and JSON for tube lists
+    Text,
+    /// One JSON document per line
+    Json,
+    /// One YAML document
+    Yaml,
+    /// A header line and a line per record, e.g. per tube
+    Csv,
+}
+
+/// Prints the results of the commands, as asked by the global flags.
+pub struct Printer {
+    pub output: Output,
+    /// A template such as "{{id}} {{state}}", filled with the fields of the documents.
+    pub format: Option<String>,
+    pub color: bool,
+    /// Nothing is printed, see [`failed`](Self::failed).
+    pub quiet: bool,
+    pub failed: Cell<bool>,
+}
+
+impl Printer {
+    /// Whether job bodies are printed as is, rather than within a document.
+    pub fn raw(&self) -> bool {
+        self.output == Output::Text && self.format.is_none()
+    }
+
+    /// Whether the command did not do what was asked, e.g. the server replied
+    /// `NOT_FOUND` in quiet mode, so that the exit status can tell it.
+    pub fn failed(&self) -> bool {
+        self.failed.get()
+    }
+
+    /// Records that the command did not do what was asked.
+    pub fn fail(&self) {
+        self.failed.set(true);
+    }
+
+    /// Prints a job body as is.
+    pub fn emit_data(&self, data: &Body) -> Result<(), Report> {
+        if !self.quiet {
+            data.write_to(&mut io::stdout())?;
+        }
+        Ok(())
+    }
+
+    /// Prints `text` in text mode, `doc` otherwise.
+    pub fn emit(&self, text: impl Display, doc: Value) -> Result<(), Report> {
+        let reply = doc.get("reply").and_then(Value::as_str);
+        if self.quiet {
+            if reply.is_some_and(|reply| FAILURES.contains(&reply)) {
+                self.fail();
+            }
+            return Ok(());
+        }
+        if self.raw() {
+            println!("{text}");
+            return Ok(());
+        }
+        self.emit_doc(doc)
+    }
+
+    /// Prints `doc`, as JSON in text mode too.
+    pub fn emit_doc(&self, doc: Value) -> Result<(), Report> {
+        if self.quiet {
+            return Ok(());
+        }
+        if let Some(template) = &self.format {
+            println!("{}", render(template, &doc)?);
+            return Ok(());
+        }
+        match self.output {
+            Output::Text => serde_json::to_writer(io::stdout(), &doc)?,
+            Output::Json => println!("{doc}"),
+            Output::Yaml => print!("{}", serde_yaml::to_string(&doc)?),
+            Output::Csv => write_csv(serde_yaml::to_value(doc)?, true)?,
+        }
+        Ok(())
+    }
+
+    /// Prints stats, or a list of stats, as a table in text mode and as a document
+    /// otherwise. They are given as YAML as, unlike JSON maps, YAML mappings keep the
+    /// order of the fields.
+    pub fn emit_stats(&self, stats: serde_yaml::Value) -> Result<(), Report> {
+        match self.output {
+            _ if self.quiet => Ok(()),
+            Output::Csv if self.format.is_none() => write_csv(stats, true),
+            _ if !self.raw() => self.emit_doc(serde_json::to_value(stats)?),
+            _ => {
+                let (header, rows) = records(stats, scalar);
+                if let [row] = &rows[..] {
+                    // a single record reads better with one field per line
+                    let fields = header.iter().zip(row);
+                    let rows = fields.map(|(name, value)| vec![name.clone(), value.clone()]);
+                    self.print_table(None, rows.collect())
+                } else {
+                    self.print_table(Some(header), rows)
+                }
+            }
+        }
+    }
+
+    /// Prints the stats returned by `fetch` every `interval`, with the change of the
+    /// counters (e.g. `cmd-put`) since the previous refresh, until `fetch` returns
+    /// `None`. In text mode the table is refreshed in place on a terminal, otherwise
+    /// each refresh is a document where every counter has its `-delta` and `-rate`
+    /// per second, null on the first refresh.
+    pub fn watch_stats(
+        &self,
+        interval: Duration,
+        mut fetch: impl FnMut() -> Result<Option<serde_yaml::Value>, Report>,
+    ) -> Result<(), Report> {
+        let mut previous: Option<(Instant, serde_yaml::Mapping)> = None;
+        loop {
+            let Some(serde_yaml::Value::Mapping(stats)) = fetch()? else {
+                return Ok(());
+            };
+            let now = Instant::now();
+            // every counter, with its delta and rate unless this is the first refresh
+            let mut deltas = Vec::new();
+            for (field, value) in &stats {
+                let name = scalar(field);
+                let counter = name.starts_with("cmd-")
+                    || name.starts_with("total-")
+                    || name.starts_with("binlog-records-")
+                    || name == "job-timeouts";
+                if !counter {
+                    continue;
+                }
+                let change = previous.as_ref().and_then(|(then, before)| {
+                    let delta = value.as_u64()?.saturating_sub(before.get(field)?.as_u64()?);
+                    let secs = now.duration_since(*then).as_secs_f64();
+                    Some((delta, delta as f64 / secs))
+                });
+                deltas.push((name, change));
+            }
+
+            if self.quiet {
+                // nothing to print
+            } else if self.raw() {
+                let header = ["field", "value", "delta", "rate/s"].map(String::from);
+                let rows = stats
+                    .iter()
+                    .map(|(field, value)| {
+                        let name = scalar(field);
+                        let change = deltas.iter().find(|(counter, _)| *counter == name);
+                        let (delta, rate) = match change {
+                            Some((_, Some((delta, rate)))) => {
+                                (format!("+{delta}"), format!("{rate:.1}"))
+                            }
+                            _ => (String::new(), String::new()),
+                        };
+                        vec![name, scalar(value), delta, rate]
+                    })
+                    .collect();
+                if io::stdout().is_terminal() {
+                    print!("\x1b[H\x1b[2J");
+                } else if previous.is_some() {
+                    println!();
+                }
+                self.print_table(Some(header.to_vec()), rows)?;
+            } else {
+                let mut doc = stats.clone();
+                for (name, change) in &deltas {
+                    let (delta, rate) = match change {
+                        Some((delta, rate)) => ((*delta).into(), (*rate).into()),
+                        None => (serde_yaml::Value::Null, serde_yaml::Value::Null),
+                    };
+                    doc.insert(format!("{name}-delta").into(), delta);
+                    doc.insert(format!("{name}-rate").into(), rate);
+                }
+                match self.output {
+                    // a header for the first refresh only, to keep a single table
+                    Output::Csv if self.format.is_none() => {
+                        write_csv(doc.into(), previous.is_none())?
+                    }
+                    _ => self.emit_doc(serde_json::to_value(doc)?)?,
+                }
+            }
+            io::stdout().flush()?;
+            previous = Some((now, stats));
+            thread::sleep(interval);
+        }
+    }
+
+    /// Prints `rows` as aligned columns, under `header` if any, highlighting buried
+    /// jobs and urgent counts.
+    fn print_table(
+        &self,
+        header: Option<Vec<String>>,
+        rows: Vec<Vec<String>>,
+    ) -> Result<(), Report> {
+        let lines: Vec<&Vec<String>> = header.iter().chain(&rows).collect();
+        let columns = lines.iter().map(|line| line.len()).max().unwrap_or(0);
+        let widths: Vec<usize> = (0..columns)
+            .map(|i| {
+                lines
+                    .iter()
+                    .filter_map(|line| line.get(i))
+                    .map(String::len)
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let mut stdout = io::stdout().lock();
+        for (n, line) in lines.iter().enumerate() {
+            let is_header = n == 0 && header.is_some();
+            for (i, cell) in line.iter().enumerate() {
+                // the field of a cell is named by its column, or by the first cell of
+                // its line
+                let field = match &header {
+                    Some(header) => &header[i],
+                    None => &line[0],
+                };
+                let highlight = match (field.as_str(), cell.as_str()) {
+                    _ if is_header || !self.color => None,
+                    (_, "0") => None,
+                    (field, _) if field.ends_with("jobs-buried") => Some(RED),
+                    (field, _) if field.ends_with("jobs-urgent") => Some(YELLOW),
+                    ("state", "buried") => Some(RED),
+                    _ => None,
+                };
+                let width = widths[i];
+                match (highlight, i + 1 == line.len()) {
+                    (Some(style), true) => write!(stdout, "{style}{cell}{RESET}")?,
+                    (Some(style), false) => write!(stdout, "{style}{cell:<width$}{RESET}  ")?,
+                    (None, true) => write!(stdout, "{cell}")?,
+                    (None, false) => write!(stdout, "{cell:<width$}  ")?,
+                }
+            }
+            writeln!(stdout)?;
+        }
+        Ok(())
+    }
+
+    /// Prints the result of a peek, the job included unless in text mode.
+    pub fn emit_job(&self, res: PeekResponse) -> Result<(), Report> {
+        let doc = match &res {
+            PeekResponse::Found { id, data } => {
+                json!({ "reply": "FOUND", "id": id, "data": data })
+            }
+            PeekResponse::NotFound => reply(&res),
+        };
+        self.emit(&res, doc)
+    }
+}
+
+/// Replies telling that a command did not do what was asked.
+const FAILURES: &[&str] = &[
+    "NOT_FOUND",
+    "TIMED_OUT",
+    "DEADLINE_SOON",
+    "NOT_IGNORED",
+    "JOB_TOO_BIG",
+    "EXPECTED_CRLF",
+    "DRAINING",
+];
+
+pub const RED: &str = "\x1b[1;31m";
+pub const YELLOW: &str = "\x1b[1;33m";
+pub const GREEN: &str = "\x1b[1;32m";
+pub const RESET: &str = "\x1b[0m";
+
+/// The width of the bar of [`Progress`], in characters.
+const PROGRESS_WIDTH: usize = 30;
+
+/// A progress bar drawn on stderr while jobs are processed, when stderr is a terminal,
+/// so that it does not end up in logs.
+pub struct Progress {
+    label: &'static str,
+    total: usize,
+    enabled: bool,
+}
+
+impl Progress {
+    /// A bar for `total` jobs, none drawn if `quiet`.
+    pub fn new(label: &'static str, total: usize, quiet: bool) -> Self {
+        Self {
+            label,
+            total,
+            enabled: !quiet && io::stderr().is_terminal(),
+        }
+    }
+
+    /// Draws the bar with `done` of the jobs processed. More jobs than expected may
+    /// be processed, e.g. when some are added meanwhile.
+    pub fn update(&self, done: usize) {
+        if !self.enabled {
+            return;
+        }
+        let total = self.total.max(done);
+        let filled = match total {
+            0 => PROGRESS_WIDTH,
+            total => done * PROGRESS_WIDTH / total,
+        };
+        let bar = format!(
+            "{}{}",
+            "=".repeat(filled),
+            " ".repeat(PROGRESS_WIDTH - filled)
+        );
+        eprint!("\r{} [{bar}] {done}/{total}", self.label);
+    }
+
+    /// Ends the line of the bar.
+    pub fn finish(&self) {
+        if self.enabled {
+            eprintln!();
+        }
+    }
+}
+
+/// Whether to color the output: not with `--no-color`, nor when `NO_COLOR` is set
+/// (see <https://no-color.org>), nor when stdout is not a terminal.
+pub fn use_color(no_color: bool) -> bool {
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    !no_color && !no_color_env && io::stdout().is_terminal()
+}
+
+/// A stats value as shown in tables, durations in seconds.
+fn scalar(value: &serde_yaml::Value) -> String {
+    use serde_yaml::Value as Yaml;
+    match value {
+        Yaml::Null => "-".to_string(),
+        Yaml::Bool(b) => b.to_string(),
+        // CPU times are f32 seconds with microseconds, widened to f64
+        Yaml::Number(n) if n.is_f64() => {
+            let secs = n.as_f64().unwrap_or_default();
+            ((secs * 1e6).round() / 1e6).to_string()
+        }
+        Yaml::Number(n) => n.to_string(),
+        Yaml::String(s) => s.clone(),
+        Yaml::Mapping(map) => match map.get("secs") {
+            Some(secs) => format!("{}s", scalar(secs)),
+            None => serde_yaml::to_string(map).unwrap_or_default(),
+        },
+        other => serde_yaml::to_string(other).unwrap_or_default(),
+    }
+}
+
+/// A stats value as a number, durations in seconds without unit.
+fn seconds(value: &serde_yaml::Value) -> String {
+    match value {
+        serde_yaml::Value::Mapping(map) if map.contains_key("secs") => scalar(&map["secs"]),
+        value => scalar(value),
+    }
+}
+
+/// Turns a mapping, or a list of mappings, into a header and a row per mapping, the
+/// values being turned into cells by `cell`. Other values have a single "value"
+/// column.
+fn records(
+    value: serde_yaml::Value,
+    cell: fn(&serde_yaml::Value) -> String,
+) -> (Vec<String>, Vec<Vec<String>>) {
+    use serde_yaml::Value as Yaml;
+    let items = match value {
+        Yaml::Sequence(items) => items,
+        value => vec![value],
+    };
+    let header = match items.first() {
+        Some(Yaml::Mapping(fields)) => fields.keys().map(scalar).collect(),
+        _ => vec!["value".to_string()],
+    };
+    let rows = items
+        .iter()
+        .map(|item| match item {
+            Yaml::Mapping(fields) => fields.values().map(cell).collect(),
+            item => vec![cell(item)],
+        })
+        .collect();
+    (header, rows)
+}
+
+/// Prints `value` as CSV, see [`records`], durations in seconds, under a header line
+/// if `with_header`.
+fn write_csv(value: serde_yaml::Value, with_header: bool) -> Result<(), Report> {
+    let (header, rows) = records(value, seconds);
+    let header = with_header.then_some(header);
+    let mut stdout = io::stdout().lock();
+    for row in header.iter().chain(&rows) {
+        let cells: Vec<_> = row.iter().map(|cell| quote(cell)).collect();
+        writeln!(stdout, "{}", cells.join(","))?;
+    }
+    Ok(())
+}
+
+/// Quotes a CSV `cell` if needed.
+fn quote(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", cell.replace('"', "\"\""));
+    }
+    cell.to_string()
+}
+
+/// Fills the `{{field}}` placeholders of `template` with the fields of `doc`.
+fn render(template: &str, doc: &Value) -> Result<String, Report> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find("}}") else {
+            bail!("unclosed placeholder in --format {template:?}");
+        };
+        let name = rest[start + 2..start + end].trim();
+        let value = doc.get(name).ok_or_else(|| match doc.as_object() {
+            Some(fields) => {
+                let known: Vec<_> = fields.keys().map(String::as_str).collect();
+                eyre!(
+                    "unknown field {name:?}, the fields are: {}",
+                    known.join(", ")
+                )
+            }
+            None => eyre!("unknown field {name:?}, the result has no fields"),
+        })?;
+        match value {
+            Value::String(s) => out.push_str(s),
+            // durations, in seconds
+            Value::Object(map) if map.contains_key("secs") => {
+                out.push_str(&map["secs"].to_string())
+            }
+            other => out.push_str(&other.to_string()),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// The document of a reply without arguments.
+pub fn reply(res: &impl Display) -> Value {
+    json!({ "reply": res.to_string() })
+}
+
+/// The document of a reply to a command about the job `id`.
+pub fn about_job(res: &impl Display, id: Id) -> Value {
+    json!({ "reply": res.to_string(), "id": id })
+}
+
+/// `err` followed by its causes, e.g. "connect: I/O error: Connection refused (os
+/// error 111)". A cause is skipped when the error it caused already ends with it.
+pub fn with_causes(err: &dyn std::error::Error) -> String {
+    let mut text = err.to_string();
+    let mut source = err.source();
+    while let Some(cause) = source {
+        let cause_text = cause.to_string();
+        if !text.ends_with(&cause_text) {
+            text.push_str(&format!(": {cause_text}"));
+        }
+        source = cause.source();
+    }
+    text
+}
+
+/// The document describing a failure: its `kind`, the `command` and the `reply` of
+/// the server when known, the server `address`, and the `message` with its causes.
+pub fn error_doc(report: &Report, address: &str) -> Value {
+    let error = report.chain().find_map(|err| err.downcast_ref::<Error>());
+    let (kind, command, reply) = match error {
+        Some(Error::Io(_)) => ("io", None, None),
+        Some(Error::Bs(_)) => ("protocol", None, None),
+        Some(Error::Parse(_) | Error::Yaml(_)) => ("parse", None, None),
+        Some(Error::Reply { cmd, reply, .. }) => ("reply", Some(*cmd), Some(reply.as_str())),
+        Some(Error::Unsupported { cmd, .. }) => ("unsupported", Some(*cmd), None),
+        Some(Error::WouldBeBadFormat { cmd, .. }) => ("bad_format", Some(cmd.as_str()), None),
+        Some(Error::ResponseLineTooLong { cmd, .. }) => ("protocol", Some(*cmd), None),
+        Some(Error::RateLimited) => ("rate_limited", None, None),
+        Some(Error::ResponseTimeout) => ("timeout", None, None),
+        Some(Error::Body { .. }) => ("body", None, None),
+        None => ("cli", None, None),
+    };
+    let causes: Vec<String> = report.chain().skip(1).map(|err| err.to_string()).collect();
+    json!({
+        "error": {
+            "kind": kind,
+            "command": command,
+            "reply": reply,
+            "address": address,
+            "message": report.to_string(),
+            "causes": causes,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_csv_cells() {
+        assert_eq!(quote("plain"), "plain");
+        assert_eq!(quote(""), "");
+        assert_eq!(quote("a,b"), "\"a,b\"");
+        assert_eq!(quote("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(quote("two\nlines"), "\"two\nlines\"");
+        assert_eq!(quote("cr\r"), "\"cr\r\"");
+    }
+
+    #[test]
+    fn renders_templates() {
+        let doc = json!({
+            "id": 12,
+            "reply": "INSERTED",
+            "tube": "emails",
+            "delay": { "secs": 5, "nanos": 0 },
+            "paused": false,
+        });
+        let render = |template| render(template, &doc).unwrap();
+        assert_eq!(render("{{reply}} {{id}}"), "INSERTED 12");
+        assert_eq!(render("{{ tube }}:{{delay}}s"), "emails:5s");
+        assert_eq!(render("paused={{paused}}"), "paused=false");
+        assert_eq!(render("no placeholder"), "no placeholder");
+        assert_eq!(render(""), "");
+    }
+
+    #[test]
+    fn render_errors() {
+        let doc = json!({ "id": 1, "reply": "DELETED" });
+        let err = render("{{id", &doc).unwrap_err();
+        assert_eq!(err.to_string(), "unclosed placeholder in --format \"{{id\"");
+        let err = render("{{tube}}", &doc).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown field \"tube\", the fields are: id, reply"
+        );
+        let err = render("{{id}}", &json!("OK")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unknown field \"id\", the result has no fields"
+        );
+    }
+
+    #[test]
+    fn causes_told_once() {
+        let io = std::io::Error::new(std::io::ErrorKind::ConnectionRefused, "refused");
+        let report = Report::new(Error::Io(io)).wrap_err("unable to connect");
+        assert_eq!(
+            with_causes(report.as_ref()),
+            "unable to connect: I/O error: refused"
+        );
+    }
+}
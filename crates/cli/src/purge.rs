@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde_json::json;
+use simple_eyre::eyre::{bail, Report};
+
+use bsc::{
+    Beanstalk, DeleteResponse, Id, Job, PeekResponse, ReserveResponse, State, StatsJob,
+    StatsJobResponse,
+};
+
+use crate::consume::watch_only;
+use crate::output::Printer;
+
+/// The states of the jobs that can be purged or exported, reserved ones belonging to
+/// their worker.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum JobState {
+    Ready,
+    Delayed,
+    Buried,
+}
+
+impl JobState {
+    /// The state of a job as given by `stats-job`, `None` when reserved.
+    pub fn of(state: State) -> Option<Self> {
+        match state {
+            State::Ready => Some(JobState::Ready),
+            State::Delayed => Some(JobState::Delayed),
+            State::Buried => Some(JobState::Buried),
+            State::Reserved => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobState::Ready => "ready",
+            JobState::Delayed => "delayed",
+            JobState::Buried => "buried",
+        }
+    }
+}
+
+/// Runs `bsc purge`: deletes the jobs of the used tube in the given `states`, only those older
+/// than `older_than` if given, and at most `limit` of them, then prints how many were
+/// deleted in each state.
+///
+/// The jobs are found with `peek-<state>` and deleted one by one. The ready jobs are
+/// ordered by priority rather than age, so with `older_than` they are reserved instead,
+/// the younger ones being held until the end and then released. The buried jobs are
+/// ordered by age, so the purge stops at the first younger one. The delayed jobs are
+/// ordered by the time left before they are ready, and cannot be filtered by age.
+pub fn purge(
+    bsc: &mut Beanstalk,
+    states: &[JobState],
+    older_than: Option<Duration>,
+    limit: Option<usize>,
+    out: &Printer,
+) -> Result<(), Report> {
+    if older_than.is_some() && states.contains(&JobState::Delayed) {
+        bail!("--older-than does not apply to delayed jobs, which beanstalkd cannot list by age");
+    }
+    let tube = bsc.used_tube().to_string();
+    let mut left = limit.unwrap_or(usize::MAX);
+    let mut counts = Vec::new();
+    for &state in states {
+        let deleted = match (state, older_than) {
+            (JobState::Ready, Some(age)) => purge_ready_older(bsc, &tube, age, left)?,
+            (state, older_than) => purge_front(bsc, state, older_than, left)?,
+        };
+        left -= deleted;
+        counts.push((state, deleted));
+    }
+
+    let total: usize = counts.iter().map(|(_, deleted)| deleted).sum();
+    let details: Vec<String> = counts
+        .iter()
+        .map(|(state, deleted)| format!("{deleted} {}", state.as_str()))
+        .collect();
+    let deleted: serde_json::Map<_, _> = counts
+        .iter()
+        .map(|(state, deleted)| (state.as_str().to_string(), json!(deleted)))
+        .collect();
+    out.emit(
+        format_args!("DELETED {total} ({})", details.join(", ")),
+        json!({ "tube": tube, "deleted": deleted, "total": total }),
+    )
+}
+
+/// Deletes the job at the front of the `state` queue of the used tube, until it is
+/// empty, `limit` jobs are deleted, or the front job is younger than `older_than`.
+fn purge_front(
+    bsc: &mut Beanstalk,
+    state: JobState,
+    older_than: Option<Duration>,
+    limit: usize,
+) -> Result<usize, Report> {
+    let mut deleted = 0;
+    while deleted < limit {
+        let res = match state {
+            JobState::Ready => bsc.peek_ready()?,
+            JobState::Delayed => bsc.peek_delayed()?,
+            JobState::Buried => bsc.peek_buried()?,
+        };
+        let PeekResponse::Found { id, .. } = res else {
+            break;
+        };
+        if let Some(older_than) = older_than {
+            match stats(bsc, id)? {
+                Some(job) if job.age < older_than => break,
+                Some(_) => {}
+                // gone since it was peeked
+                None => continue,
+            }
+        }
+        // a job reserved by a worker since it was peeked is left alone
+        if let DeleteResponse::Deleted = bsc.delete(id)? {
+            deleted += 1;
+        }
+    }
+    Ok(deleted)
+}
+
+/// Reserves the ready jobs of `tube`, deleting those older than `older_than` until
+/// `limit` jobs are deleted. The other ones are released once done.
+fn purge_ready_older(
+    bsc: &mut Beanstalk,
+    tube: &str,
+    older_than: Duration,
+    limit: usize,
+) -> Result<usize, Report> {
+    watch_only(bsc, tube)?;
+    let mut held: Vec<(Id, u32)> = Vec::new();
+    let mut deleted = 0;
+    while deleted < limit {
+        let Some(job) = reserve_next(bsc, &held)? else {
+            break;
+        };
+        // a held job comes back once its TTR elapses, every job has been seen
+        if held.iter().any(|(id, _)| *id == job.id) {
+            break;
+        }
+        match stats(bsc, job.id)? {
+            Some(stats) if stats.age < older_than => held.push((job.id, stats.pri)),
+            Some(_) => {
+                bsc.delete(job.id)?;
+                deleted += 1;
+            }
+            None => {}
+        }
+    }
+    for (id, pri) in held {
+        bsc.release(id, pri, Duration::ZERO)?;
+    }
+    Ok(deleted)
+}
+
+/// Reserves the next ready job of the watched tubes, `None` when there is none left.
+///
+/// The jobs `held` until the end get `DEADLINE_SOON` once their TTR is nearly over:
+/// they are touched and the reserve is sent again, rather than stopping there.
+pub fn reserve_next(bsc: &mut Beanstalk, held: &[(Id, u32)]) -> Result<Option<Job>, Report> {
+    loop {
+        match bsc.reserve(Some(Duration::ZERO))? {
+            ReserveResponse::Reserved { id, data } => return Ok(Some(Job { id, data })),
+            ReserveResponse::TimedOut => return Ok(None),
+            // not one of ours, nothing to touch
+            ReserveResponse::DeadlineSoon if held.is_empty() => return Ok(None),
+            ReserveResponse::DeadlineSoon => {
+                for &(id, _) in held {
+                    bsc.touch(id)?;
+                }
+            }
+        }
+    }
+}
+
+/// The stats of the job `id`, `None` when it is gone.
+pub fn stats(bsc: &mut Beanstalk, id: Id) -> Result<Option<StatsJob>, Report> {
+    match bsc.stats_job(id)? {
+        StatsJobResponse::Ok(job) => Ok(Some(job)),
+        StatsJobResponse::NotFound => Ok(None),
+    }
+}
@@ -0,0 +1,124 @@
+use std::io::Write;
+use std::net::{TcpStream, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use simple_eyre::eyre::{Report, WrapErr};
+
+use bsc::Beanstalk;
+
+/// The largest StatsD datagram sent, to stay below the usual MTU.
+const MAX_DATAGRAM: usize = 1432;
+
+/// Where the metrics go.
+pub struct Sinks {
+    /// A StatsD server, as `host:port`, sent gauges over UDP.
+    pub statsd: Option<String>,
+    /// A Graphite server, as `host:port`, sent the plaintext protocol over TCP.
+    pub graphite: Option<String>,
+    /// Prepended to every metric name, e.g. "beanstalk.".
+    pub prefix: String,
+}
+
+/// Runs `bsc push-stats`: pushes the numeric global stats, and those of every tube as
+/// `tubes.<tube>.<field>`, every `interval`, until interrupted. A sink failing is
+/// reported on stderr, and tried again on the next interval.
+pub fn push_stats(bsc: &mut Beanstalk, sinks: &Sinks, interval: Duration) -> Result<(), Report> {
+    let udp = match &sinks.statsd {
+        Some(_) => Some(UdpSocket::bind("0.0.0.0:0").wrap_err("unable to open a UDP socket")?),
+        None => None,
+    };
+    loop {
+        let start = Instant::now();
+        let metrics = collect(bsc, &sinks.prefix)?;
+        if let (Some(addr), Some(udp)) = (&sinks.statsd, &udp) {
+            if let Err(err) = send_statsd(udp, addr, &metrics) {
+                eprintln!("unable to push to StatsD at {addr}: {err}");
+            }
+        }
+        if let Some(addr) = &sinks.graphite {
+            if let Err(err) = send_graphite(addr, &metrics) {
+                eprintln!("unable to push to Graphite at {addr}: {err}");
+            }
+        }
+        thread::sleep(interval.saturating_sub(start.elapsed()));
+    }
+}
+
+/// The numeric stats of the server and of its tubes, as metric names and values.
+fn collect(bsc: &mut Beanstalk, prefix: &str) -> Result<Vec<(String, f64)>, Report> {
+    let mut metrics = Vec::new();
+    numbers(serde_yaml::to_value(bsc.stats()?)?, prefix, &mut metrics);
+    for stats in bsc.stats_all_tubes()? {
+        let prefix = format!("{prefix}tubes.{}.", sanitize(&stats.name));
+        numbers(serde_yaml::to_value(stats)?, &prefix, &mut metrics);
+    }
+    Ok(metrics)
+}
+
+/// Adds the numeric fields of `stats` to `metrics`, durations in seconds.
+fn numbers(stats: serde_yaml::Value, prefix: &str, metrics: &mut Vec<(String, f64)>) {
+    let serde_yaml::Value::Mapping(fields) = stats else {
+        return;
+    };
+    for (name, value) in fields {
+        let (Some(name), Some(value)) = (name.as_str(), number(&value)) else {
+            continue;
+        };
+        metrics.push((format!("{prefix}{}", sanitize(name)), value));
+    }
+}
+
+fn number(value: &serde_yaml::Value) -> Option<f64> {
+    match value {
+        serde_yaml::Value::Number(n) => n.as_f64(),
+        serde_yaml::Value::Bool(b) => Some(f64::from(u8::from(*b))),
+        serde_yaml::Value::Mapping(map) => map.get("secs").and_then(number),
+        _ => None,
+    }
+}
+
+/// A metric name part, without the characters StatsD and Graphite give a meaning to.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '-' | '.' | ':' | '|' | '@' | ' ' | '/' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Sends `metrics` as gauges, in as few datagrams as possible.
+fn send_statsd(udp: &UdpSocket, addr: &str, metrics: &[(String, f64)]) -> std::io::Result<()> {
+    let mut datagram = String::new();
+    for (name, value) in metrics {
+        let line = format!("{name}:{value}|g");
+        if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_DATAGRAM {
+            udp.send_to(datagram.as_bytes(), addr)?;
+            datagram.clear();
+        }
+        if !datagram.is_empty() {
+            datagram.push('\n');
+        }
+        datagram.push_str(&line);
+    }
+    if !datagram.is_empty() {
+        udp.send_to(datagram.as_bytes(), addr)?;
+    }
+    Ok(())
+}
+
+/// Sends `metrics` with the plaintext protocol, on a new connection each time so that
+/// a restarted Graphite is picked up.
+fn send_graphite(addr: &str, metrics: &[(String, f64)]) -> std::io::Result<()> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut lines = String::new();
+    for (name, value) in metrics {
+        lines.push_str(&format!("{name} {value} {now}\n"));
+    }
+    let mut conn = TcpStream::connect(addr)?;
+    conn.write_all(lines.as_bytes())
+}
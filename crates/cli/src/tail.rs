@@ -0,0 +1,104 @@
+use std::thread;
+use std::time::Duration;
+
+use serde_json::json;
+use simple_eyre::eyre::Report;
+
+use bsc::{Beanstalk, Id, PeekResponse, ScanOptions, StatsJob, StatsTubeResponse};
+
+use crate::output::Printer;
+
+/// Runs `bsc tail`: shows the jobs put into `tube` from now on, every `interval`, with
+/// a preview of the first `preview` bytes of their body.
+///
+/// No job is reserved, so the consumers of the tube are not disturbed: the tube's
+/// `total-jobs` tells whether jobs were put since the previous poll, and the new ids,
+/// allocated in increasing order across the server, are then probed with `peek`. A job
+/// deleted before the poll cannot be shown, only counted.
+pub fn tail(
+    bsc: &mut Beanstalk,
+    tube: &str,
+    interval: Duration,
+    preview: usize,
+    out: &Printer,
+) -> Result<(), Report> {
+    // the jobs already there are not shown
+    let mut last_id = Id::from(bsc.stats()?.total_jobs);
+    let mut last_total = total_jobs(bsc, tube)?;
+    if !out.quiet {
+        eprintln!("waiting for jobs put into {tube:?}, bodies are previews of their first {preview} bytes");
+    }
+    loop {
+        thread::sleep(interval);
+        let total = total_jobs(bsc, tube)?;
+        if total == last_total {
+            continue;
+        }
+        let newest = Id::from(bsc.stats()?.total_jobs);
+        let mut jobs = Vec::new();
+        let opts = ScanOptions::default().stats(true);
+        bsc.scan_jobs(last_id + 1..=newest, opts, |_, res, stats| {
+            if let (PeekResponse::Found { data, .. }, Some(stats)) = (res, stats) {
+                if stats.tube == tube {
+                    jobs.push((stats, data));
+                }
+            }
+        })?;
+
+        // the counter restarts when the tube is dropped and created again
+        let put = match total > last_total {
+            true => total - last_total,
+            false => total,
+        };
+        let gone = (put as usize).saturating_sub(jobs.len());
+        for (stats, data) in jobs {
+            show(out, &stats, data.into_bytes()?, preview)?;
+        }
+        if gone > 0 && !out.quiet {
+            eprintln!("{gone} jobs put into {tube:?} were gone before they could be shown");
+        }
+        last_id = newest;
+        last_total = total;
+    }
+}
+
+/// The number of jobs ever put into `tube`, 0 when it does not exist (yet).
+fn total_jobs(bsc: &mut Beanstalk, tube: &str) -> Result<u64, Report> {
+    match bsc.stats_tube(tube)? {
+        StatsTubeResponse::Ok(stats) => Ok(stats.total_jobs),
+        StatsTubeResponse::NotFound => Ok(0),
+    }
+}
+
+/// Prints a job found by `tail`, its body cut to `preview` bytes.
+fn show(out: &Printer, job: &StatsJob, body: Vec<u8>, preview: usize) -> Result<(), Report> {
+    let truncated = body.len() > preview;
+    let preview = String::from_utf8_lossy(&body[..body.len().min(preview)]);
+    // control characters would garble the line
+    let shown: String = preview
+        .chars()
+        .flat_map(|c| match c.is_control() {
+            true => c.escape_default().collect::<Vec<_>>(),
+            false => vec![c],
+        })
+        .collect();
+    let ellipsis = if truncated { "…" } else { "" };
+    let state = json!(job.state);
+    let text = format!(
+        "{} {} {} bytes, {}, preview: {shown}{ellipsis}",
+        job.id,
+        job.tube,
+        body.len(),
+        state.as_str().unwrap_or_default(),
+    );
+    let doc = json!({
+        "id": job.id,
+        "tube": job.tube,
+        "state": state,
+        "pri": job.pri,
+        "size": body.len(),
+        "preview": preview,
+        "truncated": truncated,
+    });
+    out.emit(text, doc)
+}
@@ -0,0 +1,426 @@
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor};
+use crossterm::{cursor, queue, terminal};
+use simple_eyre::eyre::Report;
+
+use bsc::{Beanstalk, PauseTubeResponse, Stats, StatsTube};
+
+/// The columns of the tube table, in the order they are shown and sorted by.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Column {
+    Name,
+    Ready,
+    Reserved,
+    Delayed,
+    Buried,
+    Waiting,
+    PutRate,
+    Paused,
+}
+
+const COLUMNS: [Column; 8] = [
+    Column::Name,
+    Column::Ready,
+    Column::Reserved,
+    Column::Delayed,
+    Column::Buried,
+    Column::Waiting,
+    Column::PutRate,
+    Column::Paused,
+];
+
+impl Column {
+    fn title(self) -> &'static str {
+        match self {
+            Column::Name => "TUBE",
+            Column::Ready => "READY",
+            Column::Reserved => "RESERVED",
+            Column::Delayed => "DELAYED",
+            Column::Buried => "BURIED",
+            Column::Waiting => "WAITING",
+            Column::PutRate => "PUT/S",
+            Column::Paused => "PAUSED",
+        }
+    }
+}
+
+/// A tube as shown in the table.
+struct Row {
+    stats: StatsTube,
+    /// The jobs put per second since the previous refresh.
+    put_rate: f64,
+}
+
+impl Row {
+    fn cell(&self, column: Column) -> String {
+        let stats = &self.stats;
+        match column {
+            Column::Name => stats.name.clone(),
+            Column::Ready => stats.current_jobs_ready.to_string(),
+            Column::Reserved => stats.current_jobs_reserved.to_string(),
+            Column::Delayed => stats.current_jobs_delayed.to_string(),
+            Column::Buried => stats.current_jobs_buried.to_string(),
+            Column::Waiting => stats.current_waiting.to_string(),
+            Column::PutRate => format!("{:.1}", self.put_rate),
+            Column::Paused => match stats.pause_time_left.as_secs() {
+                0 => "-".to_string(),
+                secs => format!("{secs}s"),
+            },
+        }
+    }
+
+    fn cmp(&self, other: &Row, column: Column) -> std::cmp::Ordering {
+        let (a, b) = (&self.stats, &other.stats);
+        match column {
+            Column::Name => a.name.cmp(&b.name),
+            Column::Ready => a.current_jobs_ready.cmp(&b.current_jobs_ready),
+            Column::Reserved => a.current_jobs_reserved.cmp(&b.current_jobs_reserved),
+            Column::Delayed => a.current_jobs_delayed.cmp(&b.current_jobs_delayed),
+            Column::Buried => a.current_jobs_buried.cmp(&b.current_jobs_buried),
+            Column::Waiting => a.current_waiting.cmp(&b.current_waiting),
+            Column::PutRate => self.put_rate.total_cmp(&other.put_rate),
+            Column::Paused => a.pause_time_left.cmp(&b.pause_time_left),
+        }
+    }
+}
+
+/// What the view shows, and what it remembers from the previous refresh to compute
+/// rates.
+struct View {
+    color: bool,
+    /// How long `p` pauses the selected tube.
+    pause: Duration,
+    stats: Option<Stats>,
+    rows: Vec<Row>,
+    /// Global rates, per second: put, reserve and delete.
+    rates: [f64; 3],
+    sort: Column,
+    descending: bool,
+    /// The name of the selected tube, so that it stays selected when the rows move.
+    selected: Option<String>,
+    /// The outcome of the last action, or the question to confirm a kick.
+    message: String,
+    confirm_kick: Option<String>,
+    /// When the previous refresh happened, with its put, reserve and delete counts,
+    /// and the total jobs of each tube.
+    previous: Option<(Instant, [u64; 3], HashMap<String, u64>)>,
+}
+
+/// Runs `bsc top`: a live view of the global stats and of the tubes, refreshed every
+/// `interval`, until `q` is pressed.
+pub fn top(
+    bsc: &mut Beanstalk,
+    interval: Duration,
+    pause: Duration,
+    color: bool,
+) -> Result<(), Report> {
+    let mut view = View {
+        color,
+        pause,
+        stats: None,
+        rows: Vec::new(),
+        rates: [0.0; 3],
+        sort: Column::Ready,
+        descending: true,
+        selected: None,
+        message: String::new(),
+        confirm_kick: None,
+        previous: None,
+    };
+    let _screen = Screen::enter()?;
+    let mut out = BufWriter::new(io::stdout());
+    loop {
+        view.refresh(bsc)?;
+        view.draw(&mut out)?;
+        let next = Instant::now() + interval;
+        loop {
+            let timeout = next.saturating_duration_since(Instant::now());
+            if timeout.is_zero() || !event::poll(timeout)? {
+                break;
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if ctrl_c || key.code == KeyCode::Char('q') || key.code == KeyCode::Esc {
+                return Ok(());
+            }
+            if view.handle(bsc, key.code)? {
+                // an action changed the server, show its effect right away
+                break;
+            }
+            view.draw(&mut out)?;
+        }
+    }
+}
+
+impl View {
+    /// Fetches the stats of the server and of every tube.
+    fn refresh(&mut self, bsc: &mut Beanstalk) -> Result<(), Report> {
+        let now = Instant::now();
+        let stats = bsc.stats()?;
+        let mut rows: Vec<Row> = (bsc.stats_all_tubes()?.into_iter())
+            .map(|stats| Row {
+                stats,
+                put_rate: 0.0,
+            })
+            .collect();
+
+        let counts = [stats.cmd_put, stats.cmd_reserve, stats.cmd_delete];
+        if let Some((then, before, totals)) = &self.previous {
+            let secs = now.duration_since(*then).as_secs_f64();
+            let rate = |now: u64, before: u64| now.saturating_sub(before) as f64 / secs;
+            self.rates = std::array::from_fn(|i| rate(counts[i], before[i]));
+            for row in &mut rows {
+                if let Some(&total) = totals.get(&row.stats.name) {
+                    row.put_rate = rate(row.stats.total_jobs, total);
+                }
+            }
+        }
+        let totals = rows
+            .iter()
+            .map(|row| (row.stats.name.clone(), row.stats.total_jobs))
+            .collect();
+        self.previous = Some((now, counts, totals));
+        self.stats = Some(stats);
+        self.rows = rows;
+        self.sort_rows();
+        Ok(())
+    }
+
+    fn sort_rows(&mut self) {
+        let (sort, descending) = (self.sort, self.descending);
+        self.rows.sort_by(|a, b| {
+            let order = a.cmp(b, sort).then_with(|| a.stats.name.cmp(&b.stats.name));
+            if descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+        let selected = self.selected.as_ref();
+        if !self
+            .rows
+            .iter()
+            .any(|row| Some(&row.stats.name) == selected)
+        {
+            self.selected = self.rows.first().map(|row| row.stats.name.clone());
+        }
+    }
+
+    fn selected_index(&self) -> usize {
+        let selected = self.selected.as_ref();
+        let index = self
+            .rows
+            .iter()
+            .position(|row| Some(&row.stats.name) == selected);
+        index.unwrap_or(0)
+    }
+
+    /// Handles a key press, returns whether the server has been changed.
+    fn handle(&mut self, bsc: &mut Beanstalk, key: KeyCode) -> Result<bool, Report> {
+        self.message.clear();
+        if let Some(tube) = self.confirm_kick.take() {
+            if key != KeyCode::Char('y') {
+                self.message = "kick cancelled".to_string();
+                return Ok(false);
+            }
+            bsc.use_(&tube)?;
+            let n = bsc.kick(u32::MAX)?;
+            self.message = format!("kicked {n} jobs of {tube}");
+            return Ok(true);
+        }
+        let index = self.selected_index();
+        match key {
+            KeyCode::Up => self.select(index.saturating_sub(1)),
+            KeyCode::Down => self.select(index + 1),
+            KeyCode::Tab | KeyCode::Right => self.sort_by(1),
+            KeyCode::BackTab | KeyCode::Left => self.sort_by(COLUMNS.len() - 1),
+            KeyCode::Char('r') => {
+                self.descending = !self.descending;
+                self.sort_rows();
+            }
+            KeyCode::Char('p') | KeyCode::Char('u') => {
+                let Some(tube) = self.selected.clone() else {
+                    return Ok(false);
+                };
+                let delay = match key {
+                    KeyCode::Char('p') => self.pause,
+                    _ => Duration::ZERO,
+                };
+                self.message = match bsc.pause_tube(&tube, delay)? {
+                    PauseTubeResponse::Paused if delay.is_zero() => format!("unpaused {tube}"),
+                    PauseTubeResponse::Paused => format!("paused {tube} for {delay:?}"),
+                    res => format!("pause-tube {tube}: {res}"),
+                };
+                return Ok(true);
+            }
+            KeyCode::Char('k') => {
+                if let Some(tube) = self.selected.clone() {
+                    self.message = format!("kick the buried, or delayed, jobs of {tube}? (y/n)");
+                    self.confirm_kick = Some(tube);
+                }
+            }
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    fn select(&mut self, index: usize) {
+        if let Some(row) = self.rows.get(index) {
+            self.selected = Some(row.stats.name.clone());
+        }
+    }
+
+    /// Sorts by the column `step` columns to the right, wrapping around.
+    fn sort_by(&mut self, step: usize) {
+        let current = COLUMNS.iter().position(|&column| column == self.sort);
+        self.sort = COLUMNS[(current.unwrap_or(0) + step) % COLUMNS.len()];
+        // names read better in alphabetical order, counts from the largest
+        self.descending = self.sort != Column::Name;
+        self.sort_rows();
+    }
+
+    fn draw(&self, out: &mut impl Write) -> Result<(), Report> {
+        let (width, height) = terminal::size()?;
+        let (width, height) = (usize::from(width), usize::from(height));
+        // the lines, with their color and attribute if any
+        let mut lines: Vec<(String, Option<Color>, Option<Attribute>)> = Vec::new();
+
+        if let Some(stats) = &self.stats {
+            let draining = if stats.draining { "  DRAINING" } else { "" };
+            lines.push((
+                format!(
+                    "{}  beanstalkd {}  up {:?}{draining}",
+                    stats.hostname, stats.version, stats.uptime
+                ),
+                stats.draining.then_some(Color::Red),
+                None,
+            ));
+            lines.push((
+                format!(
+                    "jobs: {} ready, {} urgent, {} reserved, {} delayed, {} buried",
+                    stats.current_jobs_ready,
+                    stats.current_jobs_urgent,
+                    stats.current_jobs_reserved,
+                    stats.current_jobs_delayed,
+                    stats.current_jobs_buried
+                ),
+                None,
+                None,
+            ));
+            lines.push((
+                format!(
+                    "conns: {} ({} producers, {} workers, {} waiting)",
+                    stats.current_connections,
+                    stats.current_producers,
+                    stats.current_workers,
+                    stats.current_waiting
+                ),
+                None,
+                None,
+            ));
+            let [put, reserve, delete] = self.rates;
+            lines.push((
+                format!("rates: {put:.1} put/s, {reserve:.1} reserve/s, {delete:.1} delete/s"),
+                None,
+                None,
+            ));
+            lines.push((String::new(), None, None));
+        }
+
+        // the table, the tube names being as wide as the longest one
+        let name_width = self
+            .rows
+            .iter()
+            .map(|row| row.stats.name.len())
+            .chain([Column::Name.title().len() + 2])
+            .max()
+            .unwrap_or(0);
+        let table_line = |cells: Vec<String>| {
+            let mut line = format!("{:<name_width$}", cells[0]);
+            for cell in &cells[1..] {
+                line.push_str(&format!("  {cell:>9}"));
+            }
+            line
+        };
+        let header = COLUMNS.iter().map(|&column| {
+            let arrow = match (column == self.sort, self.descending) {
+                (false, _) => "",
+                (true, true) => "▼",
+                (true, false) => "▲",
+            };
+            format!("{}{arrow}", column.title())
+        });
+        lines.push((table_line(header.collect()), None, Some(Attribute::Bold)));
+
+        // keep the selected row in sight
+        let rows_height = height.saturating_sub(lines.len() + 2).max(1);
+        let skip = (self.selected_index() + 1).saturating_sub(rows_height);
+        for row in self.rows.iter().skip(skip).take(rows_height) {
+            let cells = COLUMNS.iter().map(|&column| row.cell(column)).collect();
+            let highlight = match &row.stats {
+                stats if stats.current_jobs_buried > 0 => Some(Color::Red),
+                stats if stats.current_jobs_urgent > 0 => Some(Color::Yellow),
+                _ => None,
+            };
+            let selected = Some(&row.stats.name) == self.selected.as_ref();
+            let attribute = selected.then_some(Attribute::Reverse);
+            lines.push((table_line(cells), highlight, attribute));
+        }
+
+        queue!(out, terminal::Clear(terminal::ClearType::All))?;
+        for (n, (line, highlight, attribute)) in lines.iter().enumerate() {
+            queue!(out, cursor::MoveTo(0, n as u16))?;
+            if let (Some(color), true) = (highlight, self.color) {
+                queue!(out, SetForegroundColor(*color))?;
+            }
+            if let Some(attribute) = attribute {
+                queue!(out, SetAttribute(*attribute))?;
+            }
+            let line: String = line.chars().take(width).collect();
+            queue!(out, Print(line), SetAttribute(Attribute::Reset), ResetColor)?;
+        }
+        let footer = match self.message.as_str() {
+            "" => "q quit  ↑↓ select  ←→ sort  r reverse  p pause  u unpause  k kick",
+            message => message,
+        };
+        let footer: String = footer.chars().take(width).collect();
+        queue!(
+            out,
+            cursor::MoveTo(0, height.saturating_sub(1) as u16),
+            SetAttribute(Attribute::Dim),
+            Print(footer),
+            SetAttribute(Attribute::Reset)
+        )?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// The alternate screen in raw mode, restored when dropped, even on error.
+struct Screen;
+
+impl Screen {
+    fn enter() -> Result<Self, Report> {
+        terminal::enable_raw_mode()?;
+        let screen = Screen;
+        crossterm::execute!(io::stdout(), terminal::EnterAlternateScreen, cursor::Hide)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = crossterm::execute!(io::stdout(), cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
@@ -0,0 +1,175 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use simple_eyre::eyre::{Report, WrapErr};
+
+use bsc::{Body, Interceptor};
+
+use crate::output::{error_doc, with_causes};
+
+/// Logs the commands and replies to stderr, more of them as `level` grows: `-v`
+/// logs each command with its reply and latency, `-vv` adds timestamps, and `-vvv`
+/// logs every line as it is written or read, as well as the job bodies.
+pub struct Trace {
+    level: u8,
+    /// Whether the job bodies are logged, escaped, rather than their size only.
+    bodies: bool,
+}
+
+impl Trace {
+    pub fn new(level: u8, bodies: bool) -> Self {
+        Self { level, bodies }
+    }
+
+    fn body(&self, direction: char, data: Option<&[u8]>, len: u64) {
+        match data {
+            Some(data) if self.bodies => {
+                eprintln!("{} {direction} {}", timestamp(), data.escape_ascii())
+            }
+            _ => eprintln!("{} {direction} [{len} bytes]", timestamp()),
+        }
+    }
+}
+
+impl Interceptor for Trace {
+    fn before_send(&mut self, line: &mut String) {
+        if self.level >= 3 {
+            eprintln!("{} > {line}", timestamp());
+        }
+    }
+
+    fn after_receive(&mut self, cmd: &str, response: &str, elapsed: Duration) {
+        match self.level {
+            1 => eprintln!("{cmd} -> {response} ({elapsed:?})"),
+            2 => eprintln!("{} {cmd} -> {response} ({elapsed:?})", timestamp()),
+            _ => eprintln!("{} < {response} ({elapsed:?})", timestamp()),
+        }
+    }
+
+    fn body_sent(&mut self, data: &[u8]) {
+        if self.level >= 3 {
+            self.body('>', Some(data), data.len() as u64);
+        }
+    }
+
+    fn body_received(&mut self, body: &Body) {
+        if self.level >= 3 {
+            let len = match body {
+                Body::Spooled(file) => file.metadata().map_or(0, |meta| meta.len()),
+                body => body.as_bytes().map_or(0, |data| data.len() as u64),
+            };
+            self.body('<', body.as_bytes(), len);
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// One line per record, as logged by -vv
+    Text,
+    /// One JSON document per line, with the fields "ts", "level", "command", "line",
+    /// "reply" and "latency_ms", or "error" as printed by --output json
+    Json,
+}
+
+/// Appends a record per command, and the error ending the run if any, to a log file,
+/// so that long running commands can be monitored like any other service.
+pub struct LogFile {
+    file: File,
+    format: LogFormat,
+}
+
+impl LogFile {
+    pub fn open(path: &Path, format: LogFormat) -> Result<Self, Report> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("unable to open the log file {}", path.display()))?;
+        Ok(Self { file, format })
+    }
+
+    /// Another handle appending to the same file.
+    pub fn try_clone(&self) -> Result<Self, Report> {
+        let file = self.file.try_clone()?;
+        Ok(Self {
+            file,
+            format: self.format,
+        })
+    }
+
+    /// Records the error ending the run.
+    pub fn error(&mut self, report: &Report, address: &str) {
+        let record = match self.format {
+            LogFormat::Text => format!("{} error: {}", timestamp(), with_causes(report.as_ref())),
+            LogFormat::Json => {
+                let mut doc = error_doc(report, address);
+                if let Value::Object(fields) = &mut doc {
+                    fields.insert("ts".to_string(), timestamp().into());
+                    fields.insert("level".to_string(), "error".into());
+                }
+                doc.to_string()
+            }
+        };
+        self.write(record);
+    }
+
+    /// Writes `record` with a single write, so that concurrent runs logging to the
+    /// same file do not interleave.
+    fn write(&mut self, mut record: String) {
+        record.push('\n');
+        // logging must not fail the command
+        let _ = self.file.write_all(record.as_bytes());
+    }
+}
+
+impl Interceptor for LogFile {
+    fn after_receive(&mut self, cmd: &str, response: &str, elapsed: Duration) {
+        let record = match self.format {
+            LogFormat::Text => format!("{} {cmd} -> {response} ({elapsed:?})", timestamp()),
+            LogFormat::Json => json!({
+                "ts": timestamp(),
+                "level": "info",
+                "command": cmd.split(' ').next().unwrap_or_default(),
+                "line": cmd,
+                "reply": response,
+                "latency_ms": elapsed.as_micros() as f64 / 1e3,
+            })
+            .to_string(),
+        };
+        self.write(record);
+    }
+}
+
+/// The current time as RFC 3339, in UTC with milliseconds.
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+
+    // civil date from days since the epoch, see
+    // <https://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60,
+        now.subsec_millis()
+    )
+}
@@ -0,0 +1,402 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use base64::prelude::{Engine, BASE64_STANDARD};
+use serde_json::{json, Value};
+use simple_eyre::eyre::{bail, eyre, Report, WrapErr};
+
+use bsc::{
+    copy_jobs, Beanstalk, Body, Id, PeekResponse, PutResponse, ScanOptions, StatsJob, StatsTube,
+    StatsTubeResponse,
+};
+
+use crate::output::{with_causes, Printer, Progress};
+use crate::purge::{self, JobState};
+
+/// Runs `bsc move`: moves the ready jobs of the `from` tube to the `to` tube, at most
+/// `limit` of them, keeping their priority and TTR. With `dry_run`, only tells how
+/// many would be moved.
+pub fn move_jobs(
+    bsc: &mut Beanstalk,
+    from: &str,
+    to: &str,
+    limit: Option<usize>,
+    dry_run: bool,
+    out: &Printer,
+) -> Result<(), Report> {
+    // checked before a dry run too, which would report jobs that cannot move
+    if from == to {
+        bail!("cannot move the jobs of {from:?} into the same tube");
+    }
+    let ready = stats_tube(bsc, from)?.map_or(0, |stats| stats.current_jobs_ready as usize);
+    let expected = limit.map_or(ready, |limit| ready.min(limit));
+    if dry_run {
+        return out.emit(
+            format_args!("would move {expected} jobs from {from:?} to {to:?}"),
+            json!({ "from": from, "to": to, "moved": expected, "dry-run": true }),
+        );
+    }
+
+    let progress = Progress::new("moving", expected, out.quiet);
+    let moved = bsc.drain_tube(from, to, limit, |_, _| true, |moved| progress.update(moved))?;
+    progress.finish();
+    if let Some(stats) = stats_tube(bsc, from)? {
+        let left = stats.current_jobs_delayed + stats.current_jobs_buried;
+        if left > 0 && !out.quiet {
+            eprintln!("{left} delayed or buried jobs are left in {from:?}, kick them to move them");
+        }
+    }
+    out.emit(
+        format_args!("MOVED {moved}"),
+        json!({ "from": from, "to": to, "moved": moved, "dry-run": false }),
+    )
+}
+
+/// Runs `bsc migrate`: moves the ready and delayed jobs of `tubes`, or of every tube,
+/// from the `src` server to the `dst` one, at most `limit` of them. The jobs keep their
+/// tube, priority, TTR and remaining delay.
+///
+/// Each job is deleted from `src` once put into `dst`, so an interrupted migration is
+/// resumed by running it again. What was migrated, and what was left behind, is
+/// summed up per tube. The command fails when a job could not be put into `dst`.
+pub fn migrate(
+    src: &mut Beanstalk,
+    dst: &mut Beanstalk,
+    tubes: &[String],
+    limit: Option<usize>,
+    out: &Printer,
+) -> Result<(), Report> {
+    let tubes: Vec<String> = match tubes {
+        [] => src.list_tubes()?.into_iter().map(String::from).collect(),
+        tubes => tubes.to_vec(),
+    };
+    let mut left = limit;
+    let (mut copied, mut failed) = (0, 0);
+    let mut summaries = Vec::new();
+    for tube in &tubes {
+        if left == Some(0) {
+            break;
+        }
+        let Some(before) = stats_tube(src, tube)? else {
+            continue;
+        };
+        if before.current_jobs_ready + before.current_jobs_delayed == 0 {
+            continue;
+        }
+        let report = copy_jobs(src, dst, tube, left)?;
+        // jobs put since, reserved by a worker, buried, or in a paused tube
+        let remaining = stats_tube(src, tube)?.map_or(0, |stats| {
+            stats.current_jobs_ready
+                + stats.current_jobs_delayed
+                + stats.current_jobs_reserved
+                + stats.current_jobs_buried
+        });
+
+        if !out.quiet && out.raw() {
+            println!(
+                "{tube}: {} copied, {} failed, {remaining} left",
+                report.copied.len(),
+                report.failed.len()
+            );
+            for (id, err) in &report.failed {
+                println!("  job {id}: {}", with_causes(err));
+            }
+        }
+        let failures: Vec<Value> = (report.failed.iter())
+            .map(|(id, err)| json!({ "id": id, "error": with_causes(err) }))
+            .collect();
+        summaries.push(json!({
+            "tube": tube,
+            "copied": report.copied.len(),
+            "failed": failures,
+            "left": remaining,
+        }));
+        copied += report.copied.len();
+        failed += report.failed.len();
+        left = left.map(|left| left - report.copied.len());
+    }
+
+    if failed > 0 {
+        out.fail();
+    }
+    out.emit(
+        format_args!("MIGRATED {copied} jobs, {failed} failed"),
+        json!({ "tubes": summaries, "copied": copied, "failed": failed }),
+    )
+}
+
+/// Runs `bsc export`: writes the jobs of `tube` in the given `states` to `path`, or to
+/// stdout, as one JSON object per line with the body in base64, then tells how many
+/// jobs were exported.
+///
+/// beanstalkd has no command to list jobs, so every job id up to the last one is
+/// probed with `peek`, which leaves the jobs as they are. With `drain`, the jobs are
+/// rather found with `peek-<state>`, and deleted once written.
+pub fn export(
+    bsc: &mut Beanstalk,
+    tube: &str,
+    states: &[JobState],
+    path: Option<&Path>,
+    drain: bool,
+    out: &Printer,
+) -> Result<(), Report> {
+    let mut file: Box<dyn Write> = match path {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).wrap_err_with(|| format!("unable to create {path:?}"))?,
+        )),
+        None => Box::new(io::stdout().lock()),
+    };
+    let exported = match drain {
+        true => export_drain(bsc, states, &mut file)?,
+        false => export_scan(bsc, tube, states, &mut file, out.quiet)?,
+    };
+    file.flush()?;
+
+    if path.is_some() {
+        let doc = json!({ "tube": tube, "exported": exported, "drained": drain });
+        return out.emit(format_args!("EXPORTED {exported}"), doc);
+    }
+    // the jobs are on stdout
+    if !out.quiet {
+        eprintln!("exported {exported} jobs");
+    }
+    Ok(())
+}
+
+/// Writes the jobs of `tube` in `states` found by probing every job id, and returns
+/// how many were written.
+fn export_scan(
+    bsc: &mut Beanstalk,
+    tube: &str,
+    states: &[JobState],
+    file: &mut dyn Write,
+    quiet: bool,
+) -> Result<usize, Report> {
+    let last = Id::from(bsc.stats()?.total_jobs);
+    let progress = Progress::new("scanning", last as usize, quiet);
+    let mut exported = 0;
+    let mut res = Ok(());
+    bsc.scan_jobs(
+        1..=last,
+        ScanOptions::default().stats(true),
+        |id, peeked, stats| {
+            progress.update(id as usize);
+            let (PeekResponse::Found { data, .. }, Some(stats)) = (peeked, stats) else {
+                return;
+            };
+            let wanted = JobState::of(stats.state).is_some_and(|state| states.contains(&state));
+            if res.is_ok() && stats.tube == tube && wanted {
+                res = write_job(file, &stats, data);
+                exported += 1;
+            }
+        },
+    )?;
+    progress.finish();
+    res.map(|()| exported)
+}
+
+/// Writes and deletes the jobs of the used tube in `states`, and returns how many were
+/// written.
+fn export_drain(
+    bsc: &mut Beanstalk,
+    states: &[JobState],
+    file: &mut dyn Write,
+) -> Result<usize, Report> {
+    let mut exported = 0;
+    for state in states {
+        loop {
+            let peeked = match state {
+                JobState::Ready => bsc.peek_ready()?,
+                JobState::Delayed => bsc.peek_delayed()?,
+                JobState::Buried => bsc.peek_buried()?,
+            };
+            let PeekResponse::Found { id, data } = peeked else {
+                break;
+            };
+            let Some(stats) = purge::stats(bsc, id)? else {
+                continue;
+            };
+            write_job(file, &stats, data)?;
+            // written for good before the job is gone from the server
+            file.flush()?;
+            bsc.delete(id)?;
+            exported += 1;
+        }
+    }
+    Ok(exported)
+}
+
+/// Writes a job as a line of JSON, what [`import`] reads back.
+fn write_job(file: &mut dyn Write, job: &StatsJob, data: Body) -> Result<(), Report> {
+    let delay_left = match JobState::of(job.state) {
+        Some(JobState::Delayed) => job.time_left,
+        _ => Duration::ZERO,
+    };
+    let record = json!({
+        "id": job.id,
+        "tube": job.tube,
+        "state": job.state,
+        "pri": job.pri,
+        "delay-left": delay_left.as_secs(),
+        "ttr": job.ttr,
+        "body": BASE64_STANDARD.encode(data.into_bytes()?),
+    });
+    writeln!(file, "{record}")?;
+    Ok(())
+}
+
+/// How `bsc import` tells the jobs already imported.
+pub struct Duplicates<'a> {
+    /// Skip the jobs with the same tube and body as an earlier one of the input.
+    pub skip_same_body: bool,
+    /// A file listing the ids, in the input, of the jobs imported by previous runs,
+    /// which are skipped, and to which the ids of the jobs imported are added.
+    pub journal: Option<&'a Path>,
+}
+
+/// A job read from an export.
+struct Record {
+    id: Id,
+    tube: String,
+    pri: u32,
+    delay: Duration,
+    ttr: Duration,
+    body: Vec<u8>,
+}
+
+/// Runs `bsc import`: puts the jobs read from `path`, or from stdin, as written by
+/// `bsc export`, with their priority, remaining delay and TTR, into their tube or into
+/// `tube` if given. Buried jobs are put as ready ones. A job id found twice in the
+/// input, e.g. in concatenated exports, is only imported once.
+///
+/// With `dry_run`, the input is checked and the jobs are counted, but none is put.
+pub fn import(
+    bsc: &mut Beanstalk,
+    path: Option<&Path>,
+    tube: Option<&str>,
+    duplicates: &Duplicates,
+    dry_run: bool,
+    out: &Printer,
+) -> Result<(), Report> {
+    let input: Box<dyn BufRead> = match path {
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).wrap_err_with(|| format!("unable to read {path:?}"))?,
+        )),
+        None => Box::new(io::stdin().lock()),
+    };
+    let mut imported_ids = HashSet::new();
+    let mut journal = None;
+    if let Some(path) = duplicates.journal {
+        if path.exists() {
+            let ids = std::fs::read_to_string(path)
+                .wrap_err_with(|| format!("unable to read {path:?}"))?;
+            imported_ids.extend(ids.lines().filter_map(|id| id.trim().parse::<Id>().ok()));
+        }
+        if !dry_run {
+            let file = OpenOptions::new().create(true).append(true).open(path);
+            journal = Some(file.wrap_err_with(|| format!("unable to write {path:?}"))?);
+        }
+    }
+
+    let mut bodies = HashSet::new();
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    let (mut imported, mut skipped) = (0, 0);
+    for (n, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut record =
+            parse_record(&line).wrap_err_with(|| format!("invalid job at line {}", n + 1))?;
+        if let Some(tube) = tube {
+            record.tube = tube.to_string();
+        }
+        if imported_ids.contains(&record.id) {
+            skipped += 1;
+            continue;
+        }
+        if duplicates.skip_same_body {
+            let mut hasher = DefaultHasher::new();
+            (&record.tube, &record.body).hash(&mut hasher);
+            if !bodies.insert(hasher.finish()) {
+                skipped += 1;
+                continue;
+            }
+        }
+
+        if !dry_run {
+            if bsc.used_tube() != record.tube {
+                bsc.use_(&record.tube)?;
+            }
+            match bsc.put(record.pri, record.delay, record.ttr, &record.body)? {
+                PutResponse::Inserted(_) | PutResponse::Buried(_) => {}
+                res => bail!(
+                    "unable to put the job {} of line {}: {res}",
+                    record.id,
+                    n + 1
+                ),
+            }
+            if let Some(journal) = &mut journal {
+                writeln!(journal, "{}", record.id)?;
+            }
+        }
+        imported_ids.insert(record.id);
+        *counts.entry(record.tube).or_default() += 1;
+        imported += 1;
+    }
+
+    let text = match dry_run {
+        true => format!("would import {imported} jobs, {skipped} skipped as duplicates"),
+        false => format!("IMPORTED {imported} ({skipped} skipped as duplicates)"),
+    };
+    let doc = json!({
+        "imported": imported,
+        "skipped": skipped,
+        "tubes": counts,
+        "dry-run": dry_run,
+    });
+    out.emit(text, doc)
+}
+
+/// Parses a line written by [`write_job`].
+fn parse_record(line: &str) -> Result<Record, Report> {
+    let record: Value = serde_json::from_str(line)?;
+    let field = |name: &str| {
+        record
+            .get(name)
+            .ok_or_else(|| eyre!("missing field {name:?}"))
+    };
+    let number = |name: &str| {
+        field(name)?
+            .as_u64()
+            .ok_or_else(|| eyre!("field {name:?} is not a positive integer"))
+    };
+    let body = field("body")?
+        .as_str()
+        .ok_or_else(|| eyre!("field \"body\" is not a string"))?;
+    Ok(Record {
+        id: number("id")?,
+        tube: (field("tube")?.as_str())
+            .ok_or_else(|| eyre!("field \"tube\" is not a string"))?
+            .to_string(),
+        pri: u32::try_from(number("pri")?)?,
+        delay: Duration::from_secs(number("delay-left")?),
+        ttr: Duration::from_secs(number("ttr")?),
+        body: BASE64_STANDARD
+            .decode(body)
+            .wrap_err("field \"body\" is not base64")?,
+    })
+}
+
+/// The stats of `tube`, `None` when it does not exist.
+fn stats_tube(bsc: &mut Beanstalk, tube: &str) -> Result<Option<StatsTube>, Report> {
+    match bsc.stats_tube(tube)? {
+        StatsTubeResponse::Ok(stats) => Ok(Some(stats)),
+        StatsTubeResponse::NotFound => Ok(None),
+    }
+}
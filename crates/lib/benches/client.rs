@@ -0,0 +1,161 @@
+use std::io::{self, Read};
+use std::time::Duration;
+
+use bsc::*;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+/// A `stats` response as sent by beanstalkd 1.13.
+const STATS: &str = "---
+current-jobs-urgent: 12
+current-jobs-ready: 3051
+current-jobs-reserved: 42
+current-jobs-delayed: 187
+current-jobs-buried: 3
+cmd-put: 1843092
+cmd-peek: 120
+cmd-peek-ready: 51
+cmd-peek-delayed: 7
+cmd-peek-buried: 19
+cmd-reserve: 210
+cmd-reserve-with-timeout: 1839776
+cmd-delete: 1839654
+cmd-release: 412
+cmd-use: 5022
+cmd-watch: 9841
+cmd-ignore: 4920
+cmd-bury: 31
+cmd-kick: 4
+cmd-touch: 1022
+cmd-stats: 8811
+cmd-stats-job: 77
+cmd-stats-tube: 13205
+cmd-list-tubes: 2201
+cmd-list-tube-used: 0
+cmd-list-tubes-watched: 5
+cmd-pause-tube: 2
+job-timeouts: 118
+total-jobs: 1843092
+max-job-size: 65535
+current-tubes: 14
+current-connections: 37
+current-producers: 11
+current-workers: 24
+current-waiting: 19
+total-connections: 6034
+pid: 1
+version: 1.13
+rusage-utime: 1520.381042
+rusage-stime: 2871.093418
+uptime: 3888122
+binlog-oldest-index: 412
+binlog-current-index: 418
+binlog-records-migrated: 50112
+binlog-records-written: 3731055
+binlog-max-size: 10485760
+draining: false
+id: 5a7bd8c1b0e43c1f
+hostname: queue-3.internal
+os: '#1 SMP PREEMPT_DYNAMIC Debian 6.1.76-1 (2024-02-01)'
+platform: x86_64
+";
+
+/// Sends the same bytes over and over, so that the client reads the same responses
+/// for as long as it is benched, without any server.
+struct Repeat {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+impl Read for Repeat {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos = (self.pos + n) % self.data.len();
+        Ok(n)
+    }
+}
+
+/// A client reading `responses` endlessly, and discarding the commands.
+fn client(responses: Vec<u8>) -> Beanstalk {
+    let reader = Repeat {
+        data: responses,
+        pos: 0,
+    };
+    Beanstalk::from_parts(reader, io::sink())
+}
+
+/// A `reserve` (or `peek` if `found`) response with a body of `size` bytes.
+fn job(size: usize, found: bool) -> Vec<u8> {
+    let reply = if found { "FOUND" } else { "RESERVED" };
+    let mut response = format!("{reply} 1 {size}\r\n").into_bytes();
+    response.extend(std::iter::repeat_n(b'x', size));
+    response.extend_from_slice(b"\r\n");
+    response
+}
+
+// jobs are peeked rather than reserved, as the client keeps track of reserved jobs
+fn parse_job(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse/job");
+    for size in [16, 4 * 1024, 64 * 1024] {
+        let mut bsc = client(job(size, true));
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| bsc.peek(1).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn parse_stats(c: &mut Criterion) {
+    let response = format!("OK {}\r\n{STATS}\r\n", STATS.len()).into_bytes();
+    let mut bsc = client(response);
+    c.bench_function("parse/stats", |b| b.iter(|| bsc.stats().unwrap()));
+}
+
+fn round_trip(c: &mut Criterion) {
+    let mut responses = b"INSERTED 1\r\n".to_vec();
+    responses.extend(job(256, false));
+    responses.extend_from_slice(b"DELETED\r\n");
+    let mut bsc = client(responses);
+    let data = [b'x'; 256];
+    let ttr = Duration::from_secs(60);
+    c.bench_function("round_trip/put_reserve_delete", |b| {
+        b.iter(|| {
+            bsc.put(0, Duration::ZERO, ttr, &data).unwrap();
+            match bsc.reserve(None).unwrap() {
+                ReserveResponse::Reserved { id, .. } => bsc.delete(id).unwrap(),
+                res => panic!("unexpected response {res:?}"),
+            }
+        })
+    });
+}
+
+fn body_buffers(c: &mut Criterion) {
+    let mut group = c.benchmark_group("body");
+    let size = 64 * 1024;
+    group.throughput(Throughput::Bytes(size as u64));
+
+    let mut bsc = client(job(size, true));
+    group.bench_function("into_bytes", |b| {
+        b.iter(|| match bsc.peek(1).unwrap() {
+            PeekResponse::Found { data, .. } => data.into_bytes().unwrap(),
+            res => panic!("unexpected response {res:?}"),
+        })
+    });
+
+    let mut bsc = client(job(size, true));
+    let mut buf = Vec::with_capacity(size);
+    group.bench_function("write_to_reused_buffer", |b| {
+        b.iter(|| match bsc.peek(1).unwrap() {
+            PeekResponse::Found { data, .. } => {
+                buf.clear();
+                data.write_to(&mut buf).unwrap()
+            }
+            res => panic!("unexpected response {res:?}"),
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse_job, parse_stats, round_trip, body_buffers);
+criterion_main!(benches);
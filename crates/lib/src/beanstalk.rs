@@ -1,36 +1,588 @@
-use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
 use std::net::{TcpStream, ToSocketAddrs};
-use std::time::Duration;
-
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Serialize;
+
+use crate::body::Body;
+use crate::cipher::{self, PayloadCipher};
+use crate::codec::{Codec, DEFAULT_DECOMPRESS_LIMIT, HEADER_LEN};
+use crate::intercept::Interceptor;
+use crate::logging::{debug, trace};
+use crate::ops::BeanstalkOps;
+use crate::options::{PutOptions, RateLimit, ScanOptions, TcpOptions, TubeConfig};
+use crate::protocol::*;
+use crate::ratelimit::TokenBucket;
+use crate::shutdown::{ShutdownHandle, ShutdownSignal, SHUTDOWN_POLL_INTERVAL};
 use crate::stats::*;
-use crate::Result;
-
-pub type Id = u32;
+use crate::transport::*;
+use crate::{Error, Result};
+
+/// A job id, beanstalkd allocates them from a 64 bits counter.
+pub type Id = u64;
+
+/// The longest response line read by default, see
+/// [`Beanstalk::max_response_line`].
+const DEFAULT_MAX_RESPONSE_LINE: usize = 4096;
+
+/// Writes a command line, formatted as with `write!`, going through the interceptors
+/// if any.
+macro_rules! send {
+    ($bsc:ident, $($arg:tt)*) => {
+        if $bsc.interceptors.is_empty() && !$bsc.strict_limits {
+            write!($bsc.writer, $($arg)*).map_err(Error::from)
+        } else {
+            let line = format!($($arg)*);
+            $bsc.send_line(line)
+        }
+    };
+}
 
 pub struct Beanstalk {
-    reader: BufReader<TcpStream>,
-    writer: BufWriter<TcpStream>,
+    reader: BufReader<Box<dyn Read + Send>>,
+    writer: BufWriter<Box<dyn Write + Send>>,
     buf: String,
+    spool_threshold: Option<u64>,
+    compression: Option<(Codec, usize)>,
+    decompress_limit: Option<u64>,
+    cipher: Option<Box<dyn PayloadCipher>>,
+    check_responses: bool,
+    namespace: String,
+    auto_deadline_soon: bool,
+    strict_limits: bool,
+    /// Jobs reserved by this connection and not yet deleted, released or buried.
+    held: Vec<Id>,
+    /// The connection, when it is a TCP one.
+    tcp: Option<TcpStream>,
+    tcp_options: Option<TcpOptions>,
+    /// The address to dial, for lazy connections and reconnects.
+    addr: Option<String>,
+    /// The commands written before a lazy connection is established.
+    queued: Option<Shared<Vec<u8>>>,
+    put_limit: Option<TokenBucket>,
+    response_timeout: Option<Duration>,
+    max_response_line: usize,
+    /// Raised when a read times out, see [`TimeoutReader`], or when a response line
+    /// is too long.
+    poisoned: Arc<AtomicBool>,
+    interceptors: Vec<Box<dyn Interceptor>>,
+    /// The commands sent through the interceptors and awaiting a response, with the
+    /// time they were sent.
+    sent: VecDeque<(String, Instant)>,
+    /// The tube used and the tubes watched, as named on the server, to restore them
+    /// on reconnect.
+    used: String,
+    watched: Vec<String>,
+    server_info: Option<ServerInfo>,
 }
 
 impl Beanstalk {
     pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Self::from_tcp(TcpStream::connect(addr)?)
+    }
+
+    /// Same as [`connect`](Self::connect), but gives up on each address `addr`
+    /// resolves to after `timeout`, e.g. when the host is unreachable and the packets
+    /// are dropped. The timeout must not be zero.
+    pub fn connect_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration) -> Result<Self> {
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(conn) => return Self::from_tcp(conn),
+                Err(err) => {
+                    debug!("unable to connect to {addr}: {err}");
+                    last_err = Some(err);
+                }
+            }
+        }
+        Err(match last_err {
+            Some(err) => err.into(),
+            None => "the address resolves to no socket address".into(),
+        })
+    }
+
+    fn from_tcp(conn: TcpStream) -> Result<Self> {
+        let mut bsc = Self::from_parts(conn.try_clone()?, conn.try_clone()?);
+        bsc.addr = Some(conn.peer_addr()?.to_string());
+        debug!("connected to {}", conn.peer_addr()?);
+        bsc.tcp = Some(conn);
+        Ok(bsc)
+    }
+
+    /// Same as [`connect`](Self::connect), but every byte sent and received is also
+    /// written to the file at `path`. The session can then be reproduced without a
+    /// server with [`replay`](Self::replay), e.g. to turn a protocol issue seen in
+    /// production into a test fixture.
+    pub fn connect_recording<A: ToSocketAddrs, P: AsRef<Path>>(addr: A, path: P) -> Result<Self> {
         let conn = TcpStream::connect(addr)?;
-        let read = BufReader::new(conn.try_clone()?);
-        let write = BufWriter::new(conn);
+        let recorder = Recorder::create(path.as_ref())?;
+        let reader = RecordingReader {
+            inner: conn.try_clone()?,
+            recorder: recorder.clone(),
+        };
+        let writer = RecordingWriter {
+            inner: conn.try_clone()?,
+            recorder,
+        };
+        let mut bsc = Self::from_parts(reader, writer);
+        bsc.addr = Some(conn.peer_addr()?.to_string());
+        debug!(
+            "connected to {}, recording to {:?}",
+            conn.peer_addr()?,
+            path.as_ref()
+        );
+        bsc.tcp = Some(conn);
+        Ok(bsc)
+    }
 
-        Ok(Self {
-            reader: read,
-            writer: write,
+    /// Replays a session recorded with [`connect_recording`](Self::connect_recording),
+    /// without any network: the recorded responses are returned in order.
+    ///
+    /// The commands must be the ones that were recorded, in the same order. Sending a
+    /// different command, or more commands than recorded, is an error.
+    pub fn replay<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let (outbound, inbound) = load(path.as_ref())?;
+        let reader = ReplayReader {
+            inbound: io::Cursor::new(inbound),
+        };
+        let writer = ReplayWriter { outbound, pos: 0 };
+        Ok(Self::from_parts(reader, writer))
+    }
+
+    /// Creates a client without connecting. The connection is established when the
+    /// first command is sent, so a client can be set up before the server is
+    /// reachable. `addr` (e.g. `"beanstalkd:11300"`) is resolved when connecting.
+    ///
+    /// When connecting fails, the command fails and the next command tries again.
+    pub fn lazy(addr: impl Into<String>) -> Self {
+        let queued = Shared::new(Vec::new());
+        let mut bsc = Self::from_parts(io::empty(), queued.clone());
+        bsc.addr = Some(addr.into());
+        bsc.queued = Some(queued);
+        bsc
+    }
+
+    /// Creates a client talking to beanstalkd through the two halves of a connection,
+    /// e.g. the clones of a `UnixStream`. Both halves are buffered by the client.
+    ///
+    /// No command is sent, the connection is expected to be fresh.
+    pub fn from_parts<R, W>(reader: R, writer: W) -> Self
+    where
+        R: Read + Send + 'static,
+        W: Write + Send + 'static,
+    {
+        let poisoned = Arc::new(AtomicBool::new(false));
+        Self {
+            reader: timeout_reader(reader, &poisoned),
+            writer: BufWriter::new(Box::new(writer)),
             buf: String::new(),
-        })
+            spool_threshold: None,
+            compression: None,
+            decompress_limit: None,
+            cipher: None,
+            check_responses: false,
+            namespace: String::new(),
+            auto_deadline_soon: false,
+            strict_limits: false,
+            held: Vec::new(),
+            tcp: None,
+            tcp_options: None,
+            addr: None,
+            queued: None,
+            put_limit: None,
+            response_timeout: None,
+            max_response_line: DEFAULT_MAX_RESPONSE_LINE,
+            poisoned,
+            interceptors: Vec::new(),
+            sent: VecDeque::new(),
+            used: DEFAULT_TUBE.to_string(),
+            watched: vec![DEFAULT_TUBE.to_string()],
+            server_info: None,
+        }
+    }
+
+    /// Same as [`from_parts`](Self::from_parts), for connections that cannot be split
+    /// in two halves, like TLS streams or in-memory duplex pipes.
+    pub fn from_stream<S: Read + Write + Send + 'static>(stream: S) -> Self {
+        let stream = Shared::new(stream);
+        Self::from_parts(stream.clone(), stream)
+    }
+
+    /// Connects and then issues the `use`, `watch` and `ignore` commands described by
+    /// `config`. The commands are pipelined: they are all written at once and the
+    /// responses are read afterwards.
+    ///
+    /// Any response other than `USING` or `WATCHING` (e.g. `NOT_IGNORED` when
+    /// ignoring the last watched tube) is an error.
+    pub fn connect_with<A: ToSocketAddrs>(addr: A, config: &TubeConfig) -> Result<Self> {
+        let mut bsc = Self::connect(addr)?;
+        bsc.configure(config)?;
+        Ok(bsc)
+    }
+
+    /// Issues the `use`, `watch` and `ignore` commands described by `config`, as
+    /// [`connect_with`](Self::connect_with) does. Useful to set up the tubes once
+    /// interceptors are in place, so that they see these commands too.
+    pub fn configure(&mut self, config: &TubeConfig) -> Result<()> {
+        self.namespace = config.namespace.clone();
+
+        // request
+        if let Some(tube) = &config.use_ {
+            send!(self, "use {}{tube}\r\n", self.namespace)?;
+        }
+        for tube in &config.watch {
+            send!(self, "watch {}{tube}\r\n", self.namespace)?;
+        }
+        for tube in &config.ignore {
+            send!(self, "ignore {}{tube}\r\n", self.namespace)?;
+        }
+        self.writer.flush()?;
+
+        // responses
+        let cmds = (config.use_.iter().map(|_| "use"))
+            .chain(config.watch.iter().map(|_| "watch"))
+            .chain(config.ignore.iter().map(|_| "ignore"));
+        self.read_tube_replies(cmds)?;
+
+        if let Some(tube) = &config.use_ {
+            self.used = format!("{}{tube}", self.namespace);
+        }
+        for tube in &config.watch {
+            self.track_watch(format!("{}{tube}", self.namespace));
+        }
+        for tube in &config.ignore {
+            self.track_ignore(&format!("{}{tube}", self.namespace));
+        }
+        Ok(())
+    }
+
+    /// Reads the replies to pipelined `use`, `watch` and `ignore` commands, any reply
+    /// other than `USING` or `WATCHING` is an error.
+    fn read_tube_replies(&mut self, cmds: impl IntoIterator<Item = &'static str>) -> Result<()> {
+        for cmd in cmds {
+            self.read_response(cmd)?;
+            let input = self.buf.trim_end_matches("\r\n");
+            if !input.starts_with("USING ") && !input.starts_with("WATCHING ") {
+                return Err(unexpected(cmd, None, input));
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes the connection and connects again to the same server, then restores the
+    /// tube used and the tubes watched, so that e.g. a worker does not silently fall
+    /// back to the "default" tube after a network failure.
+    ///
+    /// Only available for TCP connections. The address given to [`lazy`](Self::lazy)
+    /// is resolved again, other clients dial the address they first connected to.
+    /// The socket options set with [`tune_tcp`](Self::tune_tcp) are applied again,
+    /// but a recording connection stops recording and previous
+    /// [`shutdown_handle`](Self::shutdown_handle)s no longer apply.
+    ///
+    /// The jobs reserved by the previous connection are released by the server.
+    pub fn reconnect(&mut self) -> Result<()> {
+        let Some(addr) = &self.addr else {
+            return Err("not a TCP connection".into());
+        };
+        if self.queued.is_some() {
+            // a lazy client that has not connected yet
+            return Ok(());
+        }
+        let conn = TcpStream::connect(addr.as_str())?;
+        debug!("reconnected to {addr}");
+        if let Some(opts) = &self.tcp_options {
+            tune(&conn, opts)?;
+        }
+        self.poisoned.store(false, Ordering::SeqCst);
+        self.reader = timeout_reader(conn.try_clone()?, &self.poisoned);
+        self.writer = BufWriter::new(Box::new(conn.try_clone()?));
+        self.tcp = Some(conn);
+        self.held.clear();
+        self.sent.clear();
+        // the server may have been upgraded in the meantime
+        self.server_info = None;
+
+        // a new connection uses and watches "default"
+        let used = std::mem::replace(&mut self.used, DEFAULT_TUBE.to_string());
+        let watched = std::mem::replace(&mut self.watched, vec![DEFAULT_TUBE.to_string()]);
+
+        // request
+        let mut cmds = Vec::new();
+        if used != DEFAULT_TUBE {
+            send!(self, "use {used}\r\n")?;
+            cmds.push("use");
+        }
+        for tube in watched.iter().filter(|tube| *tube != DEFAULT_TUBE) {
+            send!(self, "watch {tube}\r\n")?;
+            cmds.push("watch");
+        }
+        if !watched.iter().any(|tube| tube == DEFAULT_TUBE) {
+            send!(self, "ignore {DEFAULT_TUBE}\r\n")?;
+            cmds.push("ignore");
+        }
+        self.writer.flush()?;
+
+        // responses
+        self.read_tube_replies(cmds)?;
+        self.used = used;
+        self.watched = watched;
+        Ok(())
+    }
+
+    /// The tube used by this connection, as known by the client, without a round trip
+    /// to the server (see [`list_tube_used`](Self::list_tube_used)).
+    pub fn used_tube(&self) -> &str {
+        strip_namespace(&self.namespace, &self.used)
+    }
+
+    /// The tubes watched by this connection, as known by the client, without a round
+    /// trip to the server (see [`list_tube_watched`](Self::list_tube_watched)).
+    pub fn watched_tubes(&self) -> Vec<&str> {
+        (self.watched.iter())
+            .map(|tube| strip_namespace(&self.namespace, tube))
+            .collect()
+    }
+
+    /// The ids of the jobs reserved by this connection and not deleted, released or
+    /// buried since, oldest first. Jobs the server released on TTR expiry are only
+    /// noticed when touched.
+    pub fn last_reserved_ids(&self) -> &[Id] {
+        &self.held
+    }
+
+    /// Returns a handle closing this connection from another thread, which interrupts
+    /// a blocking [`reserve`](Self::reserve) right away, e.g. to stop a worker on
+    /// SIGTERM. Only available for TCP connections made with
+    /// [`connect`](Self::connect) and the like.
+    pub fn shutdown_handle(&self) -> Result<Option<ShutdownHandle>> {
+        match &self.tcp {
+            Some(tcp) => Ok(Some(ShutdownHandle(tcp.try_clone()?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Applies the socket options of `opts` to the connection (see [`TcpOptions`]).
+    /// Only available for TCP connections made with [`connect`](Self::connect) and
+    /// the like. The options of a [`lazy`](Self::lazy) client are applied once it
+    /// connects.
+    pub fn tune_tcp(&mut self, opts: &TcpOptions) -> Result<()> {
+        match &self.tcp {
+            Some(tcp) => tune(tcp, opts)?,
+            None if self.queued.is_some() => {}
+            None => return Err("not a TCP connection".into()),
+        }
+        self.tcp_options = Some(*opts);
+        Ok(())
+    }
+
+    /// Establishes the connection of a [`lazy`](Self::lazy) client, if not done yet,
+    /// and sends the commands written so far.
+    fn connect_lazily(&mut self) -> Result<()> {
+        let (Some(queued), Some(addr)) = (self.queued.clone(), self.addr.clone()) else {
+            return Ok(());
+        };
+        match self.send_queued(&addr, &queued) {
+            Ok(()) => {
+                debug!("connected to {addr}");
+                self.queued = None;
+                Ok(())
+            }
+            Err(err) => {
+                debug!("unable to connect to {addr}: {err}");
+                // drop the failed command, and try again on the next one
+                queued.lock().clear();
+                self.sent.clear();
+                Err(err)
+            }
+        }
+    }
+
+    /// Connects to `addr` and sends the `queued` commands, the client being left
+    /// unchanged on errors.
+    fn send_queued(&mut self, addr: &str, queued: &Shared<Vec<u8>>) -> Result<()> {
+        let conn = TcpStream::connect(addr)?;
+        if let Some(opts) = &self.tcp_options {
+            tune(&conn, opts)?;
+        }
+        let mut writer: BufWriter<Box<dyn Write + Send>> =
+            BufWriter::new(Box::new(conn.try_clone()?));
+        let reader = timeout_reader(conn.try_clone()?, &self.poisoned);
+        self.writer.flush()?;
+        writer.write_all(&queued.lock())?;
+        writer.flush()?;
+        self.writer = writer;
+        self.reader = reader;
+        self.tcp = Some(conn);
+        Ok(())
+    }
+
+    /// Job bodies bigger than `threshold` bytes will be written to an anonymous
+    /// temporary file instead of being kept in memory (see [`Body::Spooled`]).
+    ///
+    /// Passing `None` (the default) keeps every body in memory.
+    pub fn spool_above(&mut self, threshold: Option<u64>) {
+        self.spool_threshold = threshold;
+    }
+
+    /// Job bodies bigger than the given threshold (in bytes) will be compressed with
+    /// the given codec on put (see [`Codec`]).
+    ///
+    /// Passing `None` (the default) disables compression. While it is enabled,
+    /// compressed bodies are decompressed on reserve and peek too, see
+    /// [`decompress_up_to`](Self::decompress_up_to).
+    pub fn compress_above(&mut self, compression: Option<(Codec, usize)>) {
+        self.compression = compression;
+    }
+
+    /// Job bodies starting with a codec header (see [`Codec`]) will be decompressed
+    /// on reserve and peek, as long as they decompress to at most `limit` bytes.
+    /// Bigger ones are errors, so that a small body cannot fill the memory or the disk.
+    ///
+    /// Passing `None` (the default) leaves bodies as they are, unless compression is
+    /// enabled with [`compress_above`](Self::compress_above): the limit is then
+    /// [`DEFAULT_DECOMPRESS_LIMIT`].
+    pub fn decompress_up_to(&mut self, limit: Option<u64>) {
+        self.decompress_limit = limit;
+    }
+
+    /// Job bodies will be encrypted with `cipher` on put, and decrypted on reserve and
+    /// peek (see [`PayloadCipher`]). Bodies that cannot be decrypted are errors.
+    ///
+    /// When compression is enabled too, bodies are compressed before being encrypted.
+    /// Passing `None` (the default) disables encryption.
+    pub fn encrypt_with(&mut self, cipher: Option<Box<dyn PayloadCipher>>) {
+        self.cipher = cipher;
+    }
+
+    /// When enabled, every response is checked against the replies the protocol
+    /// allows for the command that has just been sent (e.g. `TOUCHED` never follows
+    /// `put`). An unexpected reply means the client and the server are out of sync,
+    /// and is reported as an error instead of being mis-parsed.
+    ///
+    /// Disabled by default. Meant for debugging framing issues.
+    pub fn check_responses(&mut self, enabled: bool) {
+        self.check_responses = enabled;
+    }
+
+    /// When enabled, [`reserve`](Self::reserve) touches the jobs held by this
+    /// connection on `DeadlineSoon` and retries, instead of returning it. A timeout
+    /// given to `reserve` still applies across the retries.
+    ///
+    /// `DeadlineSoon` is still returned when the connection is not holding any job
+    /// that it knows of, e.g. one reserved with [`raw_command`](Self::raw_command):
+    /// there is nothing to touch, and retrying right away would get `DeadlineSoon`
+    /// again.
+    ///
+    /// The held jobs are the ones reserved by this client and not deleted, released
+    /// or buried since. Disabled by default.
+    pub fn auto_deadline_soon(&mut self, enabled: bool) {
+        self.auto_deadline_soon = enabled;
+    }
+
+    /// When enabled, command lines are checked against the limits of the protocol
+    /// before being sent: 224 bytes per line, and 200 bytes per tube name. A command
+    /// over a limit fails with [`Error::WouldBeBadFormat`] and nothing is sent, where
+    /// the server would otherwise answer `BAD_FORMAT`, possibly after reading part of
+    /// the line as another command.
+    ///
+    /// Disabled by default.
+    pub fn strict_limits(&mut self, enabled: bool) {
+        self.strict_limits = enabled;
+    }
+
+    /// Limits the rate of [`put`](Self::put), and of the helpers built on it, to
+    /// protect a shared server from e.g. runaway backfills (see [`RateLimit`]).
+    ///
+    /// Passing `None` (the default) removes the limit.
+    pub fn limit_puts(&mut self, limit: Option<RateLimit>) {
+        self.put_limit = limit.map(TokenBucket::new);
+    }
+
+    /// Bounds the length of response lines, `\r\n` included, so that a peer never
+    /// ending a line cannot make the client buffer forever. A longer line fails with
+    /// [`Error::ResponseLineTooLong`] and poisons the connection, as with
+    /// [`response_timeout`](Self::response_timeout).
+    ///
+    /// Defaults to 4096 bytes, well above the longest reply of beanstalkd. Job bodies
+    /// and YAML documents are not lines and are not bounded by it.
+    pub fn max_response_line(&mut self, len: usize) {
+        self.max_response_line = len;
+    }
+
+    /// Adds an interceptor seeing the commands sent and the responses received (see
+    /// [`Interceptor`]). Interceptors are layered in the order they are added: the
+    /// first one added sees commands first and responses last.
+    pub fn add_interceptor(&mut self, interceptor: Box<dyn Interceptor>) {
+        self.interceptors.push(interceptor);
+    }
+
+    /// Writes `line`, ending with `\r\n`, once the interceptors have seen it and, with
+    /// [`strict_limits`](Self::strict_limits), once it has been checked.
+    fn send_line(&mut self, line: String) -> Result<()> {
+        let mut line = match line.strip_suffix("\r\n") {
+            Some(stripped) => stripped.to_string(),
+            None => line,
+        };
+        for interceptor in &mut self.interceptors {
+            interceptor.before_send(&mut line);
+        }
+        if self.strict_limits {
+            check_limits(&line)?;
+        }
+        write!(self.writer, "{line}\r\n")?;
+        if !self.interceptors.is_empty() {
+            self.sent.push_back((line, Instant::now()));
+        }
+        Ok(())
+    }
+
+    /// Bounds the time the server may take to answer, as a safety net against a server
+    /// stalling in the middle of a response. The time a reserve with a timeout asks
+    /// the server to wait is added to it, and a reserve without timeout is not bounded.
+    ///
+    /// When the server does not answer in time, the command fails with
+    /// [`Error::ResponseTimeout`](crate::Error::ResponseTimeout) and the connection is
+    /// poisoned: the rest of the response could come later and be taken for the reply
+    /// to another command, so every command fails until [`reconnect`](Self::reconnect).
+    ///
+    /// Only applies to TCP connections. Passing `None` (the default), or a zero
+    /// timeout, waits for responses forever.
+    pub fn response_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.response_timeout = timeout.filter(|timeout| !timeout.is_zero());
+        if let (None, Some(tcp)) = (self.response_timeout, &self.tcp) {
+            tcp.set_read_timeout(None)?;
+        }
+        Ok(())
+    }
+
+    /// Prefixes every tube name sent to the server with `prefix` (e.g. `"staging."`),
+    /// and strips it from the tube names received. This lets several environments
+    /// share one beanstalkd without seeing each other's tubes:
+    ///
+    ///  - `use_`, `watch`, `ignore`, `stats_tube` and `pause_tube` add the prefix
+    ///  - `list_tubes` only returns the tubes having the prefix, without it
+    ///  - `list_tube_used`, `list_tube_watched` and `stats_tube` strip the prefix
+    ///
+    /// A new connection uses and watches the unprefixed "default" tube, so the
+    /// namespace is best set with [`TubeConfig::namespace`] and
+    /// [`connect_with`](Self::connect_with).
+    pub fn namespace(&mut self, prefix: impl Into<String>) {
+        self.namespace = prefix.into();
     }
 
     /// The "put" command is for any process that wants to insert a job into the queue.
     /// It comprises a command line followed by the job body:
     ///
-    ///     put <pri> <delay> <ttr> <bytes>\r\n
-    ///     <data>\r\n
+    /// ```text
+    /// put <pri> <delay> <ttr> <bytes>\r\n
+    /// <data>\r\n
+    /// ```
     ///
     /// It inserts a job into the client's currently used tube (see the "use" command
     /// below).
@@ -62,9 +614,30 @@ impl Beanstalk {
         ttr: Duration,
         data: &[u8],
     ) -> Result<PutResponse> {
+        if let Some(bucket) = &mut self.put_limit {
+            bucket.acquire()?;
+        }
+
+        let compressed;
+        let data = match self.compression {
+            Some((codec, threshold)) if data.len() > threshold => {
+                compressed = codec.encode(data)?;
+                &compressed[..]
+            }
+            _ => data,
+        };
+        let encrypted;
+        let data = match &self.cipher {
+            Some(c) => {
+                encrypted = cipher::seal(c.as_ref(), data)?;
+                &encrypted[..]
+            }
+            None => data,
+        };
+
         // request
-        write!(
-            self.writer,
+        send!(
+            self,
             "put {pri} {delay} {ttr} {bytes}\r\n",
             delay = delay.as_secs(),
             ttr = ttr.as_secs(),
@@ -73,10 +646,12 @@ impl Beanstalk {
         self.writer.write_all(data)?;
         self.writer.write_all(b"\r\n")?;
         self.writer.flush()?;
+        for interceptor in &mut self.interceptors {
+            interceptor.body_sent(data);
+        }
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response("put")?;
         let input = self.buf.trim_end_matches("\r\n");
         if let Some(input) = input.strip_prefix("INSERTED ") {
             return Ok(PutResponse::Inserted(input.parse()?));
@@ -88,47 +663,75 @@ impl Beanstalk {
             "EXPECTED_CRLF" => Ok(PutResponse::ExpectedCrlf),
             "JOB_TOO_BIG" => Ok(PutResponse::JobTooBig),
             "DRAINING" => Ok(PutResponse::Draining),
-            err => Err(err.into()),
+            input => Err(unexpected("put", None, input)),
         }
     }
 
+    /// Same as [`put`](Self::put), but `pri`, `delay` and `ttr` are given by `opts`.
+    pub fn put_with(&mut self, data: &[u8], opts: PutOptions) -> Result<PutResponse> {
+        self.put(opts.pri, opts.delay, opts.ttr, data)
+    }
+
+    /// Same as [`put`](Self::put), but the job will become ready at `when` instead of
+    /// after a relative `delay`.
+    ///
+    /// The delay is computed from now and rounded up to the next second, so that the
+    /// job is never ready before `when`. A `when` in the past means no delay at all.
+    pub fn put_at(
+        &mut self,
+        pri: u32,
+        when: SystemTime,
+        ttr: Duration,
+        data: &[u8],
+    ) -> Result<PutResponse> {
+        self.put(pri, delay_until(when), ttr, data)
+    }
+
     /// The "use" command is for producers. Subsequent put commands will put jobs into
     /// the tube specified by this command. If no use command has been issued, jobs
     /// will be put into the tube named "default".
     ///
-    ///     use <tube>\r\n
+    /// ```text
+    /// use <tube>\r\n
+    /// ```
     ///
     ///  - `tube` is a name at most 200 bytes. It specifies the tube to use. If the
     ///    tube does not exist, it will be created.
     ///
     /// The only reply is:
     ///
-    ///      USING <tube>\r\n
+    /// ```text
+    /// USING <tube>\r\n
+    /// ```
     ///
     ///  - `tube` is the name of the tube now being used.
     pub fn use_(&mut self, tube: &str) -> Result<&str> {
         // request
-        write!(self.writer, "use {tube}\r\n")?;
+        send!(self, "use {}{tube}\r\n", self.namespace)?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response_about("use", &tube)?;
         let input = self.buf.trim_end_matches("\r\n");
         if let Some(input) = input.strip_prefix("USING ") {
-            return Ok(input);
+            self.used = input.to_string();
+            return Ok(strip_namespace(&self.namespace, input));
         }
-        Err(input.into())
+        Err(unexpected("use", Some(&tube), input))
     }
 
     /// A process that wants to consume jobs from the queue uses "reserve", "delete",
     /// "release", and "bury". The first worker command, "reserve", looks like this:
     ///
-    ///     reserve\r\n
+    /// ```text
+    /// reserve\r\n
+    /// ```
     ///
     /// Alternatively, you can specify a timeout as follows:
     ///
-    ///     reserve-with-timeout <seconds>\r\n
+    /// ```text
+    /// reserve-with-timeout <seconds>\r\n
+    /// ```
     ///
     /// This will return a newly-reserved job. If no job is available to be reserved,
     /// beanstalkd will wait to send a response until one becomes available. Once a
@@ -146,58 +749,109 @@ impl Beanstalk {
     /// time the client will block on the reserve request until a job becomes
     /// available.
     pub fn reserve(&mut self, timeout: Option<Duration>) -> Result<ReserveResponse> {
+        let give_up = timeout.map(|timeout| Instant::now() + timeout);
+        let mut timeout = timeout;
+        loop {
+            match self.reserve_once(timeout)? {
+                ReserveResponse::DeadlineSoon
+                    if self.auto_deadline_soon && self.handle_deadline_soon()? =>
+                {
+                    timeout = give_up.map(|t| t.saturating_duration_since(Instant::now()));
+                }
+                res => return Ok(res),
+            }
+        }
+    }
+
+    fn reserve_once(&mut self, timeout: Option<Duration>) -> Result<ReserveResponse> {
         // request
         match timeout {
-            Some(timeout) => write!(
-                self.writer,
-                "reserve-with-timeout {}\r\n",
-                timeout.as_secs()
-            )?,
-            None => write!(self.writer, "reserve\r\n")?,
+            Some(timeout) => send!(self, "reserve-with-timeout {}\r\n", timeout.as_secs())?,
+            None => send!(self, "reserve\r\n")?,
         }
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response_within("reserve", None, timeout)?;
         match self.buf.trim_end_matches("\r\n") {
             "DEADLINE_SOON" => Ok(ReserveResponse::DeadlineSoon),
             "TIMED_OUT" => Ok(ReserveResponse::TimedOut),
             input => {
-                let (id, bytes) = read_reserved(input)?;
-                let mut data_reader = (&mut self.reader).take(bytes);
-                let mut data = Vec::with_capacity(bytes as usize);
-                data_reader.read_to_end(&mut data)?;
-                self.reader.read_line(&mut self.buf)?; // read ending \r\n
+                let (id, bytes) = read_reserved(input, "reserve", None)?;
+                self.held.push(id);
+                let data = self.read_body(id, bytes)?;
                 Ok(ReserveResponse::Reserved { id, data })
             }
         }
     }
 
+    /// Reserves a job if one is ready right away (`reserve-with-timeout 0`), for event
+    /// loops polling the queue between other work.
+    ///
+    /// `None` is returned when no job is ready, and on `DEADLINE_SOON` whatever
+    /// [`auto_deadline_soon`](Self::auto_deadline_soon): a job held by this connection
+    /// is about to time out, and is better finished or touched before taking another
+    /// one.
+    pub fn try_reserve(&mut self) -> Result<Option<Job>> {
+        match self.reserve_once(Some(Duration::ZERO))? {
+            ReserveResponse::Reserved { id, data } => Ok(Some(Job { id, data })),
+            ReserveResponse::DeadlineSoon | ReserveResponse::TimedOut => Ok(None),
+        }
+    }
+
+    /// Touches the held jobs, see [`auto_deadline_soon`](Self::auto_deadline_soon).
+    /// `false` when there is none, the job about to time out being unknown.
+    fn handle_deadline_soon(&mut self) -> Result<bool> {
+        for id in self.held.clone() {
+            self.touch(id)?;
+        }
+        Ok(!self.held.is_empty())
+    }
+
+    /// Same as [`reserve`](Self::reserve) with a timeout, but waits until an absolute
+    /// `deadline`, which may be less than a second away. `TimedOut` is returned once
+    /// the deadline is reached.
+    ///
+    /// beanstalkd only deals with whole seconds: the remaining time is sent rounded
+    /// down, and jobs are polled for during the last fraction of a second.
+    ///
+    /// `DeadlineSoon` is never returned: while a job held by this connection is about
+    /// to time out, the reserve is retried until the deadline. As the deadline is
+    /// absolute, calling this again with the same deadline only waits for the time
+    /// left.
+    pub fn reserve_with_deadline(&mut self, deadline: Instant) -> Result<ReserveResponse> {
+        BeanstalkOps::reserve_with_deadline(self, deadline)
+    }
+
     /// A job can be reserved by its id. Once a job is reserved for the client,
     /// the client has limited time to run (TTR) the job before the job times out.
     /// When the job times out, the server will put the job back into the ready queue.
     /// The command looks like this:
     ///
-    ///     reserve-job <id>\r\n
+    /// ```text
+    /// reserve-job <id>\r\n
+    /// ```
     ///
     /// - `id` is the job id to reserve
+    ///
+    /// Servers older than 1.12 do not know this command: [`Error::Unsupported`] is
+    /// returned, without sending anything once [`server_info`](Self::server_info) is
+    /// known.
     pub fn reserve_by_id(&mut self, id: Id) -> Result<ReserveByIdResponse> {
+        self.require("reserve-job", ServerInfo::supports_reserve_job)?;
+
         // request
-        write!(self.writer, "reserve-job {id}\r\n")?;
+        send!(self, "reserve-job {id}\r\n")?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response_about("reserve-job", &id)?;
         match self.buf.trim_end_matches("\r\n") {
             "NOT_FOUND" => Ok(ReserveByIdResponse::NotFound),
             input => {
-                let (id, bytes) = read_reserved(input)?;
-                let mut data_reader = (&mut self.reader).take(bytes);
-                let mut data = Vec::with_capacity(bytes as usize);
-                data_reader.read_to_end(&mut data)?;
-                self.reader.read_line(&mut self.buf)?; // read ending \r\n
+                let (id, bytes) = read_reserved(input, "reserve-job", Some(&id))?;
+                self.held.push(id);
+                let data = self.read_body(id, bytes)?;
                 Ok(ReserveByIdResponse::Reserved { id, data })
             }
         }
@@ -208,29 +862,36 @@ impl Beanstalk {
     /// delete jobs that it has reserved, ready jobs, delayed jobs, and jobs that are
     /// buried. The delete command looks like this:
     ///
-    ///     delete <id>\r\n
+    /// ```text
+    /// delete <id>\r\n
+    /// ```
     ///
     ///  - `id` is the job id to delete.
     pub fn delete(&mut self, id: Id) -> Result<DeleteResponse> {
         // request
-        write!(self.writer, "delete {}\r\n", id)?;
+        send!(self, "delete {}\r\n", id)?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
-        match self.buf.trim_end_matches("\r\n") {
+        self.read_response_about("delete", &id)?;
+        let res = match self.buf.trim_end_matches("\r\n") {
             "DELETED" => Ok(DeleteResponse::Deleted),
             "NOT_FOUND" => Ok(DeleteResponse::NotFound),
-            input => Err(input.into()),
+            input => Err(unexpected("delete", Some(&id), input)),
+        };
+        if res.is_ok() {
+            self.forget(id);
         }
+        res
     }
 
     /// The release command puts a reserved job back into the ready queue (and marks
     /// its state as "ready") to be run by any client. It is normally used when the job
     /// fails because of a transitory error. It looks like this:
     ///
-    ///     release <id> <pri> <delay>\r\n
+    /// ```text
+    /// release <id> <pri> <delay>\r\n
+    /// ```
     ///
     ///  - `id` is the job id to release.
     ///
@@ -240,18 +901,29 @@ impl Beanstalk {
     ///    the ready queue. The job will be in the "delayed" state during this time.
     pub fn release(&mut self, id: Id, pri: u32, delay: Duration) -> Result<ReleaseResponse> {
         // request
-        write!(self.writer, "release {id} {pri} {}\r\n", delay.as_secs())?;
+        send!(self, "release {id} {pri} {}\r\n", delay.as_secs())?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
-        match self.buf.trim_end_matches("\r\n") {
+        self.read_response_about("release", &id)?;
+        let res = match self.buf.trim_end_matches("\r\n") {
             "RELEASED" => Ok(ReleaseResponse::Released),
             "BURIED" => Ok(ReleaseResponse::Buried),
             "NOT_FOUND" => Ok(ReleaseResponse::NotFound),
-            input => Err(input.into()),
+            input => Err(unexpected("release", Some(&id), input)),
+        };
+        if res.is_ok() {
+            self.forget(id);
         }
+        res
+    }
+
+    /// Same as [`release`](Self::release), but the job will become ready at `when`
+    /// instead of after a relative `delay`.
+    ///
+    /// See [`put_at`](Self::put_at) for how the delay is computed.
+    pub fn release_at(&mut self, id: Id, pri: u32, when: SystemTime) -> Result<ReleaseResponse> {
+        self.release(id, pri, delay_until(when))
     }
 
     /// The bury command puts a job into the "buried" state. Buried jobs are put into a
@@ -260,24 +932,29 @@ impl Beanstalk {
     ///
     /// The bury command looks like this:
     ///
-    ///     bury <id> <pri>\r\n
+    /// ```text
+    /// bury <id> <pri>\r\n
+    /// ```
     ///
     ///  - `id` is the job id to bury.
     ///
     ///  - `pri` is a new priority to assign to the job.
     pub fn bury(&mut self, id: Id, pri: u32) -> Result<BuryResponse> {
         // request
-        write!(self.writer, "bury {id} {pri}\r\n")?;
+        send!(self, "bury {id} {pri}\r\n")?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
-        match self.buf.trim_end_matches("\r\n") {
+        self.read_response_about("bury", &id)?;
+        let res = match self.buf.trim_end_matches("\r\n") {
             "BURIED" => Ok(BuryResponse::Buried),
             "NOT_FOUND" => Ok(BuryResponse::NotFound),
-            input => Err(input.into()),
+            input => Err(unexpected("bury", Some(&id), input)),
+        };
+        if res.is_ok() {
+            self.forget(id);
         }
+        res
     }
 
     /// The "touch" command allows a worker to request more time to work on a job.
@@ -289,74 +966,104 @@ impl Beanstalk {
     ///
     /// The touch command looks like this:
     ///
-    ///     touch <id>\r\n
+    /// ```text
+    /// touch <id>\r\n
+    /// ```
     ///
     ///  - `id` is the ID of a job reserved by the current connection.
     pub fn touch(&mut self, id: Id) -> Result<TouchResponse> {
         // request
-        write!(self.writer, "touch {id}\r\n")?;
+        send!(self, "touch {id}\r\n")?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response_about("touch", &id)?;
         match self.buf.trim_end_matches("\r\n") {
             "TOUCHED" => Ok(TouchResponse::Touched),
-            "NOT_FOUND" => Ok(TouchResponse::NotFound),
-            input => Err(input.into()),
+            "NOT_FOUND" => {
+                self.forget(id);
+                Ok(TouchResponse::NotFound)
+            }
+            input => Err(unexpected("touch", Some(&id), input)),
         }
     }
 
+    /// Stops tracking `id` as held by this connection.
+    #[inline]
+    fn forget(&mut self, id: Id) {
+        self.held.retain(|&held| held != id);
+    }
+
+    /// Tracks `tube`, as named on the server, as watched by this connection.
+    fn track_watch(&mut self, tube: String) {
+        if !self.watched.contains(&tube) {
+            self.watched.push(tube);
+        }
+    }
+
+    /// Stops tracking `tube`, as named on the server, as watched by this connection.
+    fn track_ignore(&mut self, tube: &str) {
+        self.watched.retain(|watched| watched != tube);
+    }
+
     /// The "watch" command adds the named tube to the watch list for the current
     /// connection. A reserve command will take a job from any of the tubes in the
     /// watch list. For each new connection, the watch list initially consists of one
     /// tube, named "default".
     ///
-    ///     watch <tube>\r\n
+    /// ```text
+    /// watch <tube>\r\n
+    /// ```
     ///
     ///  - `tube` is a name at most 200 bytes. It specifies a tube to add to the watch
     ///    list. If the tube doesn't exist, it will be created.
     ///
     /// The response is:
     ///
-    ///     WATCHING <count>\r\n
+    /// ```text
+    /// WATCHING <count>\r\n
+    /// ```
     ///
     /// - `count` is the integer number of tubes currently in the watch list.
     pub fn watch(&mut self, tube: &str) -> Result<usize> {
         // request
-        write!(self.writer, "watch {tube}\r\n")?;
+        send!(self, "watch {}{tube}\r\n", self.namespace)?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response_about("watch", &tube)?;
         let input = self.buf.trim_end_matches("\r\n");
         if let Some(input) = input.strip_prefix("WATCHING ") {
-            return Ok(input.parse()?);
+            let count = input.parse()?;
+            self.track_watch(format!("{}{tube}", self.namespace));
+            return Ok(count);
         }
-        Err(input.into())
+        Err(unexpected("watch", Some(&tube), input))
     }
 
     /// The "ignore" command is for consumers. It removes the named tube from the
     /// watch list for the current connection.
     ///
-    ///     ignore <tube>\r\n
+    /// ```text
+    /// ignore <tube>\r\n
+    /// ```
     pub fn ignore(&mut self, tube: &str) -> Result<IgnoreResponse> {
         // request
-        write!(self.writer, "ignore {tube}\r\n")?;
+        send!(self, "ignore {}{tube}\r\n", self.namespace)?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response_about("ignore", &tube)?;
         match self.buf.trim_end_matches("\r\n") {
             "NOT_IGNORED" => Ok(IgnoreResponse::NotIgnored),
             input => {
                 if let Some(input) = input.strip_prefix("WATCHING ") {
-                    return Ok(IgnoreResponse::Count(input.parse()?));
+                    let count = input.parse()?;
+                    self.track_ignore(&format!("{}{tube}", self.namespace));
+                    return Ok(IgnoreResponse::Count(count));
                 }
 
-                Err(input.into())
+                Err(unexpected("ignore", Some(&tube), input))
             }
         }
     }
@@ -366,8 +1073,8 @@ impl Beanstalk {
     ///  - "peek <id>\r\n" - return job <id>.
     pub fn peek(&mut self, id: Id) -> Result<PeekResponse> {
         // request
-        write!(self.writer, "peek {id}\r\n")?;
-        self.peek_internal()
+        send!(self, "peek {id}\r\n")?;
+        self.peek_internal("peek", Some(&id))
     }
 
     /// The peek command let the client inspect a job in the system.
@@ -376,8 +1083,8 @@ impl Beanstalk {
     ///  - "peek-ready\r\n" - return the next ready job.
     pub fn peek_ready(&mut self) -> Result<PeekResponse> {
         // request
-        write!(self.writer, "peek-ready\r\n")?;
-        self.peek_internal()
+        send!(self, "peek-ready\r\n")?;
+        self.peek_internal("peek-ready", None)
     }
 
     /// The peek command let the client inspect a job in the system.
@@ -386,8 +1093,8 @@ impl Beanstalk {
     ///  - "peek-delayed\r\n" - return the delayed job with the shortest delay left.
     pub fn peek_delayed(&mut self) -> Result<PeekResponse> {
         // request
-        write!(self.writer, "peek-delayed\r\n")?;
-        self.peek_internal()
+        send!(self, "peek-delayed\r\n")?;
+        self.peek_internal("peek-delayed", None)
     }
 
     /// The peek command let the client inspect a job in the system.
@@ -396,58 +1103,195 @@ impl Beanstalk {
     ///  - "peek-buried\r\n" - return the next job in the list of buried jobs.
     pub fn peek_buried(&mut self) -> Result<PeekResponse> {
         // request
-        write!(self.writer, "peek-buried\r\n")?;
-        self.peek_internal()
+        send!(self, "peek-buried\r\n")?;
+        self.peek_internal("peek-buried", None)
     }
 
     /// Every peek commands work the same, so once the "command" is written
     /// to the `self.writer`, we can generalize the response behavior
-    fn peek_internal(&mut self) -> Result<PeekResponse> {
+    fn peek_internal(
+        &mut self,
+        cmd: &'static str,
+        arg: Option<&dyn fmt::Display>,
+    ) -> Result<PeekResponse> {
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response_within(cmd, arg, Some(Duration::ZERO))?;
         match self.buf.trim_end_matches("\r\n") {
             "NOT_FOUND" => Ok(PeekResponse::NotFound),
             input => {
-                let (id, bytes) = read_found(input)?;
-                let mut data_reader = (&mut self.reader).take(bytes);
-                let mut data = Vec::with_capacity(bytes as usize);
-                data_reader.read_to_end(&mut data)?;
-                self.reader.read_line(&mut self.buf)?; // read ending \r\n
+                let (id, bytes) = read_found(input, cmd, arg)?;
+                let data = self.read_body(id, bytes)?;
                 Ok(PeekResponse::Found { id, data })
             }
         }
     }
 
+    /// Reads the response line of `cmd` into `self.buf`. Generic errors such as
+    /// `UNKNOWN_COMMAND` are turned into [`Error::Reply`], and the line is checked to
+    /// be a valid reply to `cmd` if enabled.
+    fn read_response(&mut self, cmd: &'static str) -> Result<()> {
+        self.read_response_within(cmd, None, Some(Duration::ZERO))
+    }
+
+    /// Same as [`read_response`](Self::read_response), for a command about `arg`, a
+    /// tube or a job id, given as context in errors.
+    fn read_response_about(&mut self, cmd: &'static str, arg: &dyn fmt::Display) -> Result<()> {
+        self.read_response_within(cmd, Some(arg), Some(Duration::ZERO))
+    }
+
+    /// Same as [`read_response_about`](Self::read_response_about), for a command asking
+    /// the server to `wait` before answering, forever if `None`.
+    fn read_response_within(
+        &mut self,
+        cmd: &'static str,
+        arg: Option<&dyn fmt::Display>,
+        wait: Option<Duration>,
+    ) -> Result<()> {
+        self.read_line_within(cmd, wait)?;
+        let reply = self.buf.trim_end_matches("\r\n");
+        if reply == "UNKNOWN_COMMAND" && NEWER_COMMANDS.contains(&cmd) {
+            debug!("{cmd} is not supported by the server");
+            return Err(Error::Unsupported {
+                cmd,
+                version: self.server_info.as_ref().map(|info| info.version.clone()),
+            });
+        }
+        if GENERIC_ERRORS.contains(&reply) {
+            debug!("{cmd} failed: {reply}");
+            return Err(Error::Reply {
+                cmd,
+                arg: arg.map(|arg| arg.to_string()),
+                reply: reply.to_string(),
+            });
+        }
+        if self.check_responses {
+            let reply = self.buf.split([' ', '\r']).next().unwrap_or_default();
+            if !allowed_replies(cmd).contains(&reply) {
+                debug!("unexpected reply {reply:?} to {cmd}");
+                return Err(format!(
+                    "unexpected reply {reply:?} to {cmd:?}, the connection is out of sync"
+                )
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a line of the response to `cmd` into `self.buf`, the server being asked
+    /// to `wait` before answering, forever if `None`. The response timeout, if any,
+    /// comes on top of it.
+    fn read_line_within(&mut self, cmd: &'static str, wait: Option<Duration>) -> Result<()> {
+        if self.poisoned.load(Ordering::SeqCst) {
+            debug!("{cmd} not sent, the connection is poisoned");
+            return Err(
+                "the connection is poisoned by a previous response, reconnect first".into(),
+            );
+        }
+        self.connect_lazily()?;
+        if let (Some(limit), Some(tcp)) = (self.response_timeout, &self.tcp) {
+            tcp.set_read_timeout(wait.map(|wait| wait + limit))?;
+        }
+        self.buf.clear();
+        let limit = self.max_response_line as u64;
+        match (&mut self.reader).take(limit).read_line(&mut self.buf) {
+            Ok(len) if len as u64 == limit && !self.buf.ends_with('\n') => {
+                debug!("the response to {cmd} is longer than {limit} bytes");
+                // the rest of the line would be taken for the next response
+                self.poisoned.store(true, Ordering::SeqCst);
+                return Err(Error::ResponseLineTooLong {
+                    cmd,
+                    max: self.max_response_line,
+                });
+            }
+            Ok(0) => {
+                debug!("connection closed while waiting for the response to {cmd}");
+                return Err(
+                    io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed").into(),
+                );
+            }
+            Ok(_) => trace!("{cmd}: {}", self.buf.trim_end_matches("\r\n")),
+            Err(err) => {
+                debug!("unable to read the response to {cmd}: {err}");
+                return Err(err.into());
+            }
+        }
+        if let Some((sent, at)) = self.sent.pop_front() {
+            let response = self.buf.trim_end_matches("\r\n");
+            for interceptor in self.interceptors.iter_mut().rev() {
+                interceptor.after_receive(&sent, response, at.elapsed());
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the body of job `id`, of `bytes` length, followed by the ending "\r\n".
+    ///
+    /// The body is decrypted if a cipher is set, spooled to a temporary file if it
+    /// exceeds the spool threshold, and decompressed if it starts with a codec header
+    /// and decompression is enabled. Failing to do so is an [`Error::Body`].
+    fn read_body(&mut self, id: Id, bytes: u64) -> Result<Body> {
+        let spool = matches!(self.spool_threshold, Some(threshold) if bytes > threshold);
+        let limit = match (self.decompress_limit, self.compression) {
+            (Some(limit), _) => Some(limit),
+            (None, Some(_)) => Some(DEFAULT_DECOMPRESS_LIMIT),
+            (None, None) => None,
+        };
+        let mut data_reader = (&mut self.reader).take(bytes);
+        let body = match &self.cipher {
+            Some(c) => {
+                let mut framed = Vec::with_capacity(bytes as usize);
+                data_reader.read_to_end(&mut framed)?;
+                cipher::open(c.as_ref(), &framed)
+                    .and_then(|plain| decode_body(&plain[..], plain.len() as u64, spool, limit))
+            }
+            None => decode_body(&mut data_reader, bytes, spool, limit),
+        };
+        // even if decoding failed, consume the whole body to stay in sync with the server
+        io::copy(&mut data_reader, &mut io::sink())?;
+        self.reader.read_line(&mut self.buf)?; // read ending \r\n
+        if let Ok(body) = &body {
+            for interceptor in self.interceptors.iter_mut().rev() {
+                interceptor.body_received(body);
+            }
+        }
+        body.map_err(|err| Error::Body {
+            id,
+            source: Box::new(err),
+        })
+    }
+
     /// The kick command applies only to the currently used tube. It moves jobs into
     /// the ready queue. If there are any buried jobs, it will only kick buried jobs.
     /// Otherwise it will kick delayed jobs. It looks like:
     ///
-    ///     kick <bound>\r\n
+    /// ```text
+    /// kick <bound>\r\n
+    /// ```
     ///
     ///  - `bound` is an integer upper bound on the number of jobs to kick. The server
     ///    will kick no more than <bound> jobs.
     ///
     /// The response is of the form:
     ///
-    ///     KICKED <count>\r\n
+    /// ```text
+    /// KICKED <count>\r\n
+    /// ```
     ///
     ///  - `count` is an integer indicating the number of jobs actually kicked.
     pub fn kick(&mut self, bound: u32) -> Result<usize> {
         // request
-        write!(self.writer, "kick {bound}\r\n")?;
+        send!(self, "kick {bound}\r\n")?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response("kick")?;
         let input = self.buf.trim_end_matches("\r\n");
         if let Some(input) = input.strip_prefix("KICKED ") {
             return Ok(input.parse()?);
         }
-        Err(input.into())
+        Err(unexpected("kick", None, input))
     }
 
     /// The kick-job command is a variant of kick that operates with a single job
@@ -455,42 +1299,49 @@ impl Beanstalk {
     /// delayed state, it will be moved to the ready queue of the the same tube where it
     /// currently belongs. The syntax is:
     ///
-    ///     kick-job <id>\r\n
+    /// ```text
+    /// kick-job <id>\r\n
+    /// ```
     ///
     ///  - <id> is the job id to kick.
+    ///
+    /// Servers older than 1.8 do not know this command, see
+    /// [`reserve_by_id`](Self::reserve_by_id).
     pub fn kick_job(&mut self, id: Id) -> Result<KickJobResponse> {
+        self.require("kick-job", ServerInfo::supports_kick_job)?;
+
         // request
-        write!(self.writer, "kick-job {id}\r\n")?;
+        send!(self, "kick-job {id}\r\n")?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response_about("kick-job", &id)?;
         match self.buf.trim_end_matches("\r\n") {
             "KICKED" => Ok(KickJobResponse::Kicked),
             "NOT_FOUND" => Ok(KickJobResponse::NotFound),
-            input => Err(input.into()),
+            input => Err(unexpected("kick-job", Some(&id), input)),
         }
     }
 
     /// The stats-job command gives statistical information about the specified job if
     /// it exists. Its form is:
     ///
-    ///     stats-job <id>\r\n
+    /// ```text
+    /// stats-job <id>\r\n
+    /// ```
     ///
     ///  - <id> is a job id.
     pub fn stats_job(&mut self, id: Id) -> Result<StatsJobResponse> {
         // request
-        write!(self.writer, "stats-job {id}\r\n")?;
+        send!(self, "stats-job {id}\r\n")?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response_about("stats-job", &id)?;
         match self.buf.trim_end_matches("\r\n") {
             "NOT_FOUND" => Ok(StatsJobResponse::NotFound),
             input => {
-                let bytes = read_ok(input)?;
+                let bytes = read_ok(input, "stats-job", Some(&id))?;
                 let mut data_reader = (&mut self.reader).take(bytes);
                 let mut data = Vec::with_capacity(bytes as usize);
                 data_reader.read_to_end(&mut data)?;
@@ -500,29 +1351,94 @@ impl Beanstalk {
         }
     }
 
+    /// Polls `stats-job` every `interval` until the job `id` is in `state`, returning
+    /// its stats then, or `None` if the job is deleted in the meantime. Not reaching
+    /// `state` within `timeout` is an error.
+    ///
+    /// ```no_run
+    /// # use bsc::{Beanstalk, State};
+    /// # use std::time::Duration;
+    /// # let mut bsc = Beanstalk::connect("127.0.0.1:11300").unwrap();
+    /// let secs = Duration::from_secs;
+    /// let stats = bsc.wait_for_state(42, State::Buried, secs(10), secs(1)).unwrap();
+    /// ```
+    pub fn wait_for_state(
+        &mut self,
+        id: Id,
+        state: State,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Option<StatsJob>> {
+        BeanstalkOps::wait_for_state(self, id, state, timeout, interval)
+    }
+
     /// The stats-tube command gives statistical information about the specified tube
     /// if it exists. Its form is:
     ///
-    ///     stats-tube <tube>\r\n
+    /// ```text
+    /// stats-tube <tube>\r\n
+    /// ```
     ///
     ///  - <tube> is a name at most 200 bytes. Stats will be returned for this tube.
     pub fn stats_tube(&mut self, tube: &str) -> Result<StatsTubeResponse> {
         // request
-        write!(self.writer, "stats-tube {tube}\r\n")?;
+        send!(self, "stats-tube {}{tube}\r\n", self.namespace)?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_stats_tube(tube)
+    }
+
+    /// Returns the stats of every tube, see [`list_tubes`](Self::list_tubes) and
+    /// [`stats_tube`](Self::stats_tube).
+    ///
+    /// The `stats-tube` commands are pipelined: they are all written at once and the
+    /// responses are read afterwards, so this takes about two round trips whatever
+    /// the number of tubes. Tubes deleted by the server in the meantime are skipped.
+    ///
+    /// When a response is an error, the responses to the next commands are still to
+    /// come: the connection cannot be used anymore until [`reconnect`](Self::reconnect).
+    pub fn stats_all_tubes(&mut self) -> Result<Vec<StatsTube>> {
+        let tubes: Vec<String> = self.list_tubes()?.into_iter().map(String::from).collect();
+
+        // request
+        for tube in &tubes {
+            send!(self, "stats-tube {}{tube}\r\n", self.namespace)?;
+        }
+        self.writer.flush()?;
+
+        // responses
+        let mut stats = Vec::with_capacity(tubes.len());
+        for (i, tube) in tubes.iter().enumerate() {
+            match self.read_stats_tube(tube) {
+                Ok(StatsTubeResponse::Ok(tube)) => stats.push(tube),
+                Ok(StatsTubeResponse::NotFound) => {}
+                Err(err) => {
+                    if i + 1 < tubes.len() {
+                        debug!("stats-tube {tube} failed with responses left to read");
+                        // they would be taken for the responses to the next commands
+                        self.poisoned.store(true, Ordering::SeqCst);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    fn read_stats_tube(&mut self, tube: &str) -> Result<StatsTubeResponse> {
+        self.read_response_about("stats-tube", &tube)?;
         match self.buf.trim_end_matches("\r\n") {
             "NOT_FOUND" => Ok(StatsTubeResponse::NotFound),
             input => {
-                let bytes = read_ok(input)?;
+                let bytes = read_ok(input, "stats-tube", Some(&tube))?;
                 let mut data_reader = (&mut self.reader).take(bytes);
                 let mut data = Vec::with_capacity(bytes as usize);
                 data_reader.read_to_end(&mut data)?;
                 self.reader.read_line(&mut self.buf)?; // read ending \r\n
-                Ok(StatsTubeResponse::Ok(serde_yaml::from_slice(&data)?))
+                let mut stats: StatsTube = serde_yaml::from_slice(&data)?;
+                stats.name = strip_namespace(&self.namespace, &stats.name).to_string();
+                Ok(StatsTubeResponse::Ok(stats))
             }
         }
     }
@@ -530,17 +1446,18 @@ impl Beanstalk {
     /// The stats command gives statistical information about the system as a whole.
     /// Its form is:
     ///
-    ///     stats\r\n
+    /// ```text
+    /// stats\r\n
+    /// ```
     pub fn stats(&mut self) -> Result<Stats> {
         // request
-        write!(self.writer, "stats\r\n")?;
+        send!(self, "stats\r\n")?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response("stats")?;
         let input = self.buf.trim_end_matches("\r\n");
-        let bytes = read_ok(input)?;
+        let bytes = read_ok(input, "stats", None)?;
         let mut data_reader = (&mut self.reader).take(bytes);
         let mut data = Vec::with_capacity(bytes as usize);
         data_reader.read_to_end(&mut data)?;
@@ -548,69 +1465,106 @@ impl Beanstalk {
         Ok(serde_yaml::from_slice(&data)?)
     }
 
+    /// The version of the server and the optional commands it supports. It is read
+    /// with a `stats` command the first time, and cached until
+    /// [`reconnect`](Self::reconnect).
+    pub fn server_info(&mut self) -> Result<&ServerInfo> {
+        if self.server_info.is_none() {
+            let stats = self.stats()?;
+            debug!("server version {}", stats.version);
+            self.server_info = Some(ServerInfo::from(&stats));
+        }
+        Ok(self.server_info.as_ref().expect("server info just read"))
+    }
+
+    /// Fails with [`Error::Unsupported`] if the server info is known and says that
+    /// `cmd` is not supported. Nothing is sent to find out otherwise.
+    fn require(&self, cmd: &'static str, supported: fn(&ServerInfo) -> bool) -> Result<()> {
+        match &self.server_info {
+            Some(info) if !supported(info) => Err(Error::Unsupported {
+                cmd,
+                version: Some(info.version.clone()),
+            }),
+            _ => Ok(()),
+        }
+    }
+
     /// The list-tubes command returns a list of all existing tubes. Its form is:
     ///
-    ///       list-tubes\r\n
+    /// ```text
+    /// list-tubes\r\n
+    /// ```
     pub fn list_tubes(&mut self) -> Result<Vec<&str>> {
         // request
-        write!(self.writer, "list-tubes\r\n")?;
+        send!(self, "list-tubes\r\n")?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response("list-tubes")?;
         let input = self.buf.trim_end_matches("\r\n");
-        let bytes = read_ok(input)?;
+        let bytes = read_ok(input, "list-tubes", None)?;
         let mut data_reader = (&mut self.reader).take(bytes);
         self.buf.clear();
         data_reader.read_to_string(&mut self.buf)?;
         self.reader.read_line(&mut self.buf)?; // read ending \r\n
-        Ok(serde_yaml::from_str(&self.buf)?)
+        let tubes: Vec<&str> = serde_yaml::from_str(&self.buf)?;
+        Ok(tubes
+            .into_iter()
+            .filter_map(|tube| tube.strip_prefix(self.namespace.as_str()))
+            .collect())
     }
 
     /// The list-tube-used command returns the tube currently being used by the
     /// client. Its form is:
     ///
-    ///     list-tube-used\r\n
+    /// ```text
+    /// list-tube-used\r\n
+    /// ```
     pub fn list_tube_used(&mut self) -> Result<&str> {
         // request
-        write!(self.writer, "list-tube-used\r\n")?;
+        send!(self, "list-tube-used\r\n")?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response("list-tube-used")?;
         let input = self.buf.trim_end_matches("\r\n");
         if let Some(input) = input.strip_prefix("USING ") {
-            return Ok(input);
+            return Ok(strip_namespace(&self.namespace, input));
         }
-        Err(input.into())
+        Err(unexpected("list-tube-used", None, input))
     }
 
     /// The list-tubes-watched command returns a list tubes currently being watched by
     /// the client. Its form is:
     ///
-    ///     list-tubes-watched\r\n
+    /// ```text
+    /// list-tubes-watched\r\n
+    /// ```
     pub fn list_tube_watched(&mut self) -> Result<Vec<&str>> {
         // request
-        write!(self.writer, "list-tubes-watched\r\n")?;
+        send!(self, "list-tubes-watched\r\n")?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response("list-tubes-watched")?;
         let input = self.buf.trim_end_matches("\r\n");
-        let bytes = read_ok(input)?;
+        let bytes = read_ok(input, "list-tubes-watched", None)?;
         let mut data_reader = (&mut self.reader).take(bytes);
         self.buf.clear();
         data_reader.read_to_string(&mut self.buf)?;
         self.reader.read_line(&mut self.buf)?; // read ending \r\n
-        Ok(serde_yaml::from_str(&self.buf)?)
+        let tubes: Vec<&str> = serde_yaml::from_str(&self.buf)?;
+        Ok(tubes
+            .into_iter()
+            .map(|tube| strip_namespace(&self.namespace, tube))
+            .collect())
     }
 
     /// The pause-tube command can delay any new job being reserved for a given time. Its form is:
     ///
-    ///      pause-tube <tube-name> <delay>\r\n
+    /// ```text
+    /// pause-tube <tube-name> <delay>\r\n
+    /// ```
     ///
     /// - `tube` is the tube to pause
     ///
@@ -618,29 +1572,423 @@ impl Beanstalk {
     ///   jobs from the queue
     pub fn pause_tube(&mut self, tube: &str, delay: Duration) -> Result<PauseTubeResponse> {
         // request
-        write!(self.writer, "pause-tube {tube} {}\r\n", delay.as_secs())?;
+        send!(
+            self,
+            "pause-tube {}{tube} {}\r\n",
+            self.namespace,
+            delay.as_secs()
+        )?;
         self.writer.flush()?;
 
         // response
-        self.buf.clear();
-        self.reader.read_line(&mut self.buf)?;
+        self.read_response_about("pause-tube", &tube)?;
         match self.buf.trim_end_matches("\r\n") {
             "PAUSED" => Ok(PauseTubeResponse::Paused),
             "NOT_FOUND" => Ok(PauseTubeResponse::NotFound),
-            err => Err(err.into()),
+            input => Err(unexpected("pause-tube", Some(&tube), input)),
+        }
+    }
+
+    /// Sends a command as is, for the commands bsc has no method for yet, e.g. the ones
+    /// of a beanstalkd fork. `line` is the command line without the ending `\r\n`,
+    /// and `body` the data chunk following it, if the command takes one.
+    ///
+    /// The data chunk of the reply is read for the replies ending with a byte count
+    /// (`OK`, `FOUND` and `RESERVED`). Neither the body nor the reply go through the
+    /// namespace, compression or encryption, and the tubes used or watched this way
+    /// are not restored by [`reconnect`](Self::reconnect). As the time the server
+    /// waits before answering is not known, the response timeout does not apply.
+    ///
+    /// ```no_run
+    /// # use bsc::Beanstalk;
+    /// # let mut bsc = Beanstalk::connect("127.0.0.1:11300").unwrap();
+    /// let res = bsc.raw_command("stats", None).unwrap();
+    /// println!("{}", String::from_utf8_lossy(&res.data.unwrap_or_default()));
+    /// ```
+    pub fn raw_command(&mut self, line: &str, body: Option<&[u8]>) -> Result<RawResponse> {
+        if line.contains(['\r', '\n']) {
+            return Err("a command line cannot contain line breaks".into());
+        }
+
+        // request
+        send!(self, "{line}\r\n")?;
+        if let Some(body) = body {
+            self.writer.write_all(body)?;
+            self.writer.write_all(b"\r\n")?;
+            for interceptor in &mut self.interceptors {
+                interceptor.body_sent(body);
+            }
+        }
+        self.writer.flush()?;
+
+        // response, that cannot be checked against a command unknown to bsc
+        self.read_line_within("raw", None)?;
+        let line = self.buf.trim_end_matches("\r\n").to_string();
+        let mut words = line.split_ascii_whitespace();
+        let bytes = match (words.next(), words.last()) {
+            (Some("OK" | "FOUND" | "RESERVED"), Some(bytes)) => bytes.parse::<u64>().ok(),
+            _ => None,
+        };
+        let data = match bytes {
+            Some(bytes) => {
+                let mut data = Vec::with_capacity(bytes as usize);
+                (&mut self.reader).take(bytes).read_to_end(&mut data)?;
+                self.reader.read_line(&mut self.buf)?; // read ending \r\n
+                Some(data)
+            }
+            None => None,
+        };
+        Ok(RawResponse { line, data })
+    }
+
+    /// Watches `tubes`, and only them, then reserves jobs and passes them to `handler`
+    /// until `shutdown` is triggered. An empty `tubes` keeps the current watch list.
+    ///
+    /// A job is deleted when `handler` returns `Ok`, and buried with its priority when
+    /// it returns an error, which the handler is expected to log. The signal is checked
+    /// between jobs and at least every second while waiting for one, so the job being
+    /// handled is always finished before returning.
+    pub fn consume<F, E>(
+        &mut self,
+        tubes: &[&str],
+        shutdown: &ShutdownSignal,
+        mut handler: F,
+    ) -> Result<()>
+    where
+        F: FnMut(Id, Body) -> std::result::Result<(), E>,
+    {
+        if !tubes.is_empty() {
+            for tube in tubes {
+                self.watch(tube)?;
+            }
+            let others: Vec<String> = (self.list_tube_watched()?.into_iter())
+                .filter(|tube| !tubes.contains(tube))
+                .map(String::from)
+                .collect();
+            for tube in others {
+                self.ignore(&tube)?;
+            }
+        }
+
+        while !shutdown.is_triggered() {
+            let (id, data) = match self.reserve(Some(SHUTDOWN_POLL_INTERVAL))? {
+                ReserveResponse::Reserved { id, data } => (id, data),
+                ReserveResponse::DeadlineSoon | ReserveResponse::TimedOut => continue,
+            };
+            if handler(id, data).is_ok() {
+                self.delete(id)?;
+            } else if let StatsJobResponse::Ok(stats) = self.stats_job(id)? {
+                self.bury(id, stats.pri)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Enumerates the jobs of the server by probing each id of `ids` with `peek`, as
+    /// beanstalkd has no command to list jobs. `f` is called for every id with the
+    /// `peek` response and, if enabled in `opts`, the job stats. Returns the number of
+    /// jobs found.
+    ///
+    /// Job ids are allocated in increasing order, so the ids of the current jobs are
+    /// between the oldest job and `total-jobs` (see [`stats`](Self::stats)).
+    pub fn scan_jobs<I, F>(&mut self, ids: I, opts: ScanOptions, mut f: F) -> Result<usize>
+    where
+        I: IntoIterator<Item = Id>,
+        F: FnMut(Id, PeekResponse, Option<StatsJob>),
+    {
+        let interval = opts.max_per_sec.map(|rate| Duration::from_secs(1) / rate);
+        let mut next_probe = Instant::now();
+        let mut found = 0;
+        for id in ids {
+            if let Some(interval) = interval {
+                thread::sleep(next_probe.saturating_duration_since(Instant::now()));
+                next_probe += interval;
+            }
+            let res = self.peek(id)?;
+            let stats = match res {
+                PeekResponse::Found { .. } if opts.stats => match self.stats_job(id)? {
+                    StatsJobResponse::Ok(stats) => Some(stats),
+                    StatsJobResponse::NotFound => None,
+                },
+                _ => None,
+            };
+            if let PeekResponse::Found { .. } = res {
+                found += 1;
+            }
+            f(id, res, stats);
         }
+        Ok(found)
+    }
+
+    /// Moves the jobs of the `src` tube accepted by `filter` to the `dst` tube, e.g. to
+    /// rename a tube or to recover from producers putting jobs in the wrong tube.
+    /// Returns the number of jobs moved.
+    ///
+    /// Each job is reserved from `src`, put into `dst` with the same priority and TTR
+    /// (read with `stats-job`), then deleted from `src`. The jobs rejected by `filter`
+    /// stay reserved until the end, and are then released with their priority.
+    /// Delayed and buried jobs are not moved, kick them first if needed.
+    ///
+    /// At most `limit` jobs are moved if given, and `progress` is called with the
+    /// number of jobs moved so far after each one.
+    ///
+    /// Fails if `src` and `dst` are the same tube, whose jobs would otherwise be moved
+    /// over and over. The used tube and the watched tubes are restored afterwards, even
+    /// on error.
+    pub fn drain_tube<F, P>(
+        &mut self,
+        src: &str,
+        dst: &str,
+        limit: Option<usize>,
+        filter: F,
+        progress: P,
+    ) -> Result<usize>
+    where
+        F: FnMut(Id, &Body) -> bool,
+        P: FnMut(usize),
+    {
+        // both are namespaced alike
+        if src == dst {
+            return Err(format!("cannot drain the tube {src:?} into itself").into());
+        }
+        let used = self.list_tube_used()?.to_string();
+        let watched: Vec<String> = (self.list_tube_watched()?.into_iter())
+            .map(String::from)
+            .collect();
+        self.watch(src)?;
+        for tube in watched.iter().filter(|tube| *tube != src) {
+            self.ignore(tube)?;
+        }
+        self.use_(dst)?;
+
+        let mut rejected = Vec::new();
+        let res = self.drain_jobs(limit, &mut rejected, filter, progress);
+
+        // restore the connection state whatever happened, reporting the first error
+        let restored = self.undrain(src, &used, &watched, rejected);
+        let moved = res?;
+        restored?;
+        Ok(moved)
+    }
+
+    /// Releases the jobs rejected by [`drain_tube`](Self::drain_tube), and goes back
+    /// to using `used` and watching `watched`.
+    fn undrain(
+        &mut self,
+        src: &str,
+        used: &str,
+        watched: &[String],
+        rejected: Vec<(Id, u32)>,
+    ) -> Result<()> {
+        for (id, pri) in rejected {
+            self.release(id, pri, Duration::ZERO)?;
+        }
+        self.use_(used)?;
+        for tube in watched {
+            self.watch(tube)?;
+        }
+        if !watched.iter().any(|tube| tube == src) {
+            self.ignore(src)?;
+        }
+        Ok(())
+    }
+
+    fn drain_jobs<F, P>(
+        &mut self,
+        limit: Option<usize>,
+        rejected: &mut Vec<(Id, u32)>,
+        mut filter: F,
+        mut progress: P,
+    ) -> Result<usize>
+    where
+        F: FnMut(Id, &Body) -> bool,
+        P: FnMut(usize),
+    {
+        let mut moved = 0;
+        while limit.is_none_or(|limit| moved < limit) {
+            let (id, data) = match self.reserve_once(Some(Duration::ZERO))? {
+                ReserveResponse::Reserved { id, data } => (id, data),
+                ReserveResponse::DeadlineSoon => {
+                    if !self.handle_deadline_soon()? {
+                        return Err("DEADLINE_SOON for a job reserved by this connection \
+                            but unknown to the client"
+                            .into());
+                    }
+                    continue;
+                }
+                ReserveResponse::TimedOut => break,
+            };
+            let stats = match self.stats_job(id)? {
+                StatsJobResponse::Ok(stats) => stats,
+                StatsJobResponse::NotFound => continue,
+            };
+            if !filter(id, &data) {
+                rejected.push((id, stats.pri));
+                continue;
+            }
+            let ttr = Duration::from_secs(stats.ttr.into());
+            match self.put(stats.pri, Duration::ZERO, ttr, &data.into_bytes()?)? {
+                PutResponse::Inserted(_) | PutResponse::Buried(_) => {}
+                res => {
+                    rejected.push((id, stats.pri));
+                    return Err(
+                        format!("unable to put job {id} into the destination: {res:?}").into(),
+                    );
+                }
+            }
+            self.delete(id)?;
+            moved += 1;
+            progress(moved);
+        }
+        Ok(moved)
     }
 
     /// The quit command simply closes the connection. Its form is:
     ///
-    ///      quit\r\n
+    /// ```text
+    /// quit\r\n
+    /// ```
     pub fn quit(mut self) -> Result<()> {
-        write!(self.writer, "quit\r\n")?;
+        send!(self, "quit\r\n")?;
         Ok(())
     }
 }
 
-#[derive(Debug)]
+/// Applies the socket options of `opts` to `tcp`.
+fn tune(tcp: &TcpStream, opts: &TcpOptions) -> io::Result<()> {
+    let socket = socket2::SockRef::from(tcp);
+    if let Some(nodelay) = opts.nodelay {
+        socket.set_nodelay(nodelay)?;
+    }
+    if let Some(idle) = opts.keepalive {
+        socket.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(idle))?;
+    }
+    if let Some(size) = opts.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = opts.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    Ok(())
+}
+
+/// Removes the namespace prefix from `tube`, if present.
+#[inline]
+fn strip_namespace<'a>(namespace: &str, tube: &'a str) -> &'a str {
+    tube.strip_prefix(namespace).unwrap_or(tube)
+}
+
+/// Buffers `reader`, raising `poisoned` when a read times out.
+fn timeout_reader<R>(reader: R, poisoned: &Arc<AtomicBool>) -> BufReader<Box<dyn Read + Send>>
+where
+    R: Read + Send + 'static,
+{
+    BufReader::new(Box::new(TimeoutReader {
+        inner: reader,
+        poisoned: Arc::clone(poisoned),
+    }))
+}
+
+/// Checks a command `line`, without its ending `\r\n`, against the limits of the
+/// protocol.
+fn check_limits(line: &str) -> Result<()> {
+    let mut words = line.split(' ');
+    let cmd = words.next().unwrap_or_default();
+    let exceeded = |limit, len, max| Error::WouldBeBadFormat {
+        cmd: cmd.to_string(),
+        limit,
+        len,
+        max,
+    };
+    if line.len() + 2 > MAX_LINE_LEN {
+        return Err(exceeded("command line", line.len() + 2, MAX_LINE_LEN));
+    }
+    if let ("use" | "watch" | "ignore" | "stats-tube" | "pause-tube", Some(tube)) =
+        (cmd, words.next())
+    {
+        if tube.len() > MAX_TUBE_NAME_LEN {
+            return Err(exceeded("tube name", tube.len(), MAX_TUBE_NAME_LEN));
+        }
+    }
+    Ok(())
+}
+
+/// Commands that old servers answer with `UNKNOWN_COMMAND`.
+const NEWER_COMMANDS: &[&str] = &["reserve-job", "kick-job"];
+
+/// Replies the protocol allows for each command, in addition to [`GENERIC_ERRORS`].
+fn allowed_replies(cmd: &str) -> &'static [&'static str] {
+    match cmd {
+        "put" => &[
+            "INSERTED",
+            "BURIED",
+            "EXPECTED_CRLF",
+            "JOB_TOO_BIG",
+            "DRAINING",
+        ],
+        "use" | "list-tube-used" => &["USING"],
+        "reserve" => &["RESERVED", "DEADLINE_SOON", "TIMED_OUT"],
+        "reserve-job" => &["RESERVED", "NOT_FOUND"],
+        "delete" => &["DELETED", "NOT_FOUND"],
+        "release" => &["RELEASED", "BURIED", "NOT_FOUND"],
+        "bury" => &["BURIED", "NOT_FOUND"],
+        "touch" => &["TOUCHED", "NOT_FOUND"],
+        "watch" => &["WATCHING"],
+        "ignore" => &["WATCHING", "NOT_IGNORED"],
+        "peek" | "peek-ready" | "peek-delayed" | "peek-buried" => &["FOUND", "NOT_FOUND"],
+        "kick" => &["KICKED"],
+        "kick-job" => &["KICKED", "NOT_FOUND"],
+        "stats-job" | "stats-tube" => &["OK", "NOT_FOUND"],
+        "stats" | "list-tubes" | "list-tubes-watched" => &["OK"],
+        "pause-tube" => &["PAUSED", "NOT_FOUND"],
+        _ => &[],
+    }
+}
+
+/// Reads a job body from `reader`. With a decompression `limit`, a body starting with
+/// a codec header is decompressed, and is an error if it decompresses to more than
+/// `limit` bytes.
+fn decode_body<R: Read>(
+    mut reader: R,
+    bytes: u64,
+    spool: bool,
+    limit: Option<u64>,
+) -> Result<Body> {
+    let mut head = Vec::with_capacity(HEADER_LEN);
+    if limit.is_some() {
+        (&mut reader)
+            .take(HEADER_LEN as u64)
+            .read_to_end(&mut head)?;
+    }
+    let (mut reader, max): (Box<dyn Read + '_>, _) = match (Codec::sniff(&head), limit) {
+        (Some(codec), Some(limit)) => (Box::new(codec.decoder(reader)?.take(limit + 1)), limit),
+        _ => (Box::new(head.as_slice().chain(reader)), u64::MAX),
+    };
+    let (len, body) = if spool {
+        let mut file = tempfile::tempfile()?;
+        (io::copy(&mut reader, &mut file)?, Body::Spooled(file))
+    } else {
+        let mut data = Vec::with_capacity(bytes as usize);
+        (reader.read_to_end(&mut data)? as u64, Body::from(data))
+    };
+    if len > max {
+        return Err(format!("the body decompresses to more than {max} bytes").into());
+    }
+    Ok(body)
+}
+
+/// Converts an absolute point in time to a delay from now, rounded up to the next
+/// second as beanstalkd only deals with whole seconds.
+#[inline]
+pub(crate) fn delay_until(when: SystemTime) -> Duration {
+    match when.duration_since(SystemTime::now()) {
+        Ok(delay) if delay.subsec_nanos() > 0 => Duration::from_secs(delay.as_secs() + 1),
+        Ok(delay) => delay,
+        Err(_) => Duration::ZERO,
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub enum PutResponse {
     /// Indicates success, `id` is the integer id of the new job.
     Inserted(Id),
@@ -659,7 +2007,20 @@ pub enum PutResponse {
     Draining,
 }
 
-#[derive(Debug)]
+/// Displays the reply line of the server, without the job body or stats.
+impl std::fmt::Display for PutResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Inserted(id) => write!(f, "INSERTED {id}"),
+            Self::Buried(id) => write!(f, "BURIED {id}"),
+            Self::ExpectedCrlf => write!(f, "EXPECTED_CRLF"),
+            Self::JobTooBig => write!(f, "JOB_TOO_BIG"),
+            Self::Draining => write!(f, "DRAINING"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub enum ReserveResponse {
     /// During the TTR of a reserved job, the last second is kept by the server as a
     /// safety margin, during which the client will not be made to wait for another
@@ -677,11 +2038,43 @@ pub enum ReserveResponse {
         /// a sequence of bytes of length `bytes` from the
         /// previous line. This is a verbatim copy of the bytes that were originally
         /// sent to the server in the put command for this job
-        data: Vec<u8>,
+        data: Body,
     },
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for ReserveResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DeadlineSoon => write!(f, "DEADLINE_SOON"),
+            Self::TimedOut => write!(f, "TIMED_OUT"),
+            Self::Reserved { id, .. } => write!(f, "RESERVED {id}"),
+        }
+    }
+}
+
+/// The reply to [`Beanstalk::raw_command`].
+#[derive(Debug, Serialize)]
+pub struct RawResponse {
+    /// The response line, without the ending `\r\n`.
+    pub line: String,
+    /// The data chunk following the response line, if any.
+    pub data: Option<Vec<u8>>,
+}
+
+impl std::fmt::Display for RawResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.line)
+    }
+}
+
+/// A job and its body, as reserved or peeked, see e.g. [`Beanstalk::try_reserve`].
+#[derive(Debug, Serialize)]
+pub struct Job {
+    pub id: Id,
+    pub data: Body,
+}
+
+#[derive(Debug, Serialize)]
 pub enum ReserveByIdResponse {
     /// If the job does not exist or reserved by a client or
     /// is not either ready, buried or delayed.
@@ -693,17 +2086,40 @@ pub enum ReserveByIdResponse {
         /// a sequence of bytes of length `bytes` from the
         /// previous line. This is a verbatim copy of the bytes that were originally
         /// sent to the server in the put command for this job
-        data: Vec<u8>,
+        data: Body,
     },
 }
 
+impl std::fmt::Display for ReserveByIdResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "NOT_FOUND"),
+            Self::Reserved { id, .. } => write!(f, "RESERVED {id}"),
+        }
+    }
+}
+
+impl ReserveByIdResponse {
+    /// The job reserved, `None` if not found, so that `?` and `if let` can be used.
+    pub fn found(self) -> Option<Job> {
+        match self {
+            Self::Reserved { id, data } => Some(Job { id, data }),
+            Self::NotFound => None,
+        }
+    }
+}
+
 #[inline]
-fn read_reserved(input: &str) -> Result<(Id, u64)> {
+fn read_reserved(
+    input: &str,
+    cmd: &'static str,
+    arg: Option<&dyn fmt::Display>,
+) -> Result<(Id, u64)> {
     if let Some(input) = input.strip_prefix("RESERVED ") {
         let mut iter = input.split_ascii_whitespace();
         let id = iter
             .next()
-            .map(|s| s.parse::<u32>())
+            .map(|s| s.parse::<Id>())
             .ok_or("missing 'id' in RESERVED response")??;
         let bytes = iter
             .next()
@@ -712,10 +2128,10 @@ fn read_reserved(input: &str) -> Result<(Id, u64)> {
 
         return Ok((id, bytes));
     }
-    Err(input.into())
+    Err(unexpected(cmd, arg, input))
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub enum DeleteResponse {
     /// Indicate success
     Deleted,
@@ -725,7 +2141,16 @@ pub enum DeleteResponse {
     NotFound,
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for DeleteResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Deleted => write!(f, "DELETED"),
+            Self::NotFound => write!(f, "NOT_FOUND"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub enum ReleaseResponse {
     /// Indicate success.
     Released,
@@ -736,7 +2161,17 @@ pub enum ReleaseResponse {
     NotFound,
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for ReleaseResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Released => write!(f, "RELEASED"),
+            Self::Buried => write!(f, "BURIED"),
+            Self::NotFound => write!(f, "NOT_FOUND"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub enum BuryResponse {
     /// Indicate success
     Buried,
@@ -744,7 +2179,16 @@ pub enum BuryResponse {
     NotFound,
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for BuryResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Buried => write!(f, "BURIED"),
+            Self::NotFound => write!(f, "NOT_FOUND"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub enum TouchResponse {
     /// Indicate success
     Touched,
@@ -752,7 +2196,16 @@ pub enum TouchResponse {
     NotFound,
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for TouchResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Touched => write!(f, "TOUCHED"),
+            Self::NotFound => write!(f, "NOT_FOUND"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub enum IgnoreResponse {
     /// Is the integer number of tubes currently in the watch list.
     Count(usize),
@@ -760,7 +2213,16 @@ pub enum IgnoreResponse {
     NotIgnored,
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for IgnoreResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Count(count) => write!(f, "WATCHING {count}"),
+            Self::NotIgnored => write!(f, "NOT_IGNORED"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub enum PeekResponse {
     /// If the requested job doesn't exist or there are no jobs in
     /// the requested state.
@@ -771,17 +2233,36 @@ pub enum PeekResponse {
         id: Id,
         /// a sequence of bytes of length `bytes` from the
         /// previous line.
-        data: Vec<u8>,
+        data: Body,
     },
 }
 
+impl std::fmt::Display for PeekResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "NOT_FOUND"),
+            Self::Found { id, .. } => write!(f, "FOUND {id}"),
+        }
+    }
+}
+
+impl PeekResponse {
+    /// The job peeked, `None` if not found, so that `?` and `if let` can be used.
+    pub fn found(self) -> Option<Job> {
+        match self {
+            Self::Found { id, data } => Some(Job { id, data }),
+            Self::NotFound => None,
+        }
+    }
+}
+
 #[inline]
-fn read_found(input: &str) -> Result<(Id, u64)> {
+fn read_found(input: &str, cmd: &'static str, arg: Option<&dyn fmt::Display>) -> Result<(Id, u64)> {
     if let Some(input) = input.strip_prefix("FOUND ") {
         let mut iter = input.split_ascii_whitespace();
         let id = iter
             .next()
-            .map(|s| s.parse::<u32>())
+            .map(|s| s.parse::<Id>())
             .ok_or("missing 'id' in FOUND response")??;
         let bytes = iter
             .next()
@@ -790,10 +2271,10 @@ fn read_found(input: &str) -> Result<(Id, u64)> {
 
         return Ok((id, bytes));
     }
-    Err(input.into())
+    Err(unexpected(cmd, arg, input))
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub enum KickJobResponse {
     /// If the job does not exist or is not in a kickable state. This
     /// can also happen upon internal errors.
@@ -802,7 +2283,16 @@ pub enum KickJobResponse {
     Kicked,
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for KickJobResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "NOT_FOUND"),
+            Self::Kicked => write!(f, "KICKED"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub enum StatsJobResponse {
     /// Indicate success
     ///
@@ -812,15 +2302,43 @@ pub enum StatsJobResponse {
     NotFound,
 }
 
+impl std::fmt::Display for StatsJobResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok(_) => write!(f, "OK"),
+            Self::NotFound => write!(f, "NOT_FOUND"),
+        }
+    }
+}
+
+impl StatsJobResponse {
+    /// The stats of the job, `None` if not found, so that `?` and `if let` can be used.
+    pub fn found(self) -> Option<StatsJob> {
+        match self {
+            Self::Ok(stats) => Some(stats),
+            Self::NotFound => None,
+        }
+    }
+}
+
 #[inline]
-fn read_ok(input: &str) -> Result<u64> {
+fn read_ok(input: &str, cmd: &'static str, arg: Option<&dyn fmt::Display>) -> Result<u64> {
     if let Some(input) = input.strip_prefix("OK ") {
         return Ok(input.parse::<u64>()?);
     }
-    Err(input.into())
+    Err(unexpected(cmd, arg, input))
+}
+
+/// The error for a `reply` to `cmd` that is none of the replies it expects.
+fn unexpected(cmd: &'static str, arg: Option<&dyn fmt::Display>, reply: &str) -> Error {
+    Error::Reply {
+        cmd,
+        arg: arg.map(|arg| arg.to_string()),
+        reply: reply.to_string(),
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub enum StatsTubeResponse {
     /// Indicate success
     ///
@@ -830,10 +2348,228 @@ pub enum StatsTubeResponse {
     NotFound,
 }
 
-#[derive(Debug)]
+impl std::fmt::Display for StatsTubeResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Ok(_) => write!(f, "OK"),
+            Self::NotFound => write!(f, "NOT_FOUND"),
+        }
+    }
+}
+
+impl StatsTubeResponse {
+    /// The stats of the tube, `None` if not found, so that `?` and `if let` can be used.
+    pub fn found(self) -> Option<StatsTube> {
+        match self {
+            Self::Ok(stats) => Some(stats),
+            Self::NotFound => None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub enum PauseTubeResponse {
     /// Indicate success
     Paused,
     /// If the tube does not exist.
     NotFound,
 }
+
+impl std::fmt::Display for PauseTubeResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Paused => write!(f, "PAUSED"),
+            Self::NotFound => write!(f, "NOT_FOUND"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit_of(line: &str) -> Option<(String, &'static str, usize, usize)> {
+        match check_limits(line) {
+            Ok(()) => None,
+            Err(Error::WouldBeBadFormat {
+                cmd,
+                limit,
+                len,
+                max,
+            }) => Some((cmd, limit, len, max)),
+            Err(err) => panic!("unexpected error {err}"),
+        }
+    }
+
+    #[test]
+    fn line_length() {
+        // the limit counts the ending \r\n
+        let line = format!("put 0 0 60 {}", "1".repeat(MAX_LINE_LEN - 13));
+        assert_eq!(line.len() + 2, MAX_LINE_LEN);
+        assert_eq!(limit_of(&line), None);
+
+        let line = format!("{line}1");
+        assert_eq!(
+            limit_of(&line),
+            Some((
+                "put".to_string(),
+                "command line",
+                MAX_LINE_LEN + 1,
+                MAX_LINE_LEN
+            ))
+        );
+    }
+
+    #[test]
+    fn tube_name_length() {
+        let longest = "t".repeat(MAX_TUBE_NAME_LEN);
+        let too_long = "t".repeat(MAX_TUBE_NAME_LEN + 1);
+        for cmd in ["use", "watch", "ignore", "stats-tube"] {
+            assert_eq!(limit_of(&format!("{cmd} {longest}")), None);
+            assert_eq!(
+                limit_of(&format!("{cmd} {too_long}")),
+                Some((
+                    cmd.to_string(),
+                    "tube name",
+                    MAX_TUBE_NAME_LEN + 1,
+                    MAX_TUBE_NAME_LEN
+                ))
+            );
+        }
+        assert_eq!(
+            limit_of(&format!("pause-tube {too_long} 10")).map(|(_, limit, ..)| limit),
+            Some("tube name")
+        );
+        // only the first argument of the tube commands is a tube
+        assert_eq!(limit_of(&format!("kick {}", "1".repeat(210))), None);
+    }
+
+    fn decoded(body: &[u8], limit: Option<u64>) -> Result<Vec<u8>> {
+        Ok(decode_body(body, body.len() as u64, false, limit)?
+            .into_bytes()
+            .unwrap())
+    }
+
+    #[test]
+    fn plain_bodies_starting_like_a_header() {
+        // left as they are unless decompression is enabled
+        for body in [&b"\0bsc\x01hello"[..], b"\0bsc\x02", b"\0bsc"] {
+            assert_eq!(decoded(body, None).unwrap(), body);
+        }
+        // not a known codec, or too short to be a header
+        for body in [&b"\0bsc\x03hello"[..], b"\0bsc", b"\0b", b""] {
+            assert_eq!(decoded(body, Some(100)).unwrap(), body);
+        }
+        assert!(decoded(b"\0bsc\x01hello", Some(100)).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn decompression_limit() {
+        let encoded = Codec::Gzip.encode(&[b'a'; 1000]).unwrap();
+        assert_eq!(decoded(&encoded, Some(1000)).unwrap(), [b'a'; 1000]);
+        assert!(decoded(&encoded, Some(999)).is_err());
+    }
+
+    #[test]
+    fn unexpected_replies() {
+        let replies = io::Cursor::new(&b"TOUCHED\r\nOK 10\r\n"[..]);
+        let mut bsc = Beanstalk::from_parts(replies, io::sink());
+        match bsc.delete(5) {
+            Err(Error::Reply { cmd, arg, reply }) => {
+                assert_eq!(
+                    (cmd, arg.as_deref(), reply.as_str()),
+                    ("delete", Some("5"), "TOUCHED")
+                )
+            }
+            res => panic!("unexpected result {res:?}"),
+        }
+        match bsc.list_tube_used() {
+            Err(Error::Reply { cmd, arg, reply }) => {
+                assert_eq!(
+                    (cmd, arg, reply.as_str()),
+                    ("list-tube-used", None, "OK 10")
+                )
+            }
+            res => panic!("unexpected result {res:?}"),
+        }
+    }
+
+    #[test]
+    fn lazy_connection_retried() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            // the first connection is given up on by the client
+            drop(listener.accept().unwrap());
+            let (conn, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(conn.try_clone().unwrap());
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            (&conn).write_all(b"USING jobs\r\n").unwrap();
+            line
+        });
+
+        let mut bsc = Beanstalk::lazy(addr.to_string());
+        // connects, but is over the longest idle time of keepalive
+        let invalid = TcpOptions::default().keepalive(Duration::from_secs(1 << 20));
+        bsc.tune_tcp(&invalid).unwrap();
+        assert!(matches!(bsc.use_("jobs"), Err(Error::Io(_))));
+
+        bsc.tune_tcp(&TcpOptions::default()).unwrap();
+        assert_eq!(bsc.use_("jobs").unwrap(), "jobs");
+        // the command of the failed attempt is not sent again
+        assert_eq!(server.join().unwrap(), "use jobs\r\n");
+    }
+
+    #[test]
+    fn stats_all_tubes_poisoned_by_an_error() {
+        let tubes = "---\n- a\n- b\n";
+        let replies = format!(
+            "OK {}\r\n{tubes}\r\nINTERNAL_ERROR\r\nOK 4\r\n---\n\r\n",
+            tubes.len()
+        );
+        let mut bsc = Beanstalk::from_parts(io::Cursor::new(replies.into_bytes()), io::sink());
+        match bsc.stats_all_tubes() {
+            Err(Error::Reply { cmd, arg, reply }) => assert_eq!(
+                (cmd, arg.as_deref(), reply.as_str()),
+                ("stats-tube", Some("a"), "INTERNAL_ERROR")
+            ),
+            res => panic!("unexpected result {res:?}"),
+        }
+        // rather than reading the stats of b as the response to list-tubes
+        assert!(matches!(bsc.list_tubes(), Err(Error::Bs(_))));
+    }
+
+    #[test]
+    fn deadline_soon_handled_for_the_held_jobs() {
+        let replies = &b"DEADLINE_SOON\r\nTOUCHED\r\nTIMED_OUT\r\nDEADLINE_SOON\r\n"[..];
+        let mut bsc = Beanstalk::from_parts(io::Cursor::new(replies), io::sink());
+        bsc.auto_deadline_soon(true);
+        bsc.held.push(7);
+        let res = bsc.reserve(Some(Duration::ZERO)).unwrap();
+        assert!(matches!(res, ReserveResponse::TimedOut), "{res:?}");
+
+        // returned right away when no job is known to be held
+        bsc.held.clear();
+        let start = Instant::now();
+        let res = bsc.reserve(None).unwrap();
+        assert!(matches!(res, ReserveResponse::DeadlineSoon), "{res:?}");
+        assert!(start.elapsed() < SAFETY_MARGIN);
+    }
+
+    #[test]
+    fn job_kept_when_its_body_cannot_be_read() {
+        let body = b"\0bsc\x01hello";
+        let mut response = format!("RESERVED 7 {}\r\n", body.len()).into_bytes();
+        response.extend_from_slice(body);
+        response.extend_from_slice(b"\r\n");
+        let mut bsc = Beanstalk::from_parts(io::Cursor::new(response), io::sink());
+        bsc.decompress_up_to(Some(100));
+        match bsc.reserve_once(None) {
+            Err(Error::Body { id: 7, .. }) => {}
+            res => panic!("unexpected result {res:?}"),
+        }
+        assert_eq!(bsc.held, [7]);
+    }
+}
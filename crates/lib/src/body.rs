@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use serde::de::{self, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize};
+
+/// The body of a job, as returned by the `reserve` and `peek` commands.
+///
+/// Beanstalkd does not care about the content of a job, so the body is
+/// classified once when it is read from the connection:
+///
+///  - `Utf8` when the bytes are valid UTF-8
+///  - `Bytes` otherwise
+///  - `Spooled` when the body is bigger than the spool threshold of the client
+///    (see [`Beanstalk::spool_above`](crate::Beanstalk::spool_above)), in which case
+///    it has been written to an anonymous temporary file instead of being kept in memory
+#[derive(Debug)]
+pub enum Body {
+    Utf8(String),
+    Bytes(Vec<u8>),
+    Spooled(File),
+}
+
+impl Body {
+    /// Returns the body as a `&str` if it is valid UTF-8 and held in memory.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Body::Utf8(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Returns the body as a `&[u8]` if it is held in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Utf8(s) => Some(s.as_bytes()),
+            Body::Bytes(b) => Some(b),
+            Body::Spooled(_) => None,
+        }
+    }
+
+    /// Consumes the body and returns its bytes, reading them back from the
+    /// temporary file if the body has been spooled.
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Body::Utf8(s) => Ok(s.into_bytes()),
+            Body::Bytes(b) => Ok(b),
+            Body::Spooled(mut file) => {
+                let mut buf = Vec::new();
+                file.seek(SeekFrom::Start(0))?;
+                file.read_to_end(&mut buf)?;
+                Ok(buf)
+            }
+        }
+    }
+
+    /// Same as [`into_bytes`](Self::into_bytes), as a [`bytes::Bytes`] that can be
+    /// cloned cheaply to hand the body to several tasks. In-memory bodies are not
+    /// copied. A `Bytes` can be put back as is, as it dereferences to `&[u8]`.
+    #[cfg(feature = "bytes")]
+    pub fn into_shared(self) -> io::Result<bytes::Bytes> {
+        self.into_bytes().map(bytes::Bytes::from)
+    }
+
+    /// Writes the whole body to `w`, returning the number of bytes written.
+    pub fn write_to<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<u64> {
+        match self {
+            Body::Utf8(s) => w.write_all(s.as_bytes()).map(|_| s.len() as u64),
+            Body::Bytes(b) => w.write_all(b).map(|_| b.len() as u64),
+            Body::Spooled(file) => {
+                let mut file: &File = file;
+                file.seek(SeekFrom::Start(0))?;
+                io::copy(&mut file, w)
+            }
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(value: Vec<u8>) -> Self {
+        match String::from_utf8(value) {
+            Ok(s) => Body::Utf8(s),
+            Err(err) => Body::Bytes(err.into_bytes()),
+        }
+    }
+}
+
+impl From<String> for Body {
+    fn from(value: String) -> Self {
+        Body::Utf8(value)
+    }
+}
+
+#[cfg(feature = "bytes")]
+impl From<bytes::Bytes> for Body {
+    fn from(value: bytes::Bytes) -> Self {
+        Body::from(Vec::from(value))
+    }
+}
+
+/// Text bodies are serialized as strings, binary bodies as sequences of bytes.
+impl Serialize for Body {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            Body::Utf8(s) => serializer.serialize_str(s),
+            Body::Bytes(b) => b.serialize(serializer),
+            Body::Spooled(file) => {
+                let mut file: &File = file;
+                let mut buf = Vec::new();
+                file.seek(SeekFrom::Start(0))
+                    .and_then(|_| file.read_to_end(&mut buf))
+                    .map_err(serde::ser::Error::custom)?;
+                Body::from(buf).serialize(serializer)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Body {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        deserializer.deserialize_any(BodyVisitor)
+    }
+}
+
+struct BodyVisitor;
+
+impl<'de> Visitor<'de> for BodyVisitor {
+    type Value = Body;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a string or a sequence of bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Body, E> {
+        Ok(Body::Utf8(v.to_string()))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Body, E> {
+        Ok(Body::Utf8(v))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Body, E> {
+        Ok(Body::Bytes(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Body, E> {
+        Ok(Body::Bytes(v))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Body, A::Error> {
+        let mut buf = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(b) = seq.next_element::<u8>()? {
+            buf.push(b);
+        }
+        Ok(Body::Bytes(buf))
+    }
+}
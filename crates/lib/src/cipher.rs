@@ -0,0 +1,151 @@
+use crate::{Error, Result};
+
+/// Encrypts job bodies on put and decrypts them on reserve and peek, see
+/// [`Beanstalk::encrypt_with`](crate::Beanstalk::encrypt_with).
+///
+/// Implementations only deal with the cryptography and the keys. The client handles
+/// the framing: bodies are stored in beanstalkd as the nonce directly followed by the
+/// ciphertext, and `nonce_len` is used to split them back.
+pub trait PayloadCipher: Send {
+    /// Length in bytes of the nonces returned by `encrypt`.
+    fn nonce_len(&self) -> usize;
+
+    /// Encrypts `plaintext` with a fresh nonce, returning `(nonce, ciphertext)`.
+    fn encrypt(&self, plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error>;
+
+    /// Decrypts `ciphertext` using the `nonce` it has been encrypted with.
+    fn decrypt(&self, nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// Encrypts `data` and frames it as `nonce || ciphertext`.
+pub(crate) fn seal(cipher: &dyn PayloadCipher, data: &[u8]) -> Result<Vec<u8>> {
+    let (mut nonce, ciphertext) = cipher.encrypt(data)?;
+    if nonce.len() != cipher.nonce_len() {
+        return Err("cipher returned a nonce of unexpected length".into());
+    }
+    nonce.extend_from_slice(&ciphertext);
+    Ok(nonce)
+}
+
+/// Splits a `nonce || ciphertext` frame and decrypts it.
+pub(crate) fn open(cipher: &dyn PayloadCipher, framed: &[u8]) -> Result<Vec<u8>> {
+    if framed.len() < cipher.nonce_len() {
+        return Err("encrypted job body is shorter than the nonce".into());
+    }
+    let (nonce, ciphertext) = framed.split_at(cipher.nonce_len());
+    cipher.decrypt(nonce, ciphertext)
+}
+
+/// AES-256-GCM [`PayloadCipher`], with random 96-bit nonces.
+#[cfg(feature = "aes-gcm")]
+pub struct AesGcmCipher(aes_gcm::Aes256Gcm);
+
+#[cfg(feature = "aes-gcm")]
+impl AesGcmCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        use aes_gcm::KeyInit;
+        Self(aes_gcm::Aes256Gcm::new(key.into()))
+    }
+}
+
+#[cfg(feature = "aes-gcm")]
+impl PayloadCipher for AesGcmCipher {
+    fn nonce_len(&self) -> usize {
+        12
+    }
+
+    fn encrypt(&self, plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        use aes_gcm::aead::{Aead, AeadCore, OsRng};
+        let nonce = aes_gcm::Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| Error::from("unable to encrypt job body"))?;
+        Ok((nonce.to_vec(), ciphertext))
+    }
+
+    fn decrypt(&self, nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+        use aes_gcm::aead::Aead;
+        self.0
+            .decrypt(aes_gcm::Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| Error::from("unable to decrypt job body"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// XORs with its nonce, enough to check the framing.
+    struct Xor {
+        nonce: Vec<u8>,
+    }
+
+    impl PayloadCipher for Xor {
+        fn nonce_len(&self) -> usize {
+            3
+        }
+
+        fn encrypt(&self, plaintext: &[u8]) -> Result<(Vec<u8>, Vec<u8>), Error> {
+            Ok((self.nonce.clone(), xor(&self.nonce, plaintext)))
+        }
+
+        fn decrypt(&self, nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>, Error> {
+            Ok(xor(nonce, ciphertext))
+        }
+    }
+
+    fn xor(nonce: &[u8], data: &[u8]) -> Vec<u8> {
+        (data.iter().zip(nonce.iter().cycle()))
+            .map(|(byte, key)| byte ^ key)
+            .collect()
+    }
+
+    #[test]
+    fn frames_nonce_then_ciphertext() {
+        let cipher = Xor {
+            nonce: vec![1, 2, 3],
+        };
+        let framed = seal(&cipher, b"hello").unwrap();
+        assert_eq!(&framed[..3], [1, 2, 3]);
+        assert_eq!(&framed[3..], xor(&[1, 2, 3], b"hello"));
+        assert_eq!(open(&cipher, &framed).unwrap(), b"hello");
+
+        // an empty body is just the nonce
+        let framed = seal(&cipher, b"").unwrap();
+        assert_eq!(framed, [1, 2, 3]);
+        assert_eq!(open(&cipher, &framed).unwrap(), b"");
+    }
+
+    #[test]
+    fn rejects_bad_frames() {
+        let cipher = Xor { nonce: vec![1, 2] };
+        let err = seal(&cipher, b"hello").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cipher returned a nonce of unexpected length"
+        );
+
+        let err = open(&cipher, &[1, 2]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "encrypted job body is shorter than the nonce"
+        );
+    }
+
+    #[cfg(feature = "aes-gcm")]
+    #[test]
+    fn aes_gcm_round_trip() {
+        let cipher = AesGcmCipher::new(&[7; 32]);
+        let framed = seal(&cipher, b"hello").unwrap();
+        assert_eq!(framed.len(), 12 + 5 + 16);
+        assert_eq!(open(&cipher, &framed).unwrap(), b"hello");
+        // a fresh nonce each time
+        assert_ne!(seal(&cipher, b"hello").unwrap(), framed);
+
+        let mut tampered = framed;
+        tampered[12] ^= 1;
+        assert!(open(&cipher, &tampered).is_err());
+        assert!(open(&AesGcmCipher::new(&[8; 32]), &seal(&cipher, b"hi").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::io::{self, Read};
+
+/// Every compressed body starts with these bytes, followed by one byte identifying
+/// the [`Codec`] used. Other clients can rely on this header to decode bodies put
+/// by `bsc`.
+pub const MAGIC: &[u8; 4] = b"\0bsc";
+
+/// Length of the header prepended to compressed bodies: [`MAGIC`] and the codec byte.
+pub const HEADER_LEN: usize = MAGIC.len() + 1;
+
+/// The size compressed bodies may decompress to by default, see
+/// [`Beanstalk::decompress_up_to`](crate::Beanstalk::decompress_up_to).
+pub const DEFAULT_DECOMPRESS_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// Compression algorithms for job bodies, see
+/// [`Beanstalk::compress_above`](crate::Beanstalk::compress_above).
+///
+/// Each codec is only usable when its feature (`gzip` or `zstd`) is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Zstd,
+}
+
+impl Codec {
+    fn id(self) -> u8 {
+        match self {
+            Codec::Gzip => 1,
+            Codec::Zstd => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Codec::Gzip),
+            2 => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Returns the codec declared by the header of `data`, if any.
+    pub fn sniff(data: &[u8]) -> Option<Codec> {
+        match data.strip_prefix(MAGIC) {
+            Some(rest) => rest.first().copied().and_then(Codec::from_id),
+            None => None,
+        }
+    }
+
+    /// Compresses `data` and prepends the header.
+    pub fn encode(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(HEADER_LEN + data.len() / 2);
+        out.extend_from_slice(MAGIC);
+        out.push(self.id());
+        match self {
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => {
+                use std::io::Write;
+                let mut encoder =
+                    flate2::write::GzEncoder::new(out, flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                zstd::stream::copy_encode(data, &mut out, 0)?;
+                Ok(out)
+            }
+            #[allow(unreachable_patterns)]
+            _ => Err(self.disabled()),
+        }
+    }
+
+    /// Wraps `reader`, which must be positioned right after the header, so that it
+    /// yields the decompressed bytes.
+    pub fn decoder<'a, R: Read + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => Ok(Box::new(flate2::read::GzDecoder::new(reader))),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(reader)?)),
+            #[allow(unreachable_patterns)]
+            _ => {
+                drop(reader);
+                Err(self.disabled())
+            }
+        }
+    }
+
+    #[allow(dead_code)]
+    fn disabled(self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{self:?} codec is not enabled, see the crate features"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sniffs_the_header() {
+        assert_eq!(Codec::sniff(b"\0bsc\x01rest"), Some(Codec::Gzip));
+        assert_eq!(Codec::sniff(b"\0bsc\x02"), Some(Codec::Zstd));
+        // unknown codec, truncated header, or a plain body
+        assert_eq!(Codec::sniff(b"\0bsc\x03rest"), None);
+        assert_eq!(Codec::sniff(b"\0bsc"), None);
+        assert_eq!(Codec::sniff(b"\0bs"), None);
+        assert_eq!(Codec::sniff(b"hello"), None);
+        assert_eq!(Codec::sniff(b""), None);
+    }
+
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    fn round_trip(codec: Codec) {
+        let data = b"hello hello hello hello hello".repeat(100);
+        let encoded = codec.encode(&data).unwrap();
+        assert_eq!(&encoded[..MAGIC.len()], MAGIC);
+        assert_eq!(Codec::sniff(&encoded), Some(codec));
+        assert!(encoded.len() < data.len());
+
+        let mut decoded = Vec::new();
+        let mut decoder = codec.decoder(&encoded[HEADER_LEN..]).unwrap();
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzip_round_trip() {
+        round_trip(Codec::Gzip);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        round_trip(Codec::Zstd);
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn disabled_codec() {
+        let err = Codec::Gzip.encode(b"hello").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(Codec::Gzip.decoder(&b""[..]).is_err());
+    }
+}
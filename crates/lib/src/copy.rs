@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use crate::beanstalk::*;
+use crate::body::Body;
+use crate::ops::BeanstalkOps;
+use crate::Error;
+
+/// The outcome of [`copy_jobs`].
+#[derive(Debug, Default)]
+pub struct CopyReport {
+    /// The jobs copied, as `(source id, destination id)` pairs.
+    pub copied: Vec<(Id, Id)>,
+    /// The jobs refused by the destination, with its reply as an [`Error::Reply`].
+    /// They are left in the source.
+    pub failed: Vec<(Id, Error)>,
+}
+
+/// Copies the ready and delayed jobs of `tube` from the `src` server to the same tube
+/// of the `dst` server, e.g. to migrate a queue to another host. At most `limit` jobs
+/// are copied if given.
+///
+/// The jobs keep their priority, TTR and remaining delay. Each job is deleted from
+/// `src` once it has been put into `dst`, so that an interrupted copy can be resumed
+/// by calling this again. A job is only copied twice if the copy is interrupted
+/// between the put and the delete.
+///
+/// A job refused by `dst` (`JOB_TOO_BIG`, `DRAINING`, or `BURIED` when it runs out of
+/// memory, the copy then being buried there) is reported in [`CopyReport::failed`]
+/// and left in `src`. Other errors, such as a lost connection to either server, abort
+/// the copy and are returned, the jobs still held being released first. So is
+/// `DEADLINE_SOON` for a job `src` reserved before the copy, which it should not hold.
+/// Buried jobs are not copied, kick them first if needed.
+///
+/// The tubes used and watched by both clients are restored afterwards.
+pub fn copy_jobs<S, D>(
+    src: &mut S,
+    dst: &mut D,
+    tube: &str,
+    limit: Option<usize>,
+) -> Result<CopyReport, Error>
+where
+    S: BeanstalkOps + ?Sized,
+    D: BeanstalkOps + ?Sized,
+{
+    let src_used = src.list_tube_used()?.to_string();
+    let src_watched: Vec<String> = (src.list_tube_watched()?.into_iter())
+        .map(String::from)
+        .collect();
+    let dst_used = dst.list_tube_used()?.to_string();
+    // peek-delayed operates on the used tube, reserve on the watched ones
+    src.use_(tube)?;
+    src.watch(tube)?;
+    for t in src_watched.iter().filter(|t| *t != tube) {
+        src.ignore(t)?;
+    }
+    dst.use_(tube)?;
+
+    let mut report = CopyReport::default();
+    let mut held = Vec::new();
+    let res = copy_all(src, dst, limit, &mut report, &mut held);
+
+    // restore both connections whatever happened, reporting the first error
+    let restored = (|| {
+        for (id, pri, delay) in held {
+            src.release(id, pri, delay)?;
+        }
+        src.use_(&src_used)?;
+        for t in &src_watched {
+            src.watch(t)?;
+        }
+        if !src_watched.iter().any(|t| t == tube) {
+            src.ignore(tube)?;
+        }
+        dst.use_(&dst_used)?;
+        Ok::<_, Error>(())
+    })();
+    res?;
+    restored?;
+    Ok(report)
+}
+
+/// Copies the jobs of the tube used and watched by `src`, keeping the jobs that
+/// failed reserved in `held` as `(id, pri, delay)` so they are not copied again.
+fn copy_all<S, D>(
+    src: &mut S,
+    dst: &mut D,
+    limit: Option<usize>,
+    report: &mut CopyReport,
+    held: &mut Vec<(Id, u32, Duration)>,
+) -> Result<(), Error>
+where
+    S: BeanstalkOps + ?Sized,
+    D: BeanstalkOps + ?Sized,
+{
+    while limit.is_none_or(|limit| report.copied.len() < limit) {
+        // ready jobs first, then the delayed ones
+        let (id, data, delay) = match src.reserve(Some(Duration::ZERO))? {
+            ReserveResponse::Reserved { id, data } => (id, data, Duration::ZERO),
+            // reserved by src before the copy, touching nothing would spin until it times out
+            ReserveResponse::DeadlineSoon if held.is_empty() => {
+                return Err("DEADLINE_SOON for a job src reserved before the copy".into());
+            }
+            ReserveResponse::DeadlineSoon => {
+                for (id, ..) in held.iter() {
+                    src.touch(*id)?;
+                }
+                continue;
+            }
+            ReserveResponse::TimedOut => match src.peek_delayed()? {
+                PeekResponse::Found { id, .. } => {
+                    // the time left of a delayed job is its remaining delay
+                    let delay = match src.stats_job(id)? {
+                        StatsJobResponse::Ok(stats) => stats.time_left,
+                        StatsJobResponse::NotFound => continue,
+                    };
+                    match src.reserve_by_id(id)? {
+                        ReserveByIdResponse::Reserved { id, data } => (id, data, delay),
+                        ReserveByIdResponse::NotFound => continue,
+                    }
+                }
+                PeekResponse::NotFound => break,
+            },
+        };
+        let stats = match src.stats_job(id)? {
+            StatsJobResponse::Ok(stats) => stats,
+            StatsJobResponse::NotFound => continue,
+        };
+        // held until put, so that it is released if talking to dst fails
+        held.push((id, stats.pri, delay));
+        match put_copy(dst, stats.pri, delay, stats.ttr, data)? {
+            PutResponse::Inserted(dst_id) => {
+                held.pop();
+                src.delete(id)?;
+                report.copied.push((id, dst_id));
+            }
+            res => {
+                let reply = res.to_string();
+                let err = Error::Reply {
+                    cmd: "put",
+                    arg: None,
+                    reply,
+                };
+                report.failed.push((id, err));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn put_copy<D>(
+    dst: &mut D,
+    pri: u32,
+    delay: Duration,
+    ttr: u32,
+    data: Body,
+) -> Result<PutResponse, Error>
+where
+    D: BeanstalkOps + ?Sized,
+{
+    let ttr = Duration::from_secs(ttr.into());
+    dst.put(pri, delay, ttr, &data.into_bytes()?)
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+    use crate::MockBeanstalk;
+
+    #[test]
+    fn job_reserved_before_the_copy() {
+        let (mut src, mut dst) = (MockBeanstalk::new(), MockBeanstalk::new());
+        src.use_("jobs").unwrap();
+        src.put(0, Duration::ZERO, Duration::from_secs(60), b"job")
+            .unwrap();
+        // the whole TTR is the safety margin
+        src.use_("other").unwrap();
+        src.watch("other").unwrap();
+        src.put(0, Duration::ZERO, Duration::ZERO, b"held").unwrap();
+        src.reserve(Some(Duration::ZERO)).unwrap();
+
+        let err = copy_jobs(&mut src, &mut dst, "jobs", None).unwrap_err();
+        assert!(matches!(err, Error::Bs(_)), "{err}");
+        assert_eq!(src.list_tube_used().unwrap(), "other");
+    }
+}
@@ -1,31 +1,134 @@
 use std::io;
 
+use crate::transport::ResponseTimedOut;
+use crate::Id;
+
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     Bs(String),
+    /// A number in a response could not be parsed.
+    Parse(std::num::ParseIntError),
+    /// The YAML document of a response (e.g. `stats`) could not be parsed.
+    Yaml(serde_yaml::Error),
+    /// The server replied to `cmd` with an error, e.g. `UNKNOWN_COMMAND` or
+    /// `OUT_OF_MEMORY`, or with a reply `cmd` does not expect. `arg` is the tube or the
+    /// job id the command was about, if any.
+    Reply {
+        cmd: &'static str,
+        arg: Option<String>,
+        reply: String,
+    },
+    /// The server does not know about `cmd`, which only newer versions support.
+    /// `version` is the version of the server, if it is known (see
+    /// [`Beanstalk::server_info`](crate::Beanstalk::server_info)).
+    Unsupported {
+        cmd: &'static str,
+        version: Option<String>,
+    },
+    /// A command has not been sent as it exceeds a `limit` of the protocol, being
+    /// `len` bytes long instead of at most `max`, see
+    /// [`Beanstalk::strict_limits`](crate::Beanstalk::strict_limits).
+    WouldBeBadFormat {
+        cmd: String,
+        limit: &'static str,
+        len: usize,
+        max: usize,
+    },
+    /// The response line to `cmd` is longer than `max` bytes, see
+    /// [`Beanstalk::max_response_line`](crate::Beanstalk::max_response_line). The
+    /// connection cannot be used anymore until
+    /// [`Beanstalk::reconnect`](crate::Beanstalk::reconnect).
+    ResponseLineTooLong {
+        cmd: &'static str,
+        max: usize,
+    },
+    /// A put has been refused by the client side rate limit, see
+    /// [`Beanstalk::limit_puts`](crate::Beanstalk::limit_puts).
+    RateLimited,
+    /// The server did not answer within the response timeout, see
+    /// [`Beanstalk::response_timeout`](crate::Beanstalk::response_timeout). The rest of
+    /// the response may still come, so the connection cannot be used anymore until
+    /// [`Beanstalk::reconnect`](crate::Beanstalk::reconnect).
+    ResponseTimeout,
+    /// The body of job `id` could not be read, e.g. it could not be decrypted or
+    /// decompressed. A reserved job is still held by the connection, to be released,
+    /// buried or deleted.
+    Body {
+        id: Id,
+        source: Box<Error>,
+    },
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Parse(err) => Some(err),
+            Error::Yaml(err) => Some(err),
+            Error::Body { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Error::Io(err) => err.fmt(f),
+            Error::Io(err) => write!(f, "I/O error: {err}"),
             Error::Bs(err) => err.fmt(f),
+            Error::Parse(err) => write!(f, "invalid number in the response: {err}"),
+            Error::Yaml(err) => write!(f, "invalid YAML document in the response: {err}"),
+            Error::Reply {
+                cmd,
+                arg: Some(arg),
+                reply,
+            } => write!(f, "{cmd} {arg}: {reply}"),
+            Error::Reply {
+                cmd,
+                arg: None,
+                reply,
+            } => write!(f, "{cmd}: {reply}"),
+            Error::Unsupported {
+                cmd,
+                version: Some(version),
+            } => write!(f, "{cmd} is not supported by beanstalkd {version}"),
+            Error::Unsupported { cmd, version: None } => {
+                write!(f, "{cmd} is not supported by the server")
+            }
+            Error::WouldBeBadFormat {
+                cmd,
+                limit,
+                len,
+                max,
+            } => write!(f, "{cmd}: {limit} of {len} bytes, over the limit of {max}"),
+            Error::ResponseLineTooLong { cmd, max } => {
+                write!(f, "{cmd}: response line longer than {max} bytes")
+            }
+            Error::RateLimited => f.write_str("put rate limit exceeded"),
+            Error::ResponseTimeout => f.write_str("no response from the server in time"),
+            Error::Body { id, source } => {
+                write!(f, "unable to read the body of job {id}: {source}")
+            }
         }
     }
 }
 
 impl From<io::Error> for Error {
     fn from(value: io::Error) -> Self {
+        let timed_out = value
+            .get_ref()
+            .is_some_and(|err| err.is::<ResponseTimedOut>());
+        if timed_out {
+            return Self::ResponseTimeout;
+        }
         Self::Io(value)
     }
 }
 
 impl From<std::num::ParseIntError> for Error {
     fn from(value: std::num::ParseIntError) -> Self {
-        Self::Bs(value.to_string())
+        Self::Parse(value)
     }
 }
 
@@ -43,6 +146,6 @@ impl From<String> for Error {
 
 impl From<serde_yaml::Error> for Error {
     fn from(value: serde_yaml::Error) -> Self {
-        Self::Bs(value.to_string())
+        Self::Yaml(value)
     }
 }
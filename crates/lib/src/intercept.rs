@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use crate::body::Body;
+
+/// Sees the commands sent by a client and the responses received, see
+/// [`Beanstalk::add_interceptor`](crate::Beanstalk::add_interceptor). Meant for
+/// logging, redaction, latency measurement, or rewriting commands.
+///
+/// Command and response lines go through interceptors without their ending `\r\n`.
+/// Job bodies are only seen, by [`body_sent`](Self::body_sent) and
+/// [`body_received`](Self::body_received), and the YAML documents of responses are
+/// not.
+///
+/// ```no_run
+/// # use bsc::{Beanstalk, Interceptor};
+/// # use std::time::Duration;
+/// struct SlowLog;
+///
+/// impl Interceptor for SlowLog {
+///     fn after_receive(&mut self, cmd: &str, response: &str, elapsed: Duration) {
+///         if elapsed > Duration::from_millis(100) {
+///             eprintln!("slow command {cmd:?}: {response:?} after {elapsed:?}");
+///         }
+///     }
+/// }
+///
+/// # let mut bsc = Beanstalk::connect("127.0.0.1:11300").unwrap();
+/// bsc.add_interceptor(Box::new(SlowLog));
+/// ```
+pub trait Interceptor: Send {
+    /// Called before `line` is sent, which may be changed. The server must still be
+    /// able to make sense of it, and to answer it like the original command.
+    fn before_send(&mut self, line: &mut String) {
+        let _ = line;
+    }
+
+    /// Called when the `response` line to the command `cmd`, as sent, is received,
+    /// `elapsed` after the command has been written.
+    fn after_receive(&mut self, cmd: &str, response: &str, elapsed: Duration) {
+        let _ = (cmd, response, elapsed);
+    }
+
+    /// Called when the body of a put, or of a raw command, has been written after its
+    /// command line, as sent: compressed and encrypted if enabled.
+    fn body_sent(&mut self, data: &[u8]) {
+        let _ = data;
+    }
+
+    /// Called when a job body has been received after its response line, once
+    /// decrypted and decompressed.
+    fn body_received(&mut self, body: &Body) {
+        let _ = body;
+    }
+}
@@ -1,9 +1,35 @@
 mod beanstalk;
+mod body;
+mod cipher;
+mod codec;
+mod copy;
 mod error;
+mod intercept;
+mod logging;
+#[cfg(feature = "mock")]
+mod mock;
+mod ops;
+mod options;
+pub mod protocol;
+mod ratelimit;
+mod shutdown;
 mod stats;
+mod transport;
+mod url;
 
 pub use error::*;
 pub use beanstalk::*;
+pub use body::*;
+pub use cipher::*;
+pub use codec::*;
+pub use copy::*;
+pub use intercept::*;
+#[cfg(feature = "mock")]
+pub use mock::*;
+pub use ops::*;
+pub use options::*;
+pub use shutdown::*;
 pub use stats::*;
+pub use url::*;
 
 pub(crate) type Result<T, E = crate::Error> = std::result::Result<T, E>;
\ No newline at end of file
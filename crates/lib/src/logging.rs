@@ -0,0 +1,25 @@
+/// Emits a debug record through the `log` crate, compiled out without the `log`
+/// feature. Used for connections and errors.
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::debug!(target: "bsc", $($arg)*);
+        // the arguments are still used, so that they do not warn as unused
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+/// Emits a trace record through the `log` crate, compiled out without the `log`
+/// feature. Used for every command and response.
+macro_rules! trace {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::trace!(target: "bsc", $($arg)*);
+        // the arguments are still used, so that they do not warn as unused
+        #[cfg(not(feature = "log"))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+pub(crate) use {debug, trace};
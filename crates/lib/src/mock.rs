@@ -0,0 +1,730 @@
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::beanstalk::*;
+use crate::body::Body;
+use crate::ops::BeanstalkOps;
+use crate::protocol::*;
+use crate::stats::*;
+use crate::Error;
+
+/// An in-memory [`BeanstalkOps`] implementation, for unit testing code that talks to
+/// beanstalkd without a running server. Only available with the `mock` feature.
+///
+/// It behaves like a beanstalkd with a single client connected: jobs go through the
+/// ready, delayed, reserved and buried states, are reserved by priority then id, and
+/// are released back to the ready queue when their TTR elapses. Delays and TTRs use
+/// the real clock, so a `reserve` waiting for a delayed job does sleep.
+///
+/// A `reserve` without timeout that could only be satisfied by another client (no
+/// delayed or reserved job in the watched tubes) is an error instead of blocking
+/// forever.
+///
+/// ```
+/// use std::time::Duration;
+/// use bsc::{BeanstalkOps, MockBeanstalk, ReserveResponse};
+///
+/// fn work(bsc: &mut impl BeanstalkOps) -> Result<(), bsc::Error> {
+///     if let ReserveResponse::Reserved { id, .. } = bsc.reserve(Some(Duration::ZERO))? {
+///         bsc.delete(id)?;
+///     }
+///     Ok(())
+/// }
+///
+/// let mut bsc = MockBeanstalk::new();
+/// bsc.put(0, Duration::ZERO, Duration::from_secs(60), b"hello")?;
+/// work(&mut bsc)?;
+/// assert_eq!(bsc.stats()?.total_jobs, 1);
+/// # Ok::<(), bsc::Error>(())
+/// ```
+pub struct MockBeanstalk {
+    jobs: BTreeMap<Id, Job>,
+    tubes: BTreeMap<String, Tube>,
+    using: String,
+    watching: Vec<String>,
+    next_id: Id,
+    next_seq: u64,
+    started: Instant,
+    max_job_size: usize,
+    draining: bool,
+    total_jobs: u64,
+    job_timeouts: u64,
+}
+
+struct Job {
+    tube: String,
+    pri: u32,
+    data: Vec<u8>,
+    state: State,
+    delay: Duration,
+    ttr: Duration,
+    created: Instant,
+    /// When a delayed job becomes ready, or a reserved job times out.
+    deadline: Instant,
+    /// Order of the buried jobs, which are kicked first in first out.
+    seq: u64,
+    reserves: u32,
+    timeouts: u32,
+    releases: u32,
+    buries: u32,
+    kicks: u32,
+}
+
+#[derive(Default)]
+struct Tube {
+    total_jobs: u64,
+    cmd_delete: u64,
+    cmd_pause_tube: u64,
+    pause: Duration,
+    paused_until: Option<Instant>,
+}
+
+impl Default for MockBeanstalk {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockBeanstalk {
+    /// Creates an empty server, using and watching the "default" tube.
+    pub fn new() -> Self {
+        let mut tubes = BTreeMap::new();
+        tubes.insert(DEFAULT_TUBE.to_string(), Tube::default());
+        Self {
+            jobs: BTreeMap::new(),
+            tubes,
+            using: DEFAULT_TUBE.to_string(),
+            watching: vec![DEFAULT_TUBE.to_string()],
+            next_id: 1,
+            next_seq: 0,
+            started: Instant::now(),
+            max_job_size: DEFAULT_MAX_JOB_SIZE as usize,
+            draining: false,
+            total_jobs: 0,
+            job_timeouts: 0,
+        }
+    }
+
+    /// Bodies bigger than `size` bytes are refused with `JOB_TOO_BIG`. Defaults to
+    /// 65535, like beanstalkd.
+    pub fn max_job_size(&mut self, size: usize) {
+        self.max_job_size = size;
+    }
+
+    /// Puts are refused with `DRAINING` while enabled.
+    pub fn draining(&mut self, enabled: bool) {
+        self.draining = enabled;
+    }
+
+    /// Moves the delayed jobs whose delay elapsed and the reserved jobs whose TTR
+    /// elapsed back to the ready queue, and un-pauses tubes.
+    fn tick(&mut self, now: Instant) {
+        for job in self.jobs.values_mut() {
+            if job.deadline > now {
+                continue;
+            }
+            match job.state {
+                State::Delayed => job.state = State::Ready,
+                State::Reserved => {
+                    job.state = State::Ready;
+                    job.timeouts += 1;
+                    self.job_timeouts += 1;
+                }
+                _ => {}
+            }
+        }
+        for tube in self.tubes.values_mut() {
+            if matches!(tube.paused_until, Some(until) if until <= now) {
+                tube.paused_until = None;
+                tube.pause = Duration::ZERO;
+            }
+        }
+    }
+
+    fn tube(&mut self, name: &str) -> &mut Tube {
+        self.tubes.entry(name.to_string()).or_default()
+    }
+
+    fn is_watched(&self, tube: &str) -> bool {
+        self.watching.iter().any(|t| t == tube)
+    }
+
+    fn is_paused(&self, tube: &str) -> bool {
+        matches!(self.tubes.get(tube), Some(t) if t.paused_until.is_some())
+    }
+
+    /// The next ready job of the watched tubes that are not paused.
+    fn next_ready(&self) -> Option<Id> {
+        self.jobs
+            .iter()
+            .filter(|(_, job)| job.state == State::Ready)
+            .filter(|(_, job)| self.is_watched(&job.tube) && !self.is_paused(&job.tube))
+            .min_by_key(|(id, job)| (job.pri, **id))
+            .map(|(id, _)| *id)
+    }
+
+    /// The next point in time at which a reserve could succeed or get `DEADLINE_SOON`.
+    fn next_wake_up(&self) -> Option<Instant> {
+        let jobs = self.jobs.values().filter_map(|job| match job.state {
+            State::Delayed if self.is_watched(&job.tube) => Some(job.deadline),
+            State::Reserved => Some(job.deadline - safety_margin(job.ttr)),
+            _ => None,
+        });
+        let tubes = self
+            .tubes
+            .iter()
+            .filter(|(name, _)| self.is_watched(name))
+            .filter_map(|(_, tube)| tube.paused_until);
+        jobs.chain(tubes).min()
+    }
+
+    fn deadline_soon(&self, now: Instant) -> bool {
+        self.jobs
+            .values()
+            .any(|job| job.state == State::Reserved && job.deadline - safety_margin(job.ttr) <= now)
+    }
+
+    fn reserve_job(&mut self, id: Id, now: Instant) -> Body {
+        let job = self.jobs.get_mut(&id).expect("job exists");
+        job.state = State::Reserved;
+        job.deadline = now + job.ttr;
+        job.reserves += 1;
+        Body::from(job.data.clone())
+    }
+
+    /// The jobs of the used tube in the given state, in the order they are peeked.
+    fn in_used_tube(&self, state: State) -> Vec<Id> {
+        let mut jobs: Vec<(&Id, &Job)> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| job.state == state && job.tube == self.using)
+            .collect();
+        match state {
+            State::Ready => jobs.sort_by_key(|(id, job)| (job.pri, **id)),
+            State::Delayed => jobs.sort_by_key(|(id, job)| (job.deadline, **id)),
+            State::Buried => jobs.sort_by_key(|(_, job)| job.seq),
+            State::Reserved => {}
+        }
+        jobs.into_iter().map(|(id, _)| *id).collect()
+    }
+
+    fn peek_first(&mut self, state: State) -> PeekResponse {
+        self.tick(Instant::now());
+        match self.in_used_tube(state).first() {
+            Some(id) => PeekResponse::Found {
+                id: *id,
+                data: Body::from(self.jobs[id].data.clone()),
+            },
+            None => PeekResponse::NotFound,
+        }
+    }
+
+    /// Forgets the tubes that have no job and are neither used nor watched, as
+    /// beanstalkd does.
+    fn collect_tubes(&mut self) {
+        let Self {
+            tubes,
+            jobs,
+            using,
+            watching,
+            ..
+        } = self;
+        tubes.retain(|name, _| {
+            name == using || watching.contains(name) || jobs.values().any(|job| &job.tube == name)
+        });
+    }
+
+    fn count(&self, tube: Option<&str>, state: State) -> u32 {
+        self.jobs
+            .values()
+            .filter(|job| job.state == state && tube.is_none_or(|t| job.tube == t))
+            .count() as u32
+    }
+
+    fn count_urgent(&self, tube: Option<&str>) -> u32 {
+        self.jobs
+            .values()
+            .filter(|job| job.state == State::Ready && job.pri < 1024)
+            .filter(|job| tube.is_none_or(|t| job.tube == t))
+            .count() as u32
+    }
+}
+
+/// The last second of a TTR during which a reserve returns `DEADLINE_SOON`.
+#[inline]
+fn safety_margin(ttr: Duration) -> Duration {
+    ttr.min(SAFETY_MARGIN)
+}
+
+impl BeanstalkOps for MockBeanstalk {
+    fn put(
+        &mut self,
+        pri: u32,
+        delay: Duration,
+        ttr: Duration,
+        data: &[u8],
+    ) -> Result<PutResponse, Error> {
+        if self.draining {
+            return Ok(PutResponse::Draining);
+        }
+        if data.len() > self.max_job_size {
+            return Ok(PutResponse::JobTooBig);
+        }
+        let now = Instant::now();
+        let id = self.next_id;
+        self.next_id += 1;
+        self.total_jobs += 1;
+        let tube = self.using.clone();
+        self.tube(&tube).total_jobs += 1;
+        self.jobs.insert(
+            id,
+            Job {
+                tube,
+                pri,
+                data: data.to_vec(),
+                state: if delay.is_zero() {
+                    State::Ready
+                } else {
+                    State::Delayed
+                },
+                delay,
+                ttr: ttr.max(Duration::from_secs(1)),
+                created: now,
+                deadline: now + delay,
+                seq: 0,
+                reserves: 0,
+                timeouts: 0,
+                releases: 0,
+                buries: 0,
+                kicks: 0,
+            },
+        );
+        Ok(PutResponse::Inserted(id))
+    }
+
+    fn use_(&mut self, tube: &str) -> Result<&str, Error> {
+        self.tube(tube);
+        self.using = tube.to_string();
+        Ok(&self.using)
+    }
+
+    fn reserve(&mut self, timeout: Option<Duration>) -> Result<ReserveResponse, Error> {
+        let give_up = timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            let now = Instant::now();
+            self.tick(now);
+            if let Some(id) = self.next_ready() {
+                let data = self.reserve_job(id, now);
+                return Ok(ReserveResponse::Reserved { id, data });
+            }
+            if self.deadline_soon(now) {
+                return Ok(ReserveResponse::DeadlineSoon);
+            }
+            if matches!(give_up, Some(give_up) if give_up <= now) {
+                return Ok(ReserveResponse::TimedOut);
+            }
+            let wake_up = match (self.next_wake_up(), give_up) {
+                (Some(a), Some(b)) => a.min(b),
+                (Some(a), None) => a,
+                (None, Some(b)) => b,
+                (None, None) => {
+                    return Err("reserve would block forever, no job can become ready".into())
+                }
+            };
+            thread::sleep(wake_up.saturating_duration_since(now));
+        }
+    }
+
+    fn reserve_by_id(&mut self, id: Id) -> Result<ReserveByIdResponse, Error> {
+        let now = Instant::now();
+        self.tick(now);
+        match self.jobs.get(&id) {
+            Some(job) if job.state != State::Reserved => {
+                let data = self.reserve_job(id, now);
+                Ok(ReserveByIdResponse::Reserved { id, data })
+            }
+            _ => Ok(ReserveByIdResponse::NotFound),
+        }
+    }
+
+    fn delete(&mut self, id: Id) -> Result<DeleteResponse, Error> {
+        match self.jobs.remove(&id) {
+            Some(job) => {
+                self.tube(&job.tube).cmd_delete += 1;
+                Ok(DeleteResponse::Deleted)
+            }
+            None => Ok(DeleteResponse::NotFound),
+        }
+    }
+
+    fn release(&mut self, id: Id, pri: u32, delay: Duration) -> Result<ReleaseResponse, Error> {
+        let now = Instant::now();
+        self.tick(now);
+        match self.jobs.get_mut(&id) {
+            Some(job) if job.state == State::Reserved => {
+                job.pri = pri;
+                job.delay = delay;
+                job.deadline = now + delay;
+                job.releases += 1;
+                job.state = if delay.is_zero() {
+                    State::Ready
+                } else {
+                    State::Delayed
+                };
+                Ok(ReleaseResponse::Released)
+            }
+            _ => Ok(ReleaseResponse::NotFound),
+        }
+    }
+
+    fn bury(&mut self, id: Id, pri: u32) -> Result<BuryResponse, Error> {
+        self.tick(Instant::now());
+        match self.jobs.get_mut(&id) {
+            Some(job) if job.state == State::Reserved => {
+                job.pri = pri;
+                job.state = State::Buried;
+                job.seq = self.next_seq;
+                job.buries += 1;
+                self.next_seq += 1;
+                Ok(BuryResponse::Buried)
+            }
+            _ => Ok(BuryResponse::NotFound),
+        }
+    }
+
+    fn touch(&mut self, id: Id) -> Result<TouchResponse, Error> {
+        let now = Instant::now();
+        self.tick(now);
+        match self.jobs.get_mut(&id) {
+            Some(job) if job.state == State::Reserved => {
+                job.deadline = now + job.ttr;
+                Ok(TouchResponse::Touched)
+            }
+            _ => Ok(TouchResponse::NotFound),
+        }
+    }
+
+    fn watch(&mut self, tube: &str) -> Result<usize, Error> {
+        self.tube(tube);
+        if !self.is_watched(tube) {
+            self.watching.push(tube.to_string());
+        }
+        Ok(self.watching.len())
+    }
+
+    fn ignore(&mut self, tube: &str) -> Result<IgnoreResponse, Error> {
+        if self.is_watched(tube) {
+            if self.watching.len() == 1 {
+                return Ok(IgnoreResponse::NotIgnored);
+            }
+            self.watching.retain(|t| t != tube);
+        }
+        Ok(IgnoreResponse::Count(self.watching.len()))
+    }
+
+    fn peek(&mut self, id: Id) -> Result<PeekResponse, Error> {
+        match self.jobs.get(&id) {
+            Some(job) => Ok(PeekResponse::Found {
+                id,
+                data: Body::from(job.data.clone()),
+            }),
+            None => Ok(PeekResponse::NotFound),
+        }
+    }
+
+    fn peek_ready(&mut self) -> Result<PeekResponse, Error> {
+        Ok(self.peek_first(State::Ready))
+    }
+
+    fn peek_delayed(&mut self) -> Result<PeekResponse, Error> {
+        Ok(self.peek_first(State::Delayed))
+    }
+
+    fn peek_buried(&mut self) -> Result<PeekResponse, Error> {
+        Ok(self.peek_first(State::Buried))
+    }
+
+    fn kick(&mut self, bound: u32) -> Result<usize, Error> {
+        self.tick(Instant::now());
+        let mut ids = self.in_used_tube(State::Buried);
+        if ids.is_empty() {
+            ids = self.in_used_tube(State::Delayed);
+        }
+        ids.truncate(bound as usize);
+        for id in &ids {
+            self.kick_job(*id)?;
+        }
+        Ok(ids.len())
+    }
+
+    fn kick_job(&mut self, id: Id) -> Result<KickJobResponse, Error> {
+        self.tick(Instant::now());
+        match self.jobs.get_mut(&id) {
+            Some(job) if matches!(job.state, State::Buried | State::Delayed) => {
+                job.state = State::Ready;
+                job.kicks += 1;
+                Ok(KickJobResponse::Kicked)
+            }
+            _ => Ok(KickJobResponse::NotFound),
+        }
+    }
+
+    fn stats_job(&mut self, id: Id) -> Result<StatsJobResponse, Error> {
+        let now = Instant::now();
+        self.tick(now);
+        let job = match self.jobs.get(&id) {
+            Some(job) => job,
+            None => return Ok(StatsJobResponse::NotFound),
+        };
+        let time_left = match job.state {
+            State::Delayed | State::Reserved => job.deadline.saturating_duration_since(now),
+            _ => Duration::ZERO,
+        };
+        Ok(StatsJobResponse::Ok(StatsJob {
+            id,
+            tube: job.tube.clone(),
+            state: job.state,
+            pri: job.pri,
+            age: Duration::from_secs(now.duration_since(job.created).as_secs()),
+            delay: job.delay,
+            ttr: job.ttr.as_secs() as u32,
+            time_left: Duration::from_secs(time_left.as_secs()),
+            file: 0,
+            reserves: job.reserves,
+            timeouts: job.timeouts,
+            releases: job.releases,
+            buries: job.buries,
+            kicks: job.kicks,
+        }))
+    }
+
+    fn stats_tube(&mut self, tube: &str) -> Result<StatsTubeResponse, Error> {
+        let now = Instant::now();
+        self.tick(now);
+        self.collect_tubes();
+        let t = match self.tubes.get(tube) {
+            Some(t) => t,
+            None => return Ok(StatsTubeResponse::NotFound),
+        };
+        Ok(StatsTubeResponse::Ok(StatsTube {
+            name: tube.to_string(),
+            current_jobs_urgent: self.count_urgent(Some(tube)),
+            current_jobs_ready: self.count(Some(tube), State::Ready),
+            current_jobs_reserved: self.count(Some(tube), State::Reserved),
+            current_jobs_delayed: self.count(Some(tube), State::Delayed),
+            current_jobs_buried: self.count(Some(tube), State::Buried),
+            total_jobs: t.total_jobs,
+            current_using: (self.using == tube) as u32,
+            current_waiting: 0,
+            current_watching: self.is_watched(tube) as u32,
+            pause: t.pause.as_secs() as u32,
+            cmd_delete: t.cmd_delete,
+            cmd_pause_tube: t.cmd_pause_tube,
+            pause_time_left: t
+                .paused_until
+                .map_or(Duration::ZERO, |until| until.saturating_duration_since(now)),
+        }))
+    }
+
+    fn stats(&mut self) -> Result<Stats, Error> {
+        self.tick(Instant::now());
+        self.collect_tubes();
+        Ok(Stats {
+            current_jobs_urgent: self.count_urgent(None),
+            current_jobs_ready: self.count(None, State::Ready),
+            current_jobs_reserved: self.count(None, State::Reserved),
+            current_jobs_delayed: self.count(None, State::Delayed),
+            current_jobs_buried: self.count(None, State::Buried),
+            job_timeouts: self.job_timeouts,
+            total_jobs: self.total_jobs,
+            max_job_size: self.max_job_size as u32,
+            current_tubes: self.tubes.len() as u32,
+            current_connections: 1,
+            total_connections: 1,
+            pid: std::process::id(),
+            version: "mock".to_string(),
+            uptime: Duration::from_secs(self.started.elapsed().as_secs()),
+            draining: self.draining,
+            id: "mock".to_string(),
+            hostname: "localhost".to_string(),
+            ..Default::default()
+        })
+    }
+
+    fn list_tubes(&mut self) -> Result<Vec<&str>, Error> {
+        self.collect_tubes();
+        Ok(self.tubes.keys().map(String::as_str).collect())
+    }
+
+    fn list_tube_used(&mut self) -> Result<&str, Error> {
+        Ok(&self.using)
+    }
+
+    fn list_tube_watched(&mut self) -> Result<Vec<&str>, Error> {
+        Ok(self.watching.iter().map(String::as_str).collect())
+    }
+
+    fn pause_tube(&mut self, tube: &str, delay: Duration) -> Result<PauseTubeResponse, Error> {
+        self.collect_tubes();
+        match self.tubes.get_mut(tube) {
+            Some(t) => {
+                t.pause = delay;
+                t.paused_until = Some(Instant::now() + delay);
+                t.cmd_pause_tube += 1;
+                Ok(PauseTubeResponse::Paused)
+            }
+            None => Ok(PauseTubeResponse::NotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTR: Duration = Duration::from_secs(60);
+
+    fn reserved_id(res: ReserveResponse) -> Id {
+        match res {
+            ReserveResponse::Reserved { id, .. } => id,
+            res => panic!("expected a job, got {res}"),
+        }
+    }
+
+    fn job_stats(bsc: &mut MockBeanstalk, id: Id) -> StatsJob {
+        match bsc.stats_job(id).unwrap() {
+            StatsJobResponse::Ok(stats) => stats,
+            StatsJobResponse::NotFound => panic!("job {id} not found"),
+        }
+    }
+
+    #[test]
+    fn reserves_by_priority_then_id() {
+        let mut bsc = MockBeanstalk::new();
+        for pri in [5, 1, 5, 0] {
+            bsc.put(pri, Duration::ZERO, TTR, b"job").unwrap();
+        }
+        let order: Vec<Id> = (0..4)
+            .map(|_| reserved_id(bsc.reserve(Some(Duration::ZERO)).unwrap()))
+            .collect();
+        assert_eq!(order, [4, 2, 1, 3]);
+        assert!(matches!(
+            bsc.reserve(Some(Duration::ZERO)).unwrap(),
+            ReserveResponse::TimedOut
+        ));
+    }
+
+    #[test]
+    fn delayed_job_becomes_ready() {
+        let mut bsc = MockBeanstalk::new();
+        let delay = Duration::from_millis(100);
+        bsc.put(0, delay, TTR, b"later").unwrap();
+        assert_eq!(job_stats(&mut bsc, 1).state, State::Delayed);
+        assert!(matches!(
+            bsc.peek_delayed().unwrap(),
+            PeekResponse::Found { id: 1, .. }
+        ));
+        assert!(matches!(
+            bsc.reserve(Some(Duration::ZERO)).unwrap(),
+            ReserveResponse::TimedOut
+        ));
+
+        let start = Instant::now();
+        let id = reserved_id(bsc.reserve(Some(Duration::from_secs(1))).unwrap());
+        assert_eq!(id, 1);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn release_with_delay() {
+        let mut bsc = MockBeanstalk::new();
+        bsc.put(0, Duration::ZERO, TTR, b"job").unwrap();
+        let id = reserved_id(bsc.reserve(Some(Duration::ZERO)).unwrap());
+        bsc.release(id, 7, Duration::from_secs(10)).unwrap();
+
+        let stats = job_stats(&mut bsc, id);
+        assert_eq!(stats.state, State::Delayed);
+        assert_eq!((stats.pri, stats.releases), (7, 1));
+        assert_eq!(bsc.kick(10).unwrap(), 1);
+        assert_eq!(job_stats(&mut bsc, id).state, State::Ready);
+    }
+
+    #[test]
+    fn ttr_elapses() {
+        let mut bsc = MockBeanstalk::new();
+        // the TTR is at least one second, all of it being the safety margin
+        bsc.put(0, Duration::ZERO, Duration::ZERO, b"job").unwrap();
+        let id = reserved_id(bsc.reserve(Some(Duration::ZERO)).unwrap());
+        assert_eq!(job_stats(&mut bsc, id).ttr, 1);
+        assert!(matches!(
+            bsc.reserve(Some(Duration::ZERO)).unwrap(),
+            ReserveResponse::DeadlineSoon
+        ));
+
+        // back in the ready queue once the TTR is over
+        thread::sleep(Duration::from_secs(1));
+        assert_eq!(reserved_id(bsc.reserve(Some(Duration::ZERO)).unwrap()), id);
+        let stats = job_stats(&mut bsc, id);
+        assert_eq!((stats.reserves, stats.timeouts), (2, 1));
+        assert_eq!(bsc.stats().unwrap().job_timeouts, 1);
+    }
+
+    #[test]
+    fn reserve_with_deadline_waits_past_deadline_soon() {
+        let mut bsc = MockBeanstalk::new();
+        bsc.put(0, Duration::ZERO, Duration::ZERO, b"job").unwrap();
+        reserved_id(bsc.reserve(Some(Duration::ZERO)).unwrap());
+
+        let start = Instant::now();
+        let deadline = start + Duration::from_millis(300);
+        let res = bsc.reserve_with_deadline(deadline).unwrap();
+        assert!(matches!(res, ReserveResponse::TimedOut));
+        assert!(start.elapsed() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn touch_extends_ttr() {
+        let mut bsc = MockBeanstalk::new();
+        bsc.put(0, Duration::ZERO, Duration::from_secs(2), b"job")
+            .unwrap();
+        let id = reserved_id(bsc.reserve(Some(Duration::ZERO)).unwrap());
+        thread::sleep(Duration::from_millis(600));
+        assert!(matches!(bsc.touch(id).unwrap(), TouchResponse::Touched));
+        thread::sleep(Duration::from_millis(600));
+        // without the touch, the last second of the TTR would have started
+        assert!(matches!(
+            bsc.reserve(Some(Duration::ZERO)).unwrap(),
+            ReserveResponse::TimedOut
+        ));
+        assert_eq!(job_stats(&mut bsc, id).state, State::Reserved);
+    }
+
+    #[test]
+    fn buried_jobs_are_kicked_in_order() {
+        let mut bsc = MockBeanstalk::new();
+        for _ in 0..3 {
+            bsc.put(0, Duration::ZERO, TTR, b"job").unwrap();
+        }
+        for id in [2, 1] {
+            bsc.reserve_by_id(id).unwrap();
+            assert!(matches!(bsc.bury(id, 9).unwrap(), BuryResponse::Buried));
+        }
+        // only reserved jobs can be buried
+        assert!(matches!(bsc.bury(3, 0).unwrap(), BuryResponse::NotFound));
+
+        let stats = job_stats(&mut bsc, 2);
+        assert_eq!(stats.state, State::Buried);
+        assert_eq!((stats.pri, stats.buries), (9, 1));
+        assert_eq!(bsc.stats().unwrap().current_jobs_buried, 2);
+        assert!(matches!(
+            bsc.peek_buried().unwrap(),
+            PeekResponse::Found { id: 2, .. }
+        ));
+
+        assert_eq!(bsc.kick(1).unwrap(), 1);
+        assert_eq!(job_stats(&mut bsc, 2).state, State::Ready);
+        assert_eq!(job_stats(&mut bsc, 2).kicks, 1);
+        assert_eq!(job_stats(&mut bsc, 1).state, State::Buried);
+    }
+}
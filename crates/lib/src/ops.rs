@@ -0,0 +1,235 @@
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::beanstalk::*;
+use crate::options::PutOptions;
+use crate::stats::{State, Stats, StatsJob};
+use crate::Error;
+
+/// How often [`BeanstalkOps::reserve_with_deadline`] polls for a job during the last
+/// second before the deadline.
+const DEADLINE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The beanstalkd commands, implemented by [`Beanstalk`] and, with the `mock` feature,
+/// by `MockBeanstalk`.
+///
+/// Code written against this trait can be unit tested without a running server.
+/// See the methods of [`Beanstalk`] for the documentation of each command.
+pub trait BeanstalkOps {
+    fn put(
+        &mut self,
+        pri: u32,
+        delay: Duration,
+        ttr: Duration,
+        data: &[u8],
+    ) -> Result<PutResponse, Error>;
+    fn use_(&mut self, tube: &str) -> Result<&str, Error>;
+    fn reserve(&mut self, timeout: Option<Duration>) -> Result<ReserveResponse, Error>;
+    fn reserve_by_id(&mut self, id: Id) -> Result<ReserveByIdResponse, Error>;
+    fn delete(&mut self, id: Id) -> Result<DeleteResponse, Error>;
+    fn release(&mut self, id: Id, pri: u32, delay: Duration) -> Result<ReleaseResponse, Error>;
+    fn bury(&mut self, id: Id, pri: u32) -> Result<BuryResponse, Error>;
+    fn touch(&mut self, id: Id) -> Result<TouchResponse, Error>;
+    fn watch(&mut self, tube: &str) -> Result<usize, Error>;
+    fn ignore(&mut self, tube: &str) -> Result<IgnoreResponse, Error>;
+    fn peek(&mut self, id: Id) -> Result<PeekResponse, Error>;
+    fn peek_ready(&mut self) -> Result<PeekResponse, Error>;
+    fn peek_delayed(&mut self) -> Result<PeekResponse, Error>;
+    fn peek_buried(&mut self) -> Result<PeekResponse, Error>;
+    fn kick(&mut self, bound: u32) -> Result<usize, Error>;
+    fn kick_job(&mut self, id: Id) -> Result<KickJobResponse, Error>;
+    fn stats_job(&mut self, id: Id) -> Result<StatsJobResponse, Error>;
+    fn stats_tube(&mut self, tube: &str) -> Result<StatsTubeResponse, Error>;
+    fn stats(&mut self) -> Result<Stats, Error>;
+    fn list_tubes(&mut self) -> Result<Vec<&str>, Error>;
+    fn list_tube_used(&mut self) -> Result<&str, Error>;
+    fn list_tube_watched(&mut self) -> Result<Vec<&str>, Error>;
+    fn pause_tube(&mut self, tube: &str, delay: Duration) -> Result<PauseTubeResponse, Error>;
+
+    /// See [`Beanstalk::put_with`].
+    fn put_with(&mut self, data: &[u8], opts: PutOptions) -> Result<PutResponse, Error> {
+        self.put(opts.pri, opts.delay, opts.ttr, data)
+    }
+
+    /// See [`Beanstalk::put_at`].
+    fn put_at(
+        &mut self,
+        pri: u32,
+        when: SystemTime,
+        ttr: Duration,
+        data: &[u8],
+    ) -> Result<PutResponse, Error> {
+        self.put(pri, delay_until(when), ttr, data)
+    }
+
+    /// See [`Beanstalk::reserve_with_deadline`].
+    fn reserve_with_deadline(&mut self, deadline: Instant) -> Result<ReserveResponse, Error> {
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            // the server only waits whole seconds, the last fraction of a second is polled
+            let timeout = Duration::from_secs(remaining.as_secs());
+            match self.reserve(Some(timeout))? {
+                ReserveResponse::TimedOut if !remaining.is_zero() => {
+                    if timeout.is_zero() {
+                        let left = deadline.saturating_duration_since(Instant::now());
+                        thread::sleep(left.min(DEADLINE_POLL_INTERVAL));
+                    }
+                }
+                // answered right away during the safety margin of a held job, which is
+                // either touched meanwhile or released by the server once its TTR is over
+                ReserveResponse::DeadlineSoon => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        return Ok(ReserveResponse::TimedOut);
+                    }
+                    thread::sleep(left.min(DEADLINE_POLL_INTERVAL));
+                }
+                res => return Ok(res),
+            }
+        }
+    }
+
+    /// See [`Beanstalk::try_reserve`].
+    fn try_reserve(&mut self) -> Result<Option<Job>, Error> {
+        match self.reserve(Some(Duration::ZERO))? {
+            ReserveResponse::Reserved { id, data } => Ok(Some(Job { id, data })),
+            ReserveResponse::DeadlineSoon | ReserveResponse::TimedOut => Ok(None),
+        }
+    }
+
+    /// See [`Beanstalk::wait_for_state`].
+    fn wait_for_state(
+        &mut self,
+        id: Id,
+        state: State,
+        timeout: Duration,
+        interval: Duration,
+    ) -> Result<Option<StatsJob>, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let stats = match self.stats_job(id)? {
+                StatsJobResponse::Ok(stats) => stats,
+                StatsJobResponse::NotFound => return Ok(None),
+            };
+            if stats.state == state {
+                return Ok(Some(stats));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Err(
+                    format!("job {id} is still {:?} after {timeout:?}", stats.state).into(),
+                );
+            }
+            thread::sleep(interval.min(left));
+        }
+    }
+
+    /// See [`Beanstalk::release_at`].
+    fn release_at(&mut self, id: Id, pri: u32, when: SystemTime) -> Result<ReleaseResponse, Error> {
+        self.release(id, pri, delay_until(when))
+    }
+}
+
+impl BeanstalkOps for Beanstalk {
+    fn put(
+        &mut self,
+        pri: u32,
+        delay: Duration,
+        ttr: Duration,
+        data: &[u8],
+    ) -> Result<PutResponse, Error> {
+        Beanstalk::put(self, pri, delay, ttr, data)
+    }
+
+    fn use_(&mut self, tube: &str) -> Result<&str, Error> {
+        Beanstalk::use_(self, tube)
+    }
+
+    fn reserve(&mut self, timeout: Option<Duration>) -> Result<ReserveResponse, Error> {
+        Beanstalk::reserve(self, timeout)
+    }
+
+    fn try_reserve(&mut self) -> Result<Option<Job>, Error> {
+        Beanstalk::try_reserve(self)
+    }
+
+    fn reserve_by_id(&mut self, id: Id) -> Result<ReserveByIdResponse, Error> {
+        Beanstalk::reserve_by_id(self, id)
+    }
+
+    fn delete(&mut self, id: Id) -> Result<DeleteResponse, Error> {
+        Beanstalk::delete(self, id)
+    }
+
+    fn release(&mut self, id: Id, pri: u32, delay: Duration) -> Result<ReleaseResponse, Error> {
+        Beanstalk::release(self, id, pri, delay)
+    }
+
+    fn bury(&mut self, id: Id, pri: u32) -> Result<BuryResponse, Error> {
+        Beanstalk::bury(self, id, pri)
+    }
+
+    fn touch(&mut self, id: Id) -> Result<TouchResponse, Error> {
+        Beanstalk::touch(self, id)
+    }
+
+    fn watch(&mut self, tube: &str) -> Result<usize, Error> {
+        Beanstalk::watch(self, tube)
+    }
+
+    fn ignore(&mut self, tube: &str) -> Result<IgnoreResponse, Error> {
+        Beanstalk::ignore(self, tube)
+    }
+
+    fn peek(&mut self, id: Id) -> Result<PeekResponse, Error> {
+        Beanstalk::peek(self, id)
+    }
+
+    fn peek_ready(&mut self) -> Result<PeekResponse, Error> {
+        Beanstalk::peek_ready(self)
+    }
+
+    fn peek_delayed(&mut self) -> Result<PeekResponse, Error> {
+        Beanstalk::peek_delayed(self)
+    }
+
+    fn peek_buried(&mut self) -> Result<PeekResponse, Error> {
+        Beanstalk::peek_buried(self)
+    }
+
+    fn kick(&mut self, bound: u32) -> Result<usize, Error> {
+        Beanstalk::kick(self, bound)
+    }
+
+    fn kick_job(&mut self, id: Id) -> Result<KickJobResponse, Error> {
+        Beanstalk::kick_job(self, id)
+    }
+
+    fn stats_job(&mut self, id: Id) -> Result<StatsJobResponse, Error> {
+        Beanstalk::stats_job(self, id)
+    }
+
+    fn stats_tube(&mut self, tube: &str) -> Result<StatsTubeResponse, Error> {
+        Beanstalk::stats_tube(self, tube)
+    }
+
+    fn stats(&mut self) -> Result<Stats, Error> {
+        Beanstalk::stats(self)
+    }
+
+    fn list_tubes(&mut self) -> Result<Vec<&str>, Error> {
+        Beanstalk::list_tubes(self)
+    }
+
+    fn list_tube_used(&mut self) -> Result<&str, Error> {
+        Beanstalk::list_tube_used(self)
+    }
+
+    fn list_tube_watched(&mut self) -> Result<Vec<&str>, Error> {
+        Beanstalk::list_tube_watched(self)
+    }
+
+    fn pause_tube(&mut self, tube: &str, delay: Duration) -> Result<PauseTubeResponse, Error> {
+        Beanstalk::pause_tube(self, tube, delay)
+    }
+}
@@ -0,0 +1,237 @@
+use std::time::Duration;
+
+/// Options for [`Beanstalk::put_with`](crate::Beanstalk::put_with).
+///
+/// The defaults follow beanstalkd conventions: `pri` 0, `delay` 0 and `ttr` 60 seconds.
+///
+/// ```no_run
+/// # use bsc::{Beanstalk, PutOptions};
+/// # let mut bsc = Beanstalk::connect("127.0.0.1:11300").unwrap();
+/// bsc.put_with(b"hello", PutOptions::default().pri(10).ttr_secs(120))
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct PutOptions {
+    pub(crate) pri: u32,
+    pub(crate) delay: Duration,
+    pub(crate) ttr: Duration,
+}
+
+impl Default for PutOptions {
+    fn default() -> Self {
+        Self {
+            pri: 0,
+            delay: Duration::ZERO,
+            ttr: Duration::from_secs(60),
+        }
+    }
+}
+
+impl PutOptions {
+    /// Jobs with smaller priority values will be scheduled before jobs with larger
+    /// priorities. The most urgent priority is 0; the least urgent priority is
+    /// 4,294,967,295.
+    pub fn pri(mut self, pri: u32) -> Self {
+        self.pri = pri;
+        self
+    }
+
+    /// Time to wait before putting the job in the ready queue. Only whole seconds
+    /// are sent to the server.
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Same as [`delay`](Self::delay), in seconds.
+    pub fn delay_secs(self, secs: u64) -> Self {
+        self.delay(Duration::from_secs(secs))
+    }
+
+    /// Time to allow a worker to run this job. Only whole seconds are sent to the
+    /// server, and the server silently increases a ttr of 0 to 1.
+    pub fn ttr(mut self, ttr: Duration) -> Self {
+        self.ttr = ttr;
+        self
+    }
+
+    /// Same as [`ttr`](Self::ttr), in seconds.
+    pub fn ttr_secs(self, secs: u64) -> Self {
+        self.ttr(Duration::from_secs(secs))
+    }
+}
+
+/// Tubes to set up right after connecting, see
+/// [`Beanstalk::connect_with`](crate::Beanstalk::connect_with).
+///
+/// ```no_run
+/// # use bsc::{Beanstalk, TubeConfig};
+/// let config = TubeConfig::default()
+///     .use_("emails")
+///     .watch("emails")
+///     .ignore("default");
+/// let mut bsc = Beanstalk::connect_with("127.0.0.1:11300", &config).unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TubeConfig {
+    pub(crate) namespace: String,
+    pub(crate) use_: Option<String>,
+    pub(crate) watch: Vec<String>,
+    pub(crate) ignore: Vec<String>,
+}
+
+impl TubeConfig {
+    /// The namespace prefix of every tube name, see
+    /// [`Beanstalk::namespace`](crate::Beanstalk::namespace). It applies to the `use`,
+    /// `watch` and `ignore` of this config too.
+    pub fn namespace(mut self, prefix: impl Into<String>) -> Self {
+        self.namespace = prefix.into();
+        self
+    }
+
+    /// The tube to `use` for subsequent put commands.
+    pub fn use_(mut self, tube: impl Into<String>) -> Self {
+        self.use_ = Some(tube.into());
+        self
+    }
+
+    /// The tube to `use`, if set.
+    pub fn used_tube(&self) -> Option<&str> {
+        self.use_.as_deref()
+    }
+
+    /// Adds a tube to `watch`.
+    pub fn watch(mut self, tube: impl Into<String>) -> Self {
+        self.watch.push(tube.into());
+        self
+    }
+
+    /// Adds a tube to `ignore`. Ignores are sent after the watches, so that the
+    /// "default" tube can be ignored as long as at least one other tube is watched.
+    pub fn ignore(mut self, tube: impl Into<String>) -> Self {
+        self.ignore.push(tube.into());
+        self
+    }
+}
+
+/// Options for [`Beanstalk::scan_jobs`](crate::Beanstalk::scan_jobs).
+///
+/// By default, only `peek` is used and ids are probed as fast as the server answers.
+///
+/// ```no_run
+/// # use bsc::{Beanstalk, ScanOptions};
+/// # let mut bsc = Beanstalk::connect("127.0.0.1:11300").unwrap();
+/// let opts = ScanOptions::default().stats(true).max_per_sec(500);
+/// bsc.scan_jobs(1..=10_000, opts, |id, _, stats| println!("{id}: {stats:?}"))
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanOptions {
+    pub(crate) stats: bool,
+    pub(crate) max_per_sec: Option<u32>,
+}
+
+impl ScanOptions {
+    /// Also sends `stats-job` for each job found.
+    pub fn stats(mut self, enabled: bool) -> Self {
+        self.stats = enabled;
+        self
+    }
+
+    /// Probes at most `rate` ids per second, to keep the load on a production server
+    /// low. A rate of 0 is ignored.
+    pub fn max_per_sec(mut self, rate: u32) -> Self {
+        self.max_per_sec = Some(rate).filter(|&rate| rate > 0);
+        self
+    }
+}
+
+/// A limit on the rate of puts, see
+/// [`Beanstalk::limit_puts`](crate::Beanstalk::limit_puts).
+///
+/// Puts are allowed at `per_sec` on average, with bursts of up to `burst` puts
+/// (1 by default). When the limit is reached, `put` waits by default, or fails with
+/// [`Error::RateLimited`](crate::Error::RateLimited) if `wait` is disabled.
+///
+/// ```no_run
+/// # use bsc::{Beanstalk, RateLimit};
+/// # let mut bsc = Beanstalk::connect("127.0.0.1:11300").unwrap();
+/// bsc.limit_puts(Some(RateLimit::new(100).burst(20)));
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub(crate) per_sec: u32,
+    pub(crate) burst: u32,
+    pub(crate) wait: bool,
+}
+
+impl RateLimit {
+    /// At most `per_sec` puts per second on average. A rate of 0 is raised to 1.
+    pub fn new(per_sec: u32) -> Self {
+        Self {
+            per_sec: per_sec.max(1),
+            burst: 1,
+            wait: true,
+        }
+    }
+
+    /// The number of puts allowed at once after a quiet period. A burst of 0 is
+    /// raised to 1.
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// Whether `put` waits for the rate to allow it (the default), or fails right
+    /// away.
+    pub fn wait(mut self, enabled: bool) -> Self {
+        self.wait = enabled;
+        self
+    }
+}
+
+/// Socket options of a TCP connection, see
+/// [`Beanstalk::tune_tcp`](crate::Beanstalk::tune_tcp). Options left unset keep the
+/// system defaults.
+///
+/// ```no_run
+/// # use bsc::{Beanstalk, TcpOptions};
+/// # let mut bsc = Beanstalk::connect("127.0.0.1:11300").unwrap();
+/// bsc.tune_tcp(&TcpOptions::default().nodelay(true)).unwrap();
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TcpOptions {
+    pub(crate) nodelay: Option<bool>,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) send_buffer_size: Option<usize>,
+    pub(crate) recv_buffer_size: Option<usize>,
+}
+
+impl TcpOptions {
+    /// Sets `TCP_NODELAY`, disabling Nagle's algorithm. Commands are then sent right
+    /// away instead of being delayed while waiting for more data, which lowers the
+    /// latency of small commands.
+    pub fn nodelay(mut self, enabled: bool) -> Self {
+        self.nodelay = Some(enabled);
+        self
+    }
+
+    /// Enables `SO_KEEPALIVE`, probing the server after `idle` without traffic so that
+    /// dead connections are detected, e.g. during a long blocking reserve.
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive = Some(idle);
+        self
+    }
+
+    /// Sets the size in bytes of the send buffer (`SO_SNDBUF`).
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets the size in bytes of the receive buffer (`SO_RCVBUF`).
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+}
@@ -0,0 +1,57 @@
+//! Constants of the beanstalkd protocol.
+
+use std::time::Duration;
+
+/// The port beanstalkd listens on by default.
+pub const DEFAULT_PORT: u16 = 11300;
+
+/// The tube used and watched by new connections.
+pub const DEFAULT_TUBE: &str = "default";
+
+/// The longest command line the server accepts, ending `\r\n` included.
+pub const MAX_LINE_LEN: usize = 224;
+
+/// The longest tube name the server accepts.
+pub const MAX_TUBE_NAME_LEN: usize = 200;
+
+/// The maximum size of a job body, unless the server is started with `-z`.
+pub const DEFAULT_MAX_JOB_SIZE: u32 = 65535;
+
+/// The last part of a reservation's TTR during which a reserve returns
+/// `DEADLINE_SOON` instead of waiting.
+pub const SAFETY_MARGIN: Duration = Duration::from_secs(1);
+
+/// Replies that any command can get.
+pub const GENERIC_ERRORS: &[&str] = &[
+    "OUT_OF_MEMORY",
+    "INTERNAL_ERROR",
+    "BAD_FORMAT",
+    "UNKNOWN_COMMAND",
+];
+
+/// Every reply the server may send, without arguments.
+pub const RESPONSES: &[&str] = &[
+    "OUT_OF_MEMORY",
+    "INTERNAL_ERROR",
+    "BAD_FORMAT",
+    "UNKNOWN_COMMAND",
+    "INSERTED",
+    "BURIED",
+    "EXPECTED_CRLF",
+    "JOB_TOO_BIG",
+    "DRAINING",
+    "USING",
+    "RESERVED",
+    "DEADLINE_SOON",
+    "TIMED_OUT",
+    "DELETED",
+    "NOT_FOUND",
+    "RELEASED",
+    "TOUCHED",
+    "WATCHING",
+    "NOT_IGNORED",
+    "FOUND",
+    "KICKED",
+    "OK",
+    "PAUSED",
+];
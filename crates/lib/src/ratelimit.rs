@@ -0,0 +1,44 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::options::RateLimit;
+use crate::{Error, Result};
+
+/// The token bucket enforcing a [`RateLimit`].
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst.into(),
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Takes a token, waiting for one to be available or failing, depending on the
+    /// limit.
+    pub(crate) fn acquire(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let rate = f64::from(self.limit.per_sec);
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(self.limit.burst.into());
+        self.refilled = now;
+
+        if self.tokens < 1.0 {
+            if !self.limit.wait {
+                return Err(Error::RateLimited);
+            }
+            thread::sleep(Duration::from_secs_f64((1.0 - self.tokens) / rate));
+            // the sleep produced exactly the missing part of the token
+            self.tokens = 1.0;
+            self.refilled = Instant::now();
+        }
+        self.tokens -= 1.0;
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use std::io;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long blocking loops wait on the server before checking the [`ShutdownSignal`]
+/// again. beanstalkd timeouts are whole seconds, so this is the shortest wait.
+pub(crate) const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A flag telling long running loops like [`Beanstalk::consume`](crate::Beanstalk::consume)
+/// to stop. Clones share the same flag, so one can be kept by e.g. a signal handler
+/// while another is given to the loop.
+///
+/// ```no_run
+/// # use bsc::{Beanstalk, ShutdownSignal};
+/// # let mut bsc = Beanstalk::connect("127.0.0.1:11300").unwrap();
+/// let shutdown = ShutdownSignal::new();
+/// let trigger = shutdown.clone();
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(60));
+///     trigger.trigger();
+/// });
+/// bsc.consume(&["emails"], &shutdown, |_, body| {
+///     println!("{body:?}");
+///     Ok::<_, String>(())
+/// })
+/// .unwrap();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownSignal(Arc<AtomicBool>);
+
+impl ShutdownSignal {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the loops using this signal to stop.
+    pub fn trigger(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Closes the connection of a client from another thread, see
+/// [`Beanstalk::shutdown_handle`](crate::Beanstalk::shutdown_handle).
+///
+/// The command the client is blocked on, if any, returns an I/O error, as do all the
+/// commands sent afterwards.
+#[derive(Debug)]
+pub struct ShutdownHandle(pub(crate) TcpStream);
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) -> io::Result<()> {
+        match self.0.shutdown(Shutdown::Both) {
+            // the connection is already closed
+            Err(err) if err.kind() == io::ErrorKind::NotConnected => Ok(()),
+            res => res,
+        }
+    }
+}
@@ -47,7 +47,7 @@ pub struct StatsJob {
     pub kicks: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum State {
     Ready,
@@ -56,7 +56,7 @@ pub enum State {
     Buried,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct StatsTube {
     /// "name" is the tube's name.
     pub name: String,
@@ -80,7 +80,7 @@ pub struct StatsTube {
     /// "total-jobs" is the cumulative count of jobs created in this tube in
     ///  the current beanstalkd process.
     #[serde(rename = "total-jobs")]
-    pub total_jobs: u32,
+    pub total_jobs: u64,
     /// "current-using" is the number of open connections that are currently
     ///  using this tube.
     #[serde(rename = "current-using")]
@@ -97,10 +97,10 @@ pub struct StatsTube {
     pub pause: u32,
     /// "cmd-delete" is the cumulative number of delete commands for this tube
     #[serde(rename = "cmd-delete")]
-    pub cmd_delete: u32,
+    pub cmd_delete: u64,
     /// "cmd-pause-tube" is the cumulative number of pause-tube commands for this tube.
     #[serde(rename = "cmd-pause-tube")]
-    pub cmd_pause_tube: u32,
+    pub cmd_pause_tube: u64,
     /// "pause-time-left" is the number of seconds until the tube is un-paused.
     #[serde(
         rename = "pause-time-left",
@@ -110,7 +110,7 @@ pub struct StatsTube {
     pub pause_time_left: Duration,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Stats {
     /// "current-jobs-urgent" is the number of ready jobs with priority < 1024.
     #[serde(rename = "current-jobs-urgent")]
@@ -129,70 +129,70 @@ pub struct Stats {
     pub current_jobs_buried: u32,
     /// "cmd-put" is the cumulative number of put commands.
     #[serde(rename = "cmd-put")]
-    pub cmd_put: u32,
+    pub cmd_put: u64,
     /// "cmd-peek" is the cumulative number of peek commands.
     #[serde(rename = "cmd-peek")]
-    pub cmd_peek: u32,
+    pub cmd_peek: u64,
     /// "cmd-peek-ready" is the cumulative number of peek-ready commands.
     #[serde(rename = "cmd-peek-ready")]
-    pub cmd_peek_ready: u32,
+    pub cmd_peek_ready: u64,
     /// "cmd-peek-delayed" is the cumulative number of peek-delayed commands.
     #[serde(rename = "cmd-peek-delayed")]
-    pub cmd_peek_delayed: u32,
+    pub cmd_peek_delayed: u64,
     /// "cmd-peek-buried" is the cumulative number of peek-buried commands.
     #[serde(rename = "cmd-peek-buried")]
-    pub cmd_peek_buried: u32,
+    pub cmd_peek_buried: u64,
     /// "cmd-reserve" is the cumulative number of reserve commands.
     #[serde(rename = "cmd-reserve")]
-    pub cmd_reserve: u32,
+    pub cmd_reserve: u64,
     /// "cmd-use" is the cumulative number of use commands.
     #[serde(rename = "cmd-use")]
-    pub cmd_use: u32,
+    pub cmd_use: u64,
     /// "cmd-watch" is the cumulative number of watch commands.
     #[serde(rename = "cmd-watch")]
-    pub cmd_watch: u32,
+    pub cmd_watch: u64,
     /// "cmd-ignore" is the cumulative number of ignore commands.
     #[serde(rename = "cmd-ignore")]
-    pub cmd_ignore: u32,
+    pub cmd_ignore: u64,
     /// "cmd-delete" is the cumulative number of delete commands.
     #[serde(rename = "cmd-delete")]
-    pub cmd_delete: u32,
+    pub cmd_delete: u64,
     /// "cmd-release" is the cumulative number of release commands.
     #[serde(rename = "cmd-release")]
-    pub cmd_release: u32,
+    pub cmd_release: u64,
     /// "cmd-bury" is the cumulative number of bury commands.
     #[serde(rename = "cmd-bury")]
-    pub cmd_bury: u32,
+    pub cmd_bury: u64,
     /// "cmd-kick" is the cumulative number of kick commands.
     #[serde(rename = "cmd-kick")]
-    pub cmd_kick: u32,
+    pub cmd_kick: u64,
     /// "cmd-stats" is the cumulative number of stats commands.
     #[serde(rename = "cmd-stats")]
-    pub cmd_stats: u32,
+    pub cmd_stats: u64,
     /// "cmd-stats-job" is the cumulative number of stats-job commands.
     #[serde(rename = "cmd-stats-job")]
-    pub cmd_stats_job: u32,
+    pub cmd_stats_job: u64,
     /// "cmd-stats-tube" is the cumulative number of stats-tube commands.
     #[serde(rename = "cmd-stats-tube")]
-    pub cmd_stats_tube: u32,
+    pub cmd_stats_tube: u64,
     /// "cmd-list-tubes" is the cumulative number of list-tubes commands.
     #[serde(rename = "cmd-list-tubes")]
-    pub cmd_list_tubes: u32,
+    pub cmd_list_tubes: u64,
     /// "cmd-list-tube-used" is the cumulative number of list-tube-used commands.
     #[serde(rename = "cmd-list-tube-used")]
-    pub cmd_list_tube_used: u32,
+    pub cmd_list_tube_used: u64,
     /// "cmd-list-tubes-watched" is the cumulative number of list-tubes-watched commands.
     #[serde(rename = "cmd-list-tubes-watched")]
-    pub cmd_list_tubes_watched: u32,
+    pub cmd_list_tubes_watched: u64,
     /// "cmd-pause-tube" is the cumulative number of pause-tube commands.
     #[serde(rename = "cmd-pause-tube")]
-    pub cmd_pause_tube: u32,
+    pub cmd_pause_tube: u64,
     /// "job-timeouts" is the cumulative count of times a job has timed out.
     #[serde(rename = "job-timeouts")]
-    pub job_timeouts: u32,
+    pub job_timeouts: u64,
     /// "total-jobs" is the cumulative count of jobs created.
     #[serde(rename = "total-jobs")]
-    pub total_jobs: u32,
+    pub total_jobs: u64,
     /// "max-job-size" is the maximum number of bytes in a job.
     #[serde(rename = "max-job-size")]
     pub max_job_size: u32,
@@ -213,7 +213,7 @@ pub struct Stats {
     pub current_waiting: u32,
     /// "total-connections" is the cumulative count of connections.
     #[serde(rename = "total-connections")]
-    pub total_connections: u32,
+    pub total_connections: u64,
     /// "pid" is the process id of the server.
     #[serde(rename = "pid")]
     pub pid: u32,
@@ -244,10 +244,10 @@ pub struct Stats {
     pub binlog_max_size: usize,
     /// "binlog-records-written" is the cumulative number of records written to the binlog.
     #[serde(rename = "binlog-records-written")]
-    pub binlog_records_written: u32,
+    pub binlog_records_written: u64,
     /// "binlog-records-migrated" is the cumulative number of records written as part of compaction.
     #[serde(rename = "binlog-records-migrated")]
-    pub binlog_records_migrated: u32,
+    pub binlog_records_migrated: u64,
     /// "draining" is set to "true" if the server is in drain mode, "false" otherwise.
     #[serde(default)]
     pub draining: bool,
@@ -261,6 +261,55 @@ pub struct Stats {
     pub platform: Option<String>,
 }
 
+/// What a server is and which optional commands it knows about, see
+/// [`Beanstalk::server_info`](crate::Beanstalk::server_info).
+#[derive(Debug, Clone, Serialize)]
+pub struct ServerInfo {
+    /// The version string of the server, e.g. "1.13".
+    pub version: String,
+    /// The maximum number of bytes in a job.
+    pub max_job_size: u32,
+    /// Whether the server is in drain mode, as of when the info was read.
+    pub draining: bool,
+}
+
+impl ServerInfo {
+    /// The major and minor numbers of the version, `None` if the version string is
+    /// not in that form (e.g. a development build or a fork).
+    pub fn version_number(&self) -> Option<(u32, u32)> {
+        let mut parts = self.version.split(['.', '+', '-']);
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        Some((major, minor))
+    }
+
+    /// Whether `reserve-job` is known, added in beanstalkd 1.12.
+    pub fn supports_reserve_job(&self) -> bool {
+        self.at_least(1, 12)
+    }
+
+    /// Whether `kick-job` is known, added in beanstalkd 1.8.
+    pub fn supports_kick_job(&self) -> bool {
+        self.at_least(1, 8)
+    }
+
+    /// Unknown versions are assumed to be recent enough, the server has the final say.
+    fn at_least(&self, major: u32, minor: u32) -> bool {
+        self.version_number()
+            .is_none_or(|version| version >= (major, minor))
+    }
+}
+
+impl From<&Stats> for ServerInfo {
+    fn from(stats: &Stats) -> Self {
+        Self {
+            version: stats.version.clone(),
+            max_job_size: stats.max_job_size,
+            draining: stats.draining,
+        }
+    }
+}
+
 pub fn as_seconds<S>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
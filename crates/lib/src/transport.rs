@@ -0,0 +1,232 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Direction marker of the bytes sent to the server in a recording.
+const OUTBOUND: u8 = b'>';
+/// Direction marker of the bytes received from the server in a recording.
+const INBOUND: u8 = b'<';
+
+/// Appends the bytes exchanged with the server to a recording file, see
+/// [`Beanstalk::connect_recording`](crate::Beanstalk::connect_recording).
+///
+/// A recording is a sequence of chunks, in the order they went through the
+/// connection. Each chunk is a direction (`>` sent, `<` received), a space, the
+/// decimal length of the chunk, a `\n`, the bytes themselves and a final `\n`:
+///
+/// ```text
+/// > 12
+/// use emails
+///
+/// < 14
+/// USING emails
+///
+/// ```
+#[derive(Clone)]
+pub(crate) struct Recorder(Arc<Mutex<File>>);
+
+impl Recorder {
+    pub(crate) fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self(Arc::new(Mutex::new(File::create(path)?))))
+    }
+
+    fn record(&self, direction: u8, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        let mut chunk = Vec::with_capacity(data.len() + 16);
+        chunk.push(direction);
+        writeln!(chunk, " {}", data.len())?;
+        chunk.extend_from_slice(data);
+        chunk.push(b'\n');
+        let mut file = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        file.write_all(&chunk)
+    }
+}
+
+/// Records everything read from `inner`.
+pub(crate) struct RecordingReader<R> {
+    pub(crate) inner: R,
+    pub(crate) recorder: Recorder,
+}
+
+impl<R: Read> Read for RecordingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.recorder.record(INBOUND, &buf[..n])?;
+        Ok(n)
+    }
+}
+
+/// Records everything written to `inner`.
+pub(crate) struct RecordingWriter<W> {
+    pub(crate) inner: W,
+    pub(crate) recorder: Recorder,
+}
+
+impl<W: Write> Write for RecordingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.recorder.record(OUTBOUND, &buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Parses a recording into the bytes sent and the bytes received.
+pub(crate) fn load(path: &Path) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+
+    let mut outbound = Vec::new();
+    let mut inbound = Vec::new();
+    let mut rest = &data[..];
+    while !rest.is_empty() {
+        let corrupted = || io::Error::new(io::ErrorKind::InvalidData, "corrupted recording");
+        let eol = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(corrupted)?;
+        let (direction, len) = match &rest[..eol] {
+            [direction, b' ', len @ ..] => (*direction, len),
+            _ => return Err(corrupted()),
+        };
+        let len: usize = std::str::from_utf8(len)
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(corrupted)?;
+        let chunk = rest.get(eol + 1..eol + 1 + len).ok_or_else(corrupted)?;
+        match direction {
+            OUTBOUND => outbound.extend_from_slice(chunk),
+            INBOUND => inbound.extend_from_slice(chunk),
+            _ => return Err(corrupted()),
+        }
+        rest = rest.get(eol + len + 2..).ok_or_else(corrupted)?;
+    }
+    Ok((outbound, inbound))
+}
+
+/// Feeds back the bytes received during a recording.
+pub(crate) struct ReplayReader {
+    pub(crate) inbound: io::Cursor<Vec<u8>>,
+}
+
+impl Read for ReplayReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inbound.read(buf)? {
+            0 if !buf.is_empty() => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "end of the recorded session",
+            )),
+            n => Ok(n),
+        }
+    }
+}
+
+/// Checks that the bytes written are the ones sent during a recording.
+pub(crate) struct ReplayWriter {
+    pub(crate) outbound: Vec<u8>,
+    pub(crate) pos: usize,
+}
+
+impl Write for ReplayWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let expected = &self.outbound[self.pos..];
+        let expected = &expected[..buf.len().min(expected.len())];
+        if expected != buf {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "command differs from the recorded session: sent {:?}, recorded {:?}",
+                    String::from_utf8_lossy(buf),
+                    String::from_utf8_lossy(expected),
+                ),
+            ));
+        }
+        self.pos += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A connection shared by the reading and the writing halves of a client. The client
+/// never reads and writes at the same time, so the lock is never contended.
+pub(crate) struct Shared<S>(Arc<Mutex<S>>);
+
+impl<S> Shared<S> {
+    pub(crate) fn new(stream: S) -> Self {
+        Self(Arc::new(Mutex::new(stream)))
+    }
+
+    pub(crate) fn lock(&self) -> std::sync::MutexGuard<'_, S> {
+        self.0.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl<S> Clone for Shared<S> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<S: Read> Read for Shared<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl<S: Write> Write for Shared<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.lock().flush()
+    }
+}
+
+/// Reads from `inner`, raising the `poisoned` flag when a read times out: the rest of
+/// the response may still come, and would then be taken for the next one.
+pub(crate) struct TimeoutReader<R> {
+    pub(crate) inner: R,
+    pub(crate) poisoned: Arc<AtomicBool>,
+}
+
+impl<R: Read> Read for TimeoutReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            // sockets report an elapsed read timeout as either kind, depending on the OS
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                self.poisoned.store(true, Ordering::SeqCst);
+                Err(io::Error::new(io::ErrorKind::TimedOut, ResponseTimedOut))
+            }
+            res => res,
+        }
+    }
+}
+
+/// The cause of the I/O errors returned by [`TimeoutReader`], turned into
+/// [`Error::ResponseTimeout`](crate::Error::ResponseTimeout).
+#[derive(Debug)]
+pub(crate) struct ResponseTimedOut;
+
+impl std::fmt::Display for ResponseTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("no response from the server in time")
+    }
+}
+
+impl std::error::Error for ResponseTimedOut {}
@@ -0,0 +1,329 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::beanstalk::Beanstalk;
+use crate::options::{PutOptions, TubeConfig};
+use crate::protocol::DEFAULT_PORT;
+use crate::{Error, Result};
+
+/// A connection string describing a server and how to use it, so that a whole queue
+/// configuration fits in one environment variable:
+///
+/// ```text
+/// beanstalkd://host:port/tube?watch=a,b&ttr=60
+/// beanstalkd+unix:///run/beanstalkd.sock?tube=emails
+/// ```
+///
+/// The path of a `beanstalkd://` URL is the tube to use, the port defaults to 11300.
+/// The query parameters are:
+///
+///  - `tube`: the tube to use, for `beanstalkd+unix://` URLs
+///  - `watch` and `ignore`: comma separated tubes to watch and ignore
+///  - `namespace`: see [`Beanstalk::namespace`]
+///  - `pri`, `delay` and `ttr`: the defaults for puts, see [`put_options`](Self::put_options)
+///
+/// Tube names and the namespace may be percent-encoded, e.g. `%2B` for `+`, a `+` being
+/// kept as is rather than read as a space.
+///
+/// ```no_run
+/// # use bsc::{Beanstalk, BeanstalkUrl};
+/// let url: BeanstalkUrl = "beanstalkd://127.0.0.1/emails?ttr=120".parse().unwrap();
+/// let mut bsc = url.connect().unwrap();
+/// bsc.put_with(b"hello", url.put_options()).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct BeanstalkUrl {
+    addr: Address,
+    tubes: TubeConfig,
+    /// The `pri`, `delay` and `ttr`, if any is given.
+    put: Option<PutOptions>,
+}
+
+#[derive(Debug, Clone)]
+enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl BeanstalkUrl {
+    /// The tubes to use, watch and ignore, and the namespace.
+    pub fn tube_config(&self) -> &TubeConfig {
+        &self.tubes
+    }
+
+    /// The `host:port` of a `beanstalkd://` URL, `None` for a unix socket.
+    pub fn tcp_address(&self) -> Option<&str> {
+        match &self.addr {
+            Address::Tcp(addr) => Some(addr),
+            Address::Unix(_) => None,
+        }
+    }
+
+    /// The `pri`, `delay` and `ttr` given in the URL, the [`PutOptions`] defaults
+    /// otherwise.
+    pub fn put_options(&self) -> PutOptions {
+        self.put.unwrap_or_default()
+    }
+
+    /// Connects and sets up the tubes, see [`Beanstalk::connect_with`].
+    pub fn connect(&self) -> Result<Beanstalk> {
+        self.connect_with(&self.tubes)
+    }
+
+    /// Connects to the server of this URL, but sets up the tubes of `config` instead.
+    pub fn connect_with(&self, config: &TubeConfig) -> Result<Beanstalk> {
+        self.connect_within(config, None)
+    }
+
+    /// Same as [`connect_with`](Self::connect_with), but gives up connecting after
+    /// `timeout`, see [`Beanstalk::connect_timeout`]. Connecting to a unix socket does
+    /// not wait, so the timeout only applies to `beanstalkd://` URLs.
+    pub fn connect_with_timeout(
+        &self,
+        config: &TubeConfig,
+        timeout: Duration,
+    ) -> Result<Beanstalk> {
+        self.connect_within(config, Some(timeout))
+    }
+
+    fn connect_within(&self, config: &TubeConfig, timeout: Option<Duration>) -> Result<Beanstalk> {
+        match &self.addr {
+            Address::Tcp(addr) => {
+                let mut bsc = match timeout {
+                    Some(timeout) => Beanstalk::connect_timeout(addr.as_str(), timeout)?,
+                    None => Beanstalk::connect(addr.as_str())?,
+                };
+                bsc.configure(config)?;
+                Ok(bsc)
+            }
+            #[cfg(unix)]
+            Address::Unix(path) => {
+                let conn = std::os::unix::net::UnixStream::connect(path)?;
+                let mut bsc = Beanstalk::from_parts(conn.try_clone()?, conn);
+                bsc.configure(config)?;
+                Ok(bsc)
+            }
+            #[cfg(not(unix))]
+            Address::Unix(_) => Err("unix sockets are not supported on this platform".into()),
+        }
+    }
+}
+
+impl FromStr for BeanstalkUrl {
+    type Err = Error;
+
+    fn from_str(url: &str) -> Result<Self> {
+        let invalid = |reason: &str| Error::from(format!("invalid url {url:?}: {reason}"));
+        let (scheme, rest) = url.split_once("://").ok_or_else(|| invalid("no scheme"))?;
+        let (target, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+        let mut tubes = TubeConfig::default();
+        let addr = match scheme {
+            "beanstalkd" | "beanstalk" => {
+                let (host, tube) = target.split_once('/').unwrap_or((target, ""));
+                if host.is_empty() {
+                    return Err(invalid("no host"));
+                }
+                if !tube.is_empty() {
+                    tubes = tubes.use_(decode(tube).ok_or_else(|| invalid("tube"))?);
+                }
+                // a port is present after the last colon, unless it belongs to an IPv6
+                match host.rsplit_once(':') {
+                    Some((_, "")) => return Err(invalid("no port")),
+                    Some((_, port)) if !port.contains(']') => {
+                        port.parse::<u16>().map_err(|_| invalid("port"))?;
+                        Address::Tcp(host.to_string())
+                    }
+                    _ => Address::Tcp(format!("{host}:{DEFAULT_PORT}")),
+                }
+            }
+            "beanstalkd+unix" | "beanstalk+unix" => {
+                if target.is_empty() {
+                    return Err(invalid("no socket path"));
+                }
+                Address::Unix(PathBuf::from(target))
+            }
+            _ => return Err(invalid("unknown scheme")),
+        };
+
+        let mut put: Option<PutOptions> = None;
+        for param in query.split('&').filter(|param| !param.is_empty()) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            let name = |value| decode(value).ok_or_else(|| invalid(key));
+            let list = || {
+                (value.split(',').filter(|tube| !tube.is_empty()))
+                    .map(name)
+                    .collect::<Result<Vec<_>>>()
+            };
+            let number = || value.parse::<u32>().map_err(|_| invalid(key));
+            match key {
+                "tube" => tubes = tubes.use_(name(value)?),
+                "watch" => tubes = list()?.into_iter().fold(tubes, TubeConfig::watch),
+                "ignore" => tubes = list()?.into_iter().fold(tubes, TubeConfig::ignore),
+                "namespace" => tubes = tubes.namespace(name(value)?),
+                "pri" => put = Some(put.unwrap_or_default().pri(number()?)),
+                "delay" => put = Some(put.unwrap_or_default().delay_secs(number()?.into())),
+                "ttr" => put = Some(put.unwrap_or_default().ttr_secs(number()?.into())),
+                _ => return Err(invalid(&format!("unknown parameter {key:?}"))),
+            }
+        }
+
+        Ok(Self { addr, tubes, put })
+    }
+}
+
+/// Decodes the percent-encoded bytes of `value`, `None` if an escape is invalid or
+/// the bytes are not UTF-8.
+fn decode(value: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte != b'%' {
+            bytes.push(byte);
+            rest = tail;
+            continue;
+        }
+        let hex = tail
+            .get(..2)
+            .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+        bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+        rest = &tail[2..];
+    }
+    String::from_utf8(bytes).ok()
+}
+
+impl Beanstalk {
+    /// Connects to the server described by `url`, and sets up its tubes (see
+    /// [`BeanstalkUrl`]).
+    ///
+    /// The client has no defaults for puts: a URL with `pri`, `delay` or `ttr` is an
+    /// error, rather than them being ignored. Parse a [`BeanstalkUrl`] instead, and put
+    /// with its [`put_options`](BeanstalkUrl::put_options).
+    pub fn from_url(url: &str) -> Result<Self> {
+        let parsed = url.parse::<BeanstalkUrl>()?;
+        if parsed.put.is_some() {
+            return Err(format!(
+                "{url:?}: pri, delay and ttr are not kept by Beanstalk::from_url, \
+                use BeanstalkUrl::put_options"
+            )
+            .into());
+        }
+        parsed.connect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(url: &str) -> BeanstalkUrl {
+        url.parse().unwrap_or_else(|err| panic!("{url}: {err}"))
+    }
+
+    fn unix_path(url: &BeanstalkUrl) -> Option<&std::path::Path> {
+        match &url.addr {
+            Address::Unix(path) => Some(path),
+            Address::Tcp(_) => None,
+        }
+    }
+
+    #[test]
+    fn tcp_addresses() {
+        let url = parse("beanstalkd://queue.local");
+        assert_eq!(url.tcp_address(), Some("queue.local:11300"));
+        assert_eq!(url.tube_config().used_tube(), None);
+
+        assert_eq!(
+            parse("beanstalk://10.0.0.1:11301").tcp_address(),
+            Some("10.0.0.1:11301")
+        );
+        assert_eq!(
+            parse("beanstalkd://[::1]").tcp_address(),
+            Some("[::1]:11300")
+        );
+        assert_eq!(
+            parse("beanstalkd://[::1]:4000").tcp_address(),
+            Some("[::1]:4000")
+        );
+    }
+
+    #[test]
+    fn unix_sockets() {
+        let url = parse("beanstalkd+unix:///run/beanstalkd.sock?tube=emails");
+        assert_eq!(url.tcp_address(), None);
+        assert_eq!(unix_path(&url), Some("/run/beanstalkd.sock".as_ref()));
+        assert_eq!(url.tube_config().used_tube(), Some("emails"));
+
+        let url = parse("beanstalk+unix://beanstalkd.sock");
+        assert_eq!(unix_path(&url), Some("beanstalkd.sock".as_ref()));
+    }
+
+    #[test]
+    fn tubes() {
+        let url = parse("beanstalkd://host/emails?watch=a,,b&ignore=default&namespace=staging.");
+        let tubes = url.tube_config();
+        assert_eq!(tubes.used_tube(), Some("emails"));
+        assert_eq!(tubes.watch, ["a", "b"]);
+        assert_eq!(tubes.ignore, ["default"]);
+        assert_eq!(tubes.namespace, "staging.");
+
+        // the tube parameter comes after the path
+        let url = parse("beanstalkd://host/emails?tube=sms");
+        assert_eq!(url.tube_config().used_tube(), Some("sms"));
+
+        let url = parse("beanstalkd://host/a%2Bb?watch=c%24,d+e&namespace=%28ns%29.");
+        let tubes = url.tube_config();
+        assert_eq!(tubes.used_tube(), Some("a+b"));
+        assert_eq!(tubes.watch, ["c$", "d+e"]);
+        assert_eq!(tubes.namespace, "(ns).");
+    }
+
+    #[test]
+    fn put_options() {
+        let put = parse("beanstalkd://host?pri=10&delay=5&ttr=120").put_options();
+        assert_eq!(put.pri, 10);
+        assert_eq!(put.delay, Duration::from_secs(5));
+        assert_eq!(put.ttr, Duration::from_secs(120));
+
+        let put = parse("beanstalkd://host/emails").put_options();
+        assert_eq!(
+            (put.pri, put.delay, put.ttr),
+            (0, Duration::ZERO, Duration::from_secs(60))
+        );
+
+        // rather than connecting and dropping them
+        match Beanstalk::from_url("beanstalkd://127.0.0.1:1?ttr=120") {
+            Err(Error::Bs(err)) => assert!(err.contains("BeanstalkUrl::put_options"), "{err}"),
+            Err(err) => panic!("unexpected error {err}"),
+            Ok(_) => panic!("the put options are dropped"),
+        }
+    }
+
+    #[test]
+    fn errors() {
+        for (url, reason) in [
+            ("localhost:11300", "no scheme"),
+            ("http://localhost", "unknown scheme"),
+            ("beanstalkd://", "no host"),
+            ("beanstalkd:///emails", "no host"),
+            ("beanstalkd+unix://", "no socket path"),
+            ("beanstalkd://host:", "no port"),
+            ("beanstalkd://host:http", "port"),
+            ("beanstalkd://[::1]:", "no port"),
+            ("beanstalkd://host/a%2", "tube"),
+            ("beanstalkd://host?watch=a,%zz", "watch"),
+            ("beanstalkd://host?tube=%ff", "tube"),
+            ("beanstalkd://host?ttr=soon", "ttr"),
+            ("beanstalkd://host?pri=-1", "pri"),
+            ("beanstalkd://host?delay", "delay"),
+            (
+                "beanstalkd://host?retries=3",
+                "unknown parameter \"retries\"",
+            ),
+        ] {
+            let err = url.parse::<BeanstalkUrl>().unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid url {url:?}: {reason}"));
+        }
+    }
+}